@@ -2,6 +2,7 @@ use crate::vec3::Point3;
 use crate::ray::Ray;
 
 // axis-aligned bounding boxes
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Clone)]
 pub struct AABB {
     pub min: Point3,
@@ -16,24 +17,32 @@ impl AABB {
         }
     }
 
-    pub fn hit(&self, r: &Ray, tmin: f32, tmax: f32) -> bool {
+    pub fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> bool {
+        let mut t_min = t_min;
+        let mut t_max = t_max;
+
         for a in 0..3 {
             // TODO check float NaN & Infinit later
             let inv_d = 1. / r.direction[a];
-            let mut t0 = (self.min[a] - r.origin[a]) / inv_d;
-            let mut t1 = (self.max[a] - r.origin[a]) / inv_d;
+            let mut t0 = (self.min[a] - r.origin[a]) * inv_d;
+            let mut t1 = (self.max[a] - r.origin[a]) * inv_d;
             if inv_d < 0. {
                 std::mem::swap(&mut t0, &mut t1);
             }
-            let tmin = if t0 > tmin { t0 } else { tmin };
-            let tmax = if t1 > tmax { t1 } else { tmax };
+            t_min = if t0 > t_min { t0 } else { t_min };
+            t_max = if t1 < t_max { t1 } else { t_max };
 
-            if tmax <= tmin {
+            if t_max <= t_min {
                 return false;
             }
         }
 
-        return true;
+        true
+    }
+
+    pub fn surface_area(&self) -> f32 {
+        let d = self.max - self.min;
+        2. * (d.x * d.y + d.y * d.z + d.z * d.x)
     }
 }
 
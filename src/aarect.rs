@@ -1,11 +1,23 @@
 use std::option::Option;
-use std::rc::Rc;
+use std::sync::Arc;
 
 use crate::hittable::{HitRecord, Hittable};
 use crate::ray::Ray;
 use crate::vec3::{Point3, Vec3};
 use crate::material::Material;
-use crate::aabb::{AABB, surrounding_box};
+use crate::aabb::AABB;
+use crate::sampler::Sampler;
+
+use rand::Rng;
+
+// the uniform density over a rectangle of `area` perpendicular to `axis`, over the directions seen
+// from the origin of a ray hitting it at t
+fn solid_angle_pdf(area: f32, axis: usize, r: &Ray, t: f32) -> f32 {
+    let length = r.direction.length();
+    let distance_squared = t * t * length * length;
+    let cosine = r.direction[axis].abs() / length;
+    distance_squared / (cosine * area)
+}
 
 pub struct XYRect {
     pub x0: f32,
@@ -13,7 +25,7 @@ pub struct XYRect {
     pub y0: f32,
     pub y1: f32,
     pub k: f32,
-    pub material: Rc<dyn Material>,
+    pub material: Arc<dyn Material>,
 }
 
 impl Hittable for XYRect {
@@ -23,6 +35,10 @@ impl Hittable for XYRect {
                        Point3::new(self.x1, self.y1, self.k + 0.0001)))
     }
 
+    fn emits(&self) -> bool {
+        self.material.emits()
+    }
+
     fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
         let t = (self.k - r.origin.z) / r.direction.z;
 
@@ -44,6 +60,17 @@ impl Hittable for XYRect {
             }
         }
     }
+
+    fn pdf_value(&self, r: &Ray, t_min: f32, t_max: f32) -> f32 {
+        let area = (self.x1 - self.x0) * (self.y1 - self.y0);
+        self.hit(r, t_min, t_max).map_or(0., |rec| solid_angle_pdf(area, 2, r, rec.t))
+    }
+
+    fn random(&self, origin: &Point3, rng: &mut Sampler) -> Vec3 {
+        let x = self.x0 + rng.gen::<f32>() * (self.x1 - self.x0);
+        let y = self.y0 + rng.gen::<f32>() * (self.y1 - self.y0);
+        Point3::new(x, y, self.k) - *origin
+    }
 }
 
 pub struct XZRect {
@@ -52,7 +79,7 @@ pub struct XZRect {
     pub z0: f32,
     pub z1: f32,
     pub k: f32,
-    pub material: Rc<dyn Material>,
+    pub material: Arc<dyn Material>,
 }
 
 impl Hittable for XZRect {
@@ -62,6 +89,10 @@ impl Hittable for XZRect {
                        Point3::new(self.x1, self.k + 0.0001, self.z1,)))
     }
 
+    fn emits(&self) -> bool {
+        self.material.emits()
+    }
+
     fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
         let t = (self.k - r.origin.y) / r.direction.y;
 
@@ -83,6 +114,17 @@ impl Hittable for XZRect {
             }
         }
     }
+
+    fn pdf_value(&self, r: &Ray, t_min: f32, t_max: f32) -> f32 {
+        let area = (self.x1 - self.x0) * (self.z1 - self.z0);
+        self.hit(r, t_min, t_max).map_or(0., |rec| solid_angle_pdf(area, 1, r, rec.t))
+    }
+
+    fn random(&self, origin: &Point3, rng: &mut Sampler) -> Vec3 {
+        let x = self.x0 + rng.gen::<f32>() * (self.x1 - self.x0);
+        let z = self.z0 + rng.gen::<f32>() * (self.z1 - self.z0);
+        Point3::new(x, self.k, z) - *origin
+    }
 }
 
 pub struct YZRect {
@@ -91,7 +133,7 @@ pub struct YZRect {
     pub z0: f32,
     pub z1: f32,
     pub k: f32,
-    pub material: Rc<dyn Material>,
+    pub material: Arc<dyn Material>,
 }
 
 impl Hittable for YZRect {
@@ -101,6 +143,10 @@ impl Hittable for YZRect {
                        Point3::new(self.k + 0.0001, self.y1, self.z1)))
     }
 
+    fn emits(&self) -> bool {
+        self.material.emits()
+    }
+
     fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
         let t = (self.k - r.origin.x) / r.direction.x;
 
@@ -123,4 +169,15 @@ impl Hittable for YZRect {
             }
         }
     }
+
+    fn pdf_value(&self, r: &Ray, t_min: f32, t_max: f32) -> f32 {
+        let area = (self.y1 - self.y0) * (self.z1 - self.z0);
+        self.hit(r, t_min, t_max).map_or(0., |rec| solid_angle_pdf(area, 0, r, rec.t))
+    }
+
+    fn random(&self, origin: &Point3, rng: &mut Sampler) -> Vec3 {
+        let y = self.y0 + rng.gen::<f32>() * (self.y1 - self.y0);
+        let z = self.z0 + rng.gen::<f32>() * (self.z1 - self.z0);
+        Point3::new(self.k, y, z) - *origin
+    }
 }
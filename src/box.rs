@@ -1,12 +1,13 @@
 use crate::Ray;
 use crate::aabb::AABB;
 use crate::hittable::HitRecord;
-use std::rc::Rc;
+use std::sync::Arc;
 use crate::material::Material;
 use crate::aarect::*;
 use crate::hittable_list::HittableList;
 use crate::hittable::Hittable;
 use crate::vec3::*;
+use crate::sampler::Sampler;
 
 pub struct Box {
     box_min: Point3,
@@ -15,22 +16,22 @@ pub struct Box {
 }
 
 impl Box {
-    pub fn new(p0: Point3, p1: Point3, material: Rc<dyn Material>) -> Self {
+    pub fn new(p0: Point3, p1: Point3, material: Arc<dyn Material>) -> Self {
         let mut sides = HittableList { objects: Vec::new() };
 
-        sides.add(std::boxed::Box::new(XYRect{ x0: p0.x, x1: p1.x, y0: p0.y, y1: p1.y, k: p1.z, material: material.clone()}));
-        sides.add(std::boxed::Box::new(XYRect{ x0: p0.x, x1: p1.x, y0: p0.y, y1: p1.y, k: p0.z, material: material.clone()}));
+        sides.add(Arc::new(XYRect{ x0: p0.x, x1: p1.x, y0: p0.y, y1: p1.y, k: p1.z, material: material.clone()}));
+        sides.add(Arc::new(XYRect{ x0: p0.x, x1: p1.x, y0: p0.y, y1: p1.y, k: p0.z, material: material.clone()}));
 
-        sides.add(std::boxed::Box::new(XZRect{ x0: p0.x, x1: p1.x, z0: p0.z, z1: p1.z, k: p1.y, material: material.clone()}));
-        sides.add(std::boxed::Box::new(XZRect{ x0: p0.x, x1: p1.x, z0: p0.z, z1: p1.z, k: p0.y, material: material.clone()}));
+        sides.add(Arc::new(XZRect{ x0: p0.x, x1: p1.x, z0: p0.z, z1: p1.z, k: p1.y, material: material.clone()}));
+        sides.add(Arc::new(XZRect{ x0: p0.x, x1: p1.x, z0: p0.z, z1: p1.z, k: p0.y, material: material.clone()}));
 
-        sides.add(std::boxed::Box::new(YZRect{ y0: p0.y, y1: p1.y, z0: p0.z, z1: p1.z, k: p1.x, material: material.clone()}));
-        sides.add(std::boxed::Box::new(YZRect{ y0: p0.y, y1: p1.y, z0: p0.z, z1: p1.z, k: p1.x, material: material.clone()}));
+        sides.add(Arc::new(YZRect{ y0: p0.y, y1: p1.y, z0: p0.z, z1: p1.z, k: p1.x, material: material.clone()}));
+        sides.add(Arc::new(YZRect{ y0: p0.y, y1: p1.y, z0: p0.z, z1: p1.z, k: p0.x, material: material.clone()}));
 
         Self {
             box_min: p0,
             box_max: p1,
-            sides: sides,
+            sides,
         }
     }
 }
@@ -41,8 +42,21 @@ impl Hittable for Box {
         Some(AABB::new(self.box_min, self.box_max))
     }
 
+    // the sides share one material
+    fn emits(&self) -> bool {
+        self.sides.objects[0].emits()
+    }
+
     fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
         self.sides.hit(r, t_min, t_max)
     }
 
+    fn pdf_value(&self, r: &Ray, t_min: f32, t_max: f32) -> f32 {
+        self.sides.pdf_value(r, t_min, t_max)
+    }
+
+    fn random(&self, origin: &Point3, rng: &mut Sampler) -> Vec3 {
+        self.sides.random(origin, rng)
+    }
+
 }
@@ -0,0 +1,368 @@
+use std::cmp::Ordering;
+use std::fmt::{self, Display, Formatter};
+use std::sync::Arc;
+
+use crate::aabb::{AABB, surrounding_box};
+use crate::flat_bvh::{FlatBvh, SplitMethod};
+use crate::hittable::{HitRecord, Hittable};
+use crate::hittable_list::HittableList;
+use crate::ray::Ray;
+
+// relative costs used to estimate the SAH cost of a tree
+pub const TRAVERSAL_COST: f32 = 0.125;
+pub const INTERSECTION_COST: f32 = 1.;
+
+#[derive(Clone, Copy, Debug, PartialEq, clap::ValueEnum)]
+pub enum BvhBuilder {
+    // BvhNode tree, split at the median along the longest axis
+    Recursive,
+    // flattened tree, split at the median along the longest axis
+    Median,
+    // flattened tree, split by the binned surface area heuristic
+    Sah,
+}
+
+impl BvhBuilder {
+    pub fn build(self, list: &HittableList, time0: f32, time1: f32) -> (Arc<dyn Hittable>, BvhStats) {
+        match self {
+            BvhBuilder::Recursive => BvhNode::from_list(list, time0, time1),
+            BvhBuilder::Median => {
+                let bvh = FlatBvh::new(list, SplitMethod::Median, time0, time1);
+                let stats = bvh.stats.clone();
+                (Arc::new(bvh), stats)
+            },
+            BvhBuilder::Sah => {
+                let bvh = FlatBvh::new(list, SplitMethod::Sah, time0, time1);
+                let stats = bvh.stats.clone();
+                (Arc::new(bvh), stats)
+            },
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct BvhStats {
+    pub node_count: usize,
+    pub leaf_count: usize,
+    pub max_depth: usize,
+    pub min_leaf_size: usize,
+    pub max_leaf_size: usize,
+    pub primitive_count: usize,
+    // expected cost of tracing a ray through the tree, relative to one intersection
+    pub sah_cost: f32,
+}
+
+impl BvhStats {
+    pub fn add_interior(&mut self, depth: usize, area: f32) {
+        self.node_count += 1;
+        self.max_depth = self.max_depth.max(depth);
+        self.sah_cost += TRAVERSAL_COST * area;
+    }
+
+    pub fn add_leaf(&mut self, depth: usize, size: usize, area: f32) {
+        self.min_leaf_size = if self.leaf_count == 0 { size } else { self.min_leaf_size.min(size) };
+        self.max_leaf_size = self.max_leaf_size.max(size);
+        self.node_count += 1;
+        self.leaf_count += 1;
+        self.primitive_count += size;
+        self.max_depth = self.max_depth.max(depth);
+        self.sah_cost += INTERSECTION_COST * size as f32 * area;
+    }
+
+    // costs are accumulated as absolute areas, normalize them once the root is known
+    pub fn finish(&mut self, root_area: f32) {
+        if root_area > 0. {
+            self.sah_cost /= root_area;
+        }
+    }
+
+    pub fn average_leaf_size(&self) -> f32 {
+        if self.leaf_count == 0 {
+            0.
+        } else {
+            self.primitive_count as f32 / self.leaf_count as f32
+        }
+    }
+}
+
+impl Display for BvhStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} nodes, {} leaves, depth {}, leaf size {}..{} (avg {:.2}), SAH cost {:.2}",
+            self.node_count,
+            self.leaf_count,
+            self.max_depth,
+            self.min_leaf_size,
+            self.max_leaf_size,
+            self.average_leaf_size(),
+            self.sah_cost
+        )
+    }
+}
+
+// bounding volume hierarchy, split at the median along the longest axis
+pub struct BvhNode {
+    left: Arc<dyn Hittable>,
+    right: Arc<dyn Hittable>,
+    bbox: AABB,
+}
+
+impl BvhNode {
+    // objects without a bounding box, such as infinite planes, cannot be placed in the tree and are
+    // listed next to it instead, as is everything when there is nothing to build a tree of
+    pub fn from_list(list: &HittableList, time0: f32, time1: f32) -> (Arc<dyn Hittable>, BvhStats) {
+        let mut objects = Vec::with_capacity(list.objects.len());
+        let mut unbounded = Vec::new();
+        for object in &list.objects {
+            match object.bounding_box(time0, time1) {
+                Some(bbox) => objects.push((object.clone(), bbox)),
+                None => unbounded.push(object.clone()),
+            }
+        }
+
+        let mut stats = BvhStats::default();
+        if objects.is_empty() {
+            return (Arc::new(HittableList { objects: unbounded }), stats);
+        }
+        let node = Self::build(objects, 0, &mut stats);
+        stats.finish(node.bbox.surface_area());
+
+        if unbounded.is_empty() {
+            return (Arc::new(node), stats);
+        }
+        unbounded.insert(0, Arc::new(node));
+        (Arc::new(HittableList { objects: unbounded }), stats)
+    }
+
+    fn build(mut objects: Vec<(Arc<dyn Hittable>, AABB)>, depth: usize, stats: &mut BvhStats) -> Self {
+        let axis = longest_axis(&objects);
+        objects.sort_by(|a, b| box_compare(&a.1, &b.1, axis));
+
+        let (left, right) = match objects.len() {
+            1 => {
+                stats.add_leaf(depth + 1, 1, objects[0].1.surface_area());
+                (objects[0].clone(), objects[0].clone())
+            },
+            2 => {
+                stats.add_leaf(depth + 1, 1, objects[0].1.surface_area());
+                stats.add_leaf(depth + 1, 1, objects[1].1.surface_area());
+                (objects[0].clone(), objects[1].clone())
+            },
+            n => {
+                let rest = objects.split_off(n / 2);
+                let left = Self::build(objects, depth + 1, stats);
+                let right = Self::build(rest, depth + 1, stats);
+                let left_box = left.bbox.clone();
+                let right_box = right.bbox.clone();
+                ((Arc::new(left) as Arc<dyn Hittable>, left_box),
+                 (Arc::new(right) as Arc<dyn Hittable>, right_box))
+            }
+        };
+
+        let bbox = surrounding_box(&left.1, &right.1);
+        stats.add_interior(depth, bbox.surface_area());
+
+        Self {
+            bbox,
+            left: left.0,
+            right: right.0,
+        }
+    }
+}
+
+fn centroid(bbox: &AABB, axis: usize) -> f32 {
+    0.5 * (bbox.min[axis] + bbox.max[axis])
+}
+
+fn box_compare(a: &AABB, b: &AABB, axis: usize) -> Ordering {
+    centroid(a, axis).partial_cmp(&centroid(b, axis)).unwrap_or(Ordering::Equal)
+}
+
+// the axis along which the centroids are spread out the most
+fn longest_axis(objects: &[(Arc<dyn Hittable>, AABB)]) -> usize {
+    let mut lo = [f32::INFINITY; 3];
+    let mut hi = [f32::NEG_INFINITY; 3];
+
+    for (_, bbox) in objects {
+        for axis in 0..3 {
+            lo[axis] = lo[axis].min(centroid(bbox, axis));
+            hi[axis] = hi[axis].max(centroid(bbox, axis));
+        }
+    }
+
+    let extent = |axis: usize| hi[axis] - lo[axis];
+    if extent(0) >= extent(1) && extent(0) >= extent(2) {
+        0
+    } else if extent(1) >= extent(2) {
+        1
+    } else {
+        2
+    }
+}
+
+impl Hittable for BvhNode {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        if !self.bbox.hit(r, t_min, t_max) {
+            return None;
+        }
+
+        let hit_left = self.left.hit(r, t_min, t_max);
+        let closest_so_far = hit_left.as_ref().map_or(t_max, |rec| rec.t);
+        let hit_right = self.right.hit(r, t_min, closest_so_far);
+
+        hit_right.or(hit_left)
+    }
+
+    fn bounding_box(&self, _t0: f32, _t1: f32) -> Option<AABB> {
+        Some(self.bbox.clone())
+    }
+
+    fn transmittance(&self, r: &Ray, t_min: f32, t_max: f32) -> f32 {
+        if !self.bbox.hit(r, t_min, t_max) {
+            return 1.;
+        }
+
+        // a node of a single object holds it on both sides
+        let left = self.left.transmittance(r, t_min, t_max);
+        if left == 0. || Arc::ptr_eq(&self.left, &self.right) {
+            left
+        } else {
+            left * self.right.transmittance(r, t_min, t_max)
+        }
+    }
+}
+
+// checks a hierarchy of either kind against the list it was built over, on camera rays over the whole
+// image plus rays in random directions from random origins; the uneven offsets keep camera rays off
+// the diagonals where two walls meet at the same t
+#[cfg(test)]
+pub fn assert_same_hits(list: &HittableList, bvh: &dyn Hittable, cam: &crate::camera::Camera,
+                        origin_min: crate::vec3::Point3, origin_max: crate::vec3::Point3) {
+    use crate::sampler::Sampler;
+    use crate::vec3::{Point3, Vec3};
+    use rand::{Rng, SeedableRng};
+
+    let mut rng = Sampler::seed_from_u64(42);
+    let mut rays = Vec::new();
+    for j in 0..100 {
+        for i in 0..100 {
+            rays.push(cam.get_ray((i as f32 + 0.3) / 100., (j as f32 + 0.6) / 100., &mut rng));
+        }
+    }
+    for _ in 0..10000 {
+        let origin = Point3::new(rng.gen_range(origin_min.x, origin_max.x),
+                                 rng.gen_range(origin_min.y, origin_max.y),
+                                 rng.gen_range(origin_min.z, origin_max.z));
+        let direction = Vec3::new(rng.gen_range(-1., 1.), rng.gen_range(-1., 1.), rng.gen_range(-1., 1.));
+        rays.push(Ray::new(origin, direction, rng.gen()));
+    }
+
+    for r in &rays {
+        match (list.hit(r, 0.001, f32::MAX), bvh.hit(r, 0.001, f32::MAX)) {
+            (None, None) => {},
+            (Some(expected), Some(actual)) => {
+                assert_eq!(expected.t, actual.t);
+                assert_eq!(expected.p, actual.p);
+                assert_eq!(expected.normal, actual.normal);
+                assert_eq!(expected.u, actual.u);
+                assert_eq!(expected.v, actual.v);
+                assert_eq!(expected.is_front_face, actual.is_front_face);
+            },
+            (expected, actual) => panic!("list hit: {}, bvh hit: {}", expected.is_some(), actual.is_some()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::camera::Camera;
+    use crate::vec3::{Point3, Vec3};
+    use crate::sampler::Sampler;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_random_scene_hits() {
+        let world = crate::random_scene(&mut Sampler::seed_from_u64(1));
+        let cam = Camera::new(Vec3::new(13., 2., 3.), Vec3::new(0., 0., 0.), Vec3::new(0., 1., 0.),
+                              20., 16. / 9., 0.1, 10., 0., 1.);
+
+        let (bvh, _) = BvhNode::from_list(&world, 0., 1.);
+        assert_same_hits(&world, bvh.as_ref(), &cam, Point3::new(-12., 0.1, -12.), Point3::new(12., 3., 12.));
+    }
+
+    #[test]
+    fn test_cornell_box_hits() {
+        let (world, _) = crate::cornell_box();
+        let cam = Camera::new(Vec3::new(278., 278., -800.), Vec3::new(278., 278., 0.), Vec3::new(0., 1., 0.),
+                              40., 1., 0., 10., 0., 1.);
+
+        // the boxes sit flush on the floor, so keep the origins above them to avoid coplanar ties
+        let (bvh, _) = BvhNode::from_list(&world, 0., 1.);
+        assert_same_hits(&world, bvh.as_ref(), &cam, Point3::new(1., 331., 1.), Point3::new(554., 554., 554.));
+    }
+
+    #[test]
+    fn test_bounding_box() {
+        let (world, _) = crate::cornell_box();
+        let (bvh, _) = BvhNode::from_list(&world, 0., 1.);
+
+        let expected = world.bounding_box(0., 1.).unwrap();
+        let actual = bvh.bounding_box(0., 1.).unwrap();
+        assert_eq!(expected.min, actual.min);
+        assert_eq!(expected.max, actual.max);
+    }
+
+    #[test]
+    fn test_empty_list() {
+        let list = HittableList { objects: Vec::new() };
+        let r = Ray::new(Point3::new(0., 0., 0.), Vec3::new(0., 0., 1.), 0.);
+
+        for builder in &[BvhBuilder::Recursive, BvhBuilder::Median, BvhBuilder::Sah] {
+            let (bvh, stats) = builder.build(&list, 0., 1.);
+            assert!(bvh.hit(&r, 0.001, f32::MAX).is_none());
+            assert!(bvh.bounding_box(0., 1.).is_none());
+            assert_eq!(0, stats.node_count);
+        }
+    }
+
+    // the plane y = 0.1, which no box can hold
+    struct Floor {
+        material: Arc<dyn crate::material::Material>,
+    }
+
+    impl Hittable for Floor {
+        fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+            let t = (0.1 - r.origin.y) / r.direction.y;
+            if !(t > t_min && t < t_max) {
+                return None;
+            }
+            let p = r.at(t);
+            let outward_normal = Vec3::new(0., 1., 0.);
+            Some(HitRecord::new(t, p, p.x, p.z, r.direction.dot(outward_normal) < 0., outward_normal, &self.material))
+        }
+
+        fn bounding_box(&self, _t0: f32, _t1: f32) -> Option<AABB> {
+            None
+        }
+    }
+
+    #[test]
+    fn test_unbounded_objects() {
+        use crate::color::Color;
+        use crate::material::Lambertian;
+
+        let mut world = crate::random_scene(&mut Sampler::seed_from_u64(1));
+        world.add(Arc::new(Floor { material: Arc::new(Lambertian::new_from_color(Color(Vec3::new(0.5, 0.5, 0.5)))) }));
+        let cam = Camera::new(Vec3::new(13., 2., 3.), Vec3::new(0., 0., 0.), Vec3::new(0., 1., 0.),
+                              20., 16. / 9., 0.1, 10., 0., 1.);
+
+        for builder in &[BvhBuilder::Recursive, BvhBuilder::Median, BvhBuilder::Sah] {
+            let (bvh, _) = builder.build(&world, 0., 1.);
+            assert!(bvh.bounding_box(0., 1.).is_none());
+            assert_same_hits(&world, bvh.as_ref(), &cam, Point3::new(-12., 0.2, -12.), Point3::new(12., 3., 12.));
+        }
+    }
+}
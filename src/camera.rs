@@ -1,7 +1,9 @@
 use crate::ray::Ray;
 use crate::vec3::{Point3, Vec3};
 
-use rand::{self, Rng};
+use rand::Rng;
+
+use crate::sampler::Sampler;
 
 pub struct Camera {
     pub origin: Point3,
@@ -10,15 +12,12 @@ pub struct Camera {
     pub vertical: Vec3,
     pub u: Vec3,
     pub v: Vec3,
-    pub w: Vec3,
     pub lens_radius: f32,
     pub time0: f32,
     pub time1: f32,
 }
 
-pub fn random_in_unit_disk() -> Point3 {
-    let mut rng = rand::thread_rng();
-
+pub fn random_in_unit_disk(rng: &mut Sampler) -> Point3 {
     loop {
         let p = Point3{
             x: rng.gen_range(-1., 1.),
@@ -34,6 +33,7 @@ pub fn random_in_unit_disk() -> Point3 {
 
 impl Camera {
     // vertical field of view in degrees
+    #[allow(clippy::too_many_arguments)]
     pub fn new(look_from: Vec3, look_at: Vec3, vup: Vec3, vfov: f32, aspect_ratio: f32, aperture: f32, focus_dist: f32, t0: f32, t1: f32) -> Self {
         let theta = vfov.to_radians();
         let h = (theta / 2.).tan();
@@ -53,25 +53,32 @@ impl Camera {
         let lens_radius = aperture / 2.;
 
         Camera {
-            origin: origin,
-            horizontal: horizontal,
-            vertical: vertical,
-            lower_left_corner: lower_left_corner,
-            w: w,
-            u: u,
-            v: v,
-            lens_radius: lens_radius,
+            origin,
+            horizontal,
+            vertical,
+            lower_left_corner,
+            u,
+            v,
+            lens_radius,
             time0: t0,
             time1: t1,
         }
     }
 
-    pub fn get_ray(&self, s: f32, t: f32) -> Ray {
-        let rd = random_in_unit_disk() * self.lens_radius;
+    // widens or narrows the viewport around its center, keeping the vertical field of view
+    pub fn with_aspect_ratio(mut self, aspect_ratio: f32) -> Self {
+        let current = self.horizontal.length() / self.vertical.length();
+        let horizontal = self.horizontal * (aspect_ratio / current);
+
+        self.lower_left_corner = self.lower_left_corner + self.horizontal / 2. - horizontal / 2.;
+        self.horizontal = horizontal;
+        self
+    }
+
+    pub fn get_ray(&self, s: f32, t: f32, rng: &mut Sampler) -> Ray {
+        let rd = random_in_unit_disk(rng) * self.lens_radius;
         let offset = self.u * rd.x  + self.v * rd.y;
 
-        // FIXME there should be one generator
-        let mut rng = rand::thread_rng();
         Ray {
             origin: self.origin + offset,
             direction: self.lower_left_corner + s * self.horizontal + t * self.vertical - self.origin - offset,
@@ -0,0 +1,74 @@
+use std::path::PathBuf;
+
+use clap::builder::RangedU64ValueParser;
+use clap::{CommandFactory, FromArgMatches, Parser};
+
+use crate::bvh::BvhBuilder;
+use crate::film::ExrPrecision;
+use crate::tonemap::ToneMap;
+
+#[derive(Parser)]
+#[command(about = "Renders a built-in scene, a TOML scene file or a glTF model")]
+pub struct Args {
+    /// Name of a built-in scene, or path to a TOML scene file or a glTF model
+    #[arg(short, long, default_value = "cornell_box")]
+    pub scene: String,
+
+    /// Image width in pixels, at least 2 [default: the scene's]
+    #[arg(long, value_parser = RangedU64ValueParser::<usize>::new().range(2..))]
+    pub width: Option<usize>,
+
+    /// Image height in pixels, at least 2 [default: the scene's]
+    #[arg(long, value_parser = RangedU64ValueParser::<usize>::new().range(2..))]
+    pub height: Option<usize>,
+
+    /// Samples per pixel, at least 1 [default: the scene's]
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub spp: Option<u32>,
+
+    /// Maximum number of bounces per path, at least 1
+    #[arg(long, default_value_t = 50, value_parser = clap::value_parser!(u32).range(1..))]
+    pub depth: u32,
+
+    /// Seed of every random decision, the same seed renders the same image
+    #[arg(long, default_value_t = 0)]
+    pub seed: u64,
+
+    /// Number of worker threads, 0 uses every core
+    #[arg(long, default_value_t = 0)]
+    pub threads: usize,
+
+    /// Output image, its extension selects the format, `-` writes PPM to stdout
+    #[arg(short, long, default_value = "-")]
+    pub output: PathBuf,
+
+    /// Tone mapping operator of 8 bit output
+    #[arg(long, value_enum, default_value_t = ToneMap::Clip)]
+    pub tonemap: ToneMap,
+
+    /// Exposure compensation in stops, applied before tone mapping
+    #[arg(long, default_value_t = 0., allow_negative_numbers = true)]
+    pub exposure: f32,
+
+    /// Radiance mapped to white by the extended Reinhard operator
+    #[arg(long, default_value_t = 4.)]
+    pub white_point: f32,
+
+    /// Sample type of OpenEXR output, whose depth channel is always float
+    #[arg(long, value_enum, default_value_t = ExrPrecision::Half)]
+    pub exr_precision: ExrPrecision,
+
+    /// Acceleration structure built over the scene
+    #[arg(long, value_enum, default_value_t = BvhBuilder::Sah)]
+    pub bvh: BvhBuilder,
+}
+
+impl Args {
+    pub fn parse_with_scenes(builtin_scenes: &[&str]) -> Self {
+        let command = Self::command()
+            .after_help(format!("Built-in scenes:\n  {}", builtin_scenes.join("\n  ")));
+        let matches = command.get_matches();
+
+        Self::from_arg_matches(&matches).unwrap_or_else(|e| e.exit())
+    }
+}
@@ -6,9 +6,9 @@ pub struct Color(pub vec3::Vec3);
 
 impl fmt::Display for Color {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(
+        writeln!(
             f,
-            "{} {} {}\n",
+            "{} {} {}",
             (self.0.x * 255.999) as i32,
             (self.0.y * 255.999) as i32,
             (self.0.z * 255.999) as i32
@@ -0,0 +1,122 @@
+use std::sync::Arc;
+
+use rand::Rng;
+
+use crate::aabb::AABB;
+use crate::hittable::{HitRecord, Hittable};
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::sampler::ray_sampler;
+use crate::vec3::Vec3;
+
+// a volume of uniform density filling a convex boundary, scattering with its phase function material
+pub struct ConstantMedium {
+    pub boundary: Box<dyn Hittable>,
+    pub phase_function: Arc<dyn Material>,
+    neg_inv_density: f32,
+}
+
+impl ConstantMedium {
+    pub fn new(boundary: Box<dyn Hittable>, density: f32, phase_function: Arc<dyn Material>) -> Self {
+        ConstantMedium {
+            boundary,
+            phase_function,
+            neg_inv_density: -1. / density,
+        }
+    }
+}
+
+// where the ray enters and leaves a convex boundary within [t_min, t_max], even when it starts inside
+pub fn boundary_interval(boundary: &dyn Hittable, r: &Ray, t_min: f32, t_max: f32) -> Option<(f32, f32)> {
+    let entry = boundary.hit(r, f32::NEG_INFINITY, f32::INFINITY)?;
+    let exit = boundary.hit(r, entry.t + 0.0001, f32::INFINITY)?;
+
+    let t_enter = entry.t.max(t_min).max(0.);
+    let t_exit = exit.t.min(t_max);
+    if t_enter < t_exit { Some((t_enter, t_exit)) } else { None }
+}
+
+impl Hittable for ConstantMedium {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        let (t_enter, t_exit) = boundary_interval(self.boundary.as_ref(), r, t_min, t_max)?;
+
+        // the free flight distance is exponentially distributed along the ray
+        let ray_length = r.direction.length();
+        let distance_inside_boundary = (t_exit - t_enter) * ray_length;
+        let hit_distance = self.neg_inv_density * (1. - ray_sampler(r).gen::<f32>()).ln();
+        if hit_distance > distance_inside_boundary {
+            return None;
+        }
+
+        let t = t_enter + hit_distance / ray_length;
+        // the normal and the face mean nothing inside a volume
+        Some(HitRecord::new(t, r.at(t), 0., 0., true, Vec3::new(1., 0., 0.), &self.phase_function))
+    }
+
+    fn bounding_box(&self, t0: f32, t1: f32) -> Option<AABB> {
+        self.boundary.bounding_box(t0, t1)
+    }
+
+    // Beer-Lambert over the length of the ray inside the boundary
+    fn transmittance(&self, r: &Ray, t_min: f32, t_max: f32) -> f32 {
+        match boundary_interval(self.boundary.as_ref(), r, t_min, t_max) {
+            Some((t_enter, t_exit)) => ((t_exit - t_enter) * r.direction.length() / self.neg_inv_density).exp(),
+            None => 1.,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::color::Color;
+    use crate::material::Isotropic;
+    use crate::sphere::Sphere;
+    use crate::vec3::Point3;
+
+    fn fog(density: f32) -> ConstantMedium {
+        let phase_function: Arc<dyn Material> = Arc::new(Isotropic::new(Color(Vec3::new(1., 1., 1.))));
+        let boundary = Sphere { center: Point3::new(0., 0., 0.), radius: 1., material: phase_function.clone() };
+        ConstantMedium::new(Box::new(boundary), density, phase_function)
+    }
+
+    // the fraction of rays crossing the unit sphere through its center that come out unscattered
+    fn escaping_fraction(medium: &ConstantMedium, origin: Point3) -> f32 {
+        let n = 20000;
+        let passed = (0..n)
+            .filter(|&i| {
+                // every ray must differ to get its own random numbers
+                let offset = (i as f32 / n as f32) * 1e-3;
+                let r = Ray::new_without_move(origin + Vec3::new(offset, 0., 0.), Vec3::new(0., 0., -2.));
+                medium.hit(&r, 0.001, f32::INFINITY).is_none()
+            })
+            .count();
+        passed as f32 / n as f32
+    }
+
+    #[test]
+    fn test_beer_lambert() {
+        // across a diameter of 2
+        let expected = (-2f32 * 0.5).exp();
+        assert!((escaping_fraction(&fog(0.5), Point3::new(0., 0., 5.)) - expected).abs() < 0.01);
+
+        // from the center only a radius is left
+        let expected = (-0.5f32).exp();
+        assert!((escaping_fraction(&fog(0.5), Point3::new(0., 0., 0.)) - expected).abs() < 0.01);
+
+        let r = Ray::new_without_move(Point3::new(0., 0., 5.), Vec3::new(0., 0., -2.));
+        assert!((fog(0.5).transmittance(&r, 0.001, f32::INFINITY) - (-1f32).exp()).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_scatters_inside() {
+        let medium = fog(100.);
+        let r = Ray::new_without_move(Point3::new(0., 0., 5.), Vec3::new(0., 0., -1.));
+        let rec = medium.hit(&r, 0.001, f32::INFINITY).unwrap();
+        assert!(rec.t > 4. && rec.t < 4.2);
+
+        // nothing is hit beyond t_max
+        assert!(medium.hit(&r, 0.001, 3.).is_none());
+    }
+}
@@ -0,0 +1,377 @@
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use exr::prelude::{f16, AnyChannel, AnyChannels, Encoding, FlatSamples, Image, LayerAttributes, Layer, SmallVec, WritableImage};
+use image::codecs::hdr::HdrEncoder;
+use image::codecs::pnm::{PnmEncoder, PnmSubtype, SampleEncoding};
+use image::{ExtendedColorType, ImageEncoder, ImageError, ImageFormat, RgbImage};
+
+use crate::color::Color;
+use crate::tonemap::ToneMapper;
+use crate::vec3::Vec3;
+
+#[derive(Debug)]
+pub enum FilmError {
+    Io(io::Error),
+    Image(ImageError),
+    Exr(exr::error::Error),
+}
+
+impl Display for FilmError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            FilmError::Io(e) => e.fmt(f),
+            FilmError::Image(e) => e.fmt(f),
+            FilmError::Exr(e) => e.fmt(f),
+        }
+    }
+}
+
+impl Error for FilmError {}
+
+impl From<io::Error> for FilmError {
+    fn from(e: io::Error) -> Self {
+        FilmError::Io(e)
+    }
+}
+
+impl From<ImageError> for FilmError {
+    fn from(e: ImageError) -> Self {
+        FilmError::Image(e)
+    }
+}
+
+impl From<exr::error::Error> for FilmError {
+    fn from(e: exr::error::Error) -> Self {
+        FilmError::Exr(e)
+    }
+}
+
+// sample type of the channels of an OpenEXR file but its depth
+#[derive(Clone, Copy, Debug, PartialEq, clap::ValueEnum)]
+pub enum ExrPrecision {
+    Half,
+    Float,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputFormat {
+    Png,
+    Jpeg,
+    Bmp,
+    Tga,
+    // binary P6
+    Ppm,
+    // the formats below keep the radiance above 1 unclamped
+    Exr(ExrPrecision),
+    // Radiance RGBE
+    Hdr,
+    // portable float map
+    Pfm,
+}
+
+impl OutputFormat {
+    pub const EXTENSIONS: [&'static str; 9] = ["png", "jpg", "jpeg", "bmp", "tga", "ppm", "exr", "hdr", "pfm"];
+
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+        match ext.as_str() {
+            "png" => Some(OutputFormat::Png),
+            "jpg" | "jpeg" => Some(OutputFormat::Jpeg),
+            "bmp" => Some(OutputFormat::Bmp),
+            "tga" => Some(OutputFormat::Tga),
+            "ppm" => Some(OutputFormat::Ppm),
+            "exr" => Some(OutputFormat::Exr(ExrPrecision::Half)),
+            "hdr" => Some(OutputFormat::Hdr),
+            "pfm" => Some(OutputFormat::Pfm),
+            _ => None,
+        }
+    }
+}
+
+// what the camera rays of a pixel first hit, averaged over its samples
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Surface {
+    // fraction of the samples hitting the scene
+    pub alpha: f32,
+    // distance from the camera, infinite where nothing is hit
+    pub depth: f32,
+    // world space unit normal facing the camera, zero where nothing is hit
+    pub normal: Vec3,
+}
+
+// framebuffer of the average radiance of every pixel, from the top row down, and optionally of the
+// surfaces behind them
+pub struct Film {
+    pub width: usize,
+    pub height: usize,
+    pixels: Vec<Color>,
+    surfaces: Option<Vec<Surface>>,
+}
+
+impl Film {
+    pub fn from_pixels(width: usize, height: usize, pixels: Vec<Color>) -> Self {
+        assert_eq!(pixels.len(), width * height, "Pixel count does not match the film size.");
+        Self { width, height, pixels, surfaces: None }
+    }
+
+    pub fn with_surfaces(self, surfaces: Vec<Surface>) -> Self {
+        assert_eq!(surfaces.len(), self.width * self.height, "Surface count does not match the film size.");
+        Self { surfaces: Some(surfaces), ..self }
+    }
+
+    // x from the left, y from the top
+    pub fn pixel(&self, x: usize, y: usize) -> Color {
+        self.pixels[y * self.width + x]
+    }
+
+    #[cfg(test)]
+    pub fn surface(&self, x: usize, y: usize) -> Option<Surface> {
+        self.surfaces.as_ref().map(|surfaces| surfaces[y * self.width + x])
+    }
+
+    pub fn to_rgb8(&self, tone_mapper: &ToneMapper) -> RgbImage {
+        RgbImage::from_fn(self.width as u32, self.height as u32, |x, y| {
+            image::Rgb(tone_mapper.quantize(self.pixel(x as usize, y as usize)))
+        })
+    }
+
+    // the tone mapper only applies to the 8 bit formats
+    pub fn write(&self, path: &Path, format: OutputFormat, tone_mapper: &ToneMapper) -> Result<(), FilmError> {
+        match format {
+            OutputFormat::Png => self.to_rgb8(tone_mapper).save_with_format(path, ImageFormat::Png)?,
+            OutputFormat::Jpeg => self.to_rgb8(tone_mapper).save_with_format(path, ImageFormat::Jpeg)?,
+            OutputFormat::Bmp => self.to_rgb8(tone_mapper).save_with_format(path, ImageFormat::Bmp)?,
+            OutputFormat::Tga => self.to_rgb8(tone_mapper).save_with_format(path, ImageFormat::Tga)?,
+            OutputFormat::Exr(precision) => self.write_exr(path, precision)?,
+            OutputFormat::Ppm | OutputFormat::Hdr | OutputFormat::Pfm => {
+                let mut out = BufWriter::new(File::create(path)?);
+                match format {
+                    OutputFormat::Ppm => self.write_ppm(&mut out, tone_mapper)?,
+                    OutputFormat::Hdr => self.write_hdr(&mut out)?,
+                    _ => self.write_pfm(&mut out)?,
+                }
+                out.flush()?;
+            },
+        }
+        Ok(())
+    }
+
+    // PPM needs no seeking, so it can also go to a pipe
+    pub fn write_ppm<W: Write>(&self, out: W, tone_mapper: &ToneMapper) -> Result<(), FilmError> {
+        let image = self.to_rgb8(tone_mapper);
+        PnmEncoder::new(out)
+            .with_subtype(PnmSubtype::Pixmap(SampleEncoding::Binary))
+            .write_image(image.as_raw(), image.width(), image.height(), ExtendedColorType::Rgb8)?;
+        Ok(())
+    }
+
+    // the surfaces, when recorded, add alpha (A), depth (Z) and normal (N.X, N.Y, N.Z) channels; the
+    // color keeps the background where alpha is below 1, so it is not premultiplied
+    pub fn write_exr(&self, path: &Path, precision: ExrPrecision) -> Result<(), FilmError> {
+        let surfaces = match &self.surfaces {
+            Some(surfaces) => surfaces,
+            None => return self.write_exr_rgb(path, precision),
+        };
+
+        let samples = |values: Vec<f32>| match precision {
+            ExrPrecision::Half => FlatSamples::F16(values.into_iter().map(f16::from_f32).collect()),
+            ExrPrecision::Float => FlatSamples::F32(values),
+        };
+        let color = |channel: usize| samples(self.pixels.iter().map(|c| c.0[channel]).collect());
+        let normal = |channel: usize| samples(surfaces.iter().map(|s| s.normal[channel]).collect());
+        let channels = vec![
+            AnyChannel::new("R", color(0)),
+            AnyChannel::new("G", color(1)),
+            AnyChannel::new("B", color(2)),
+            AnyChannel::new("A", samples(surfaces.iter().map(|s| s.alpha).collect())),
+            // depth always keeps full precision, half floats would band it beyond a few units
+            AnyChannel::new("Z", FlatSamples::F32(surfaces.iter().map(|s| s.depth).collect())),
+            AnyChannel::new("N.X", normal(0)),
+            AnyChannel::new("N.Y", normal(1)),
+            AnyChannel::new("N.Z", normal(2)),
+        ];
+        let layer = Layer::new((self.width, self.height), LayerAttributes::default(), Encoding::default(),
+                               AnyChannels::sort(SmallVec::from_vec(channels)));
+        Image::from_layer(layer).write().to_file(path)?;
+        Ok(())
+    }
+
+    fn write_exr_rgb(&self, path: &Path, precision: ExrPrecision) -> Result<(), FilmError> {
+        let rgb = |x, y| self.pixel(x, y).0;
+        match precision {
+            ExrPrecision::Half => exr::prelude::write_rgb_file(path, self.width, self.height, |x, y| {
+                let c = rgb(x, y);
+                (f16::from_f32(c.x), f16::from_f32(c.y), f16::from_f32(c.z))
+            })?,
+            ExrPrecision::Float => exr::prelude::write_rgb_file(path, self.width, self.height, |x, y| {
+                let c = rgb(x, y);
+                (c.x, c.y, c.z)
+            })?,
+        }
+        Ok(())
+    }
+
+    pub fn write_hdr<W: Write>(&self, out: W) -> Result<(), FilmError> {
+        let pixels: Vec<_> = self.pixels.iter().map(|c| image::Rgb([c.0.x, c.0.y, c.0.z])).collect();
+        HdrEncoder::new(out).encode(&pixels, self.width, self.height)?;
+        Ok(())
+    }
+
+    // a negative scale marks little endian samples, rows go from the bottom up
+    pub fn write_pfm<W: Write>(&self, mut out: W) -> Result<(), FilmError> {
+        write!(out, "PF\n{} {}\n-1.0\n", self.width, self.height)?;
+        for y in (0..self.height).rev() {
+            for x in 0..self.width {
+                let c = self.pixel(x, y).0;
+                for sample in [c.x, c.y, c.z] {
+                    out.write_all(&sample.to_le_bytes())?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::path::PathBuf;
+
+    fn gradient() -> Film {
+        let pixels = (0..3)
+            .flat_map(|y| (0..4).map(move |x| Color(Vec3::new(x as f32 / 4., y as f32 / 2., 2.))))
+            .collect();
+        Film::from_pixels(4, 3, pixels)
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("film-{}-{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn test_format_from_path() {
+        assert_eq!(OutputFormat::from_path(Path::new("out.PNG")), Some(OutputFormat::Png));
+        assert_eq!(OutputFormat::from_path(Path::new("out.jpeg")), Some(OutputFormat::Jpeg));
+        assert_eq!(OutputFormat::from_path(Path::new("out.ppm")), Some(OutputFormat::Ppm));
+        assert_eq!(OutputFormat::from_path(Path::new("out.txt")), None);
+        assert_eq!(OutputFormat::from_path(Path::new("out")), None);
+    }
+
+    #[test]
+    fn test_lossless_round_trip() {
+        let film = gradient();
+        let expected = film.to_rgb8(&ToneMapper::default());
+
+        for (name, format) in [("a.png", OutputFormat::Png), ("a.bmp", OutputFormat::Bmp),
+                               ("a.tga", OutputFormat::Tga), ("a.ppm", OutputFormat::Ppm)] {
+            let path = temp_path(name);
+            film.write(&path, format, &ToneMapper::default()).unwrap();
+            let actual = image::open(&path).unwrap().to_rgb8();
+            std::fs::remove_file(&path).unwrap();
+
+            assert_eq!(expected, actual, "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_ppm_is_binary() {
+        let mut out = Vec::new();
+        gradient().write_ppm(&mut out, &ToneMapper::default()).unwrap();
+
+        assert!(out.starts_with(b"P6\n4 3 255\n"));
+        assert_eq!(out.len(), b"P6\n4 3 255\n".len() + 4 * 3 * 3);
+    }
+
+    #[test]
+    fn test_exr_keeps_radiance_above_one() {
+        let film = gradient();
+
+        for (name, precision) in [("a-half.exr", ExrPrecision::Half), ("a-float.exr", ExrPrecision::Float)] {
+            let path = temp_path(name);
+            film.write(&path, OutputFormat::Exr(precision), &ToneMapper::default()).unwrap();
+            let actual = image::open(&path).unwrap().to_rgb32f();
+            std::fs::remove_file(&path).unwrap();
+
+            for y in 0..film.height {
+                for x in 0..film.width {
+                    let expected = film.pixel(x, y).0;
+                    let actual = actual.get_pixel(x as u32, y as u32).0;
+                    assert_eq!([expected.x, expected.y, expected.z], actual, "{}", name);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_exr_has_surface_channels() {
+        let surfaces = (0..12)
+            .map(|i| match i % 2 {
+                0 => Surface { alpha: 1., depth: 1000. + i as f32 / 8., normal: Vec3::new(0., 0., 1.) },
+                _ => Surface { alpha: 0., depth: f32::INFINITY, normal: Vec3::new(0., 0., 0.) },
+            })
+            .collect::<Vec<_>>();
+        let film = gradient().with_surfaces(surfaces.clone());
+
+        for (name, precision) in [("b-half.exr", ExrPrecision::Half), ("b-float.exr", ExrPrecision::Float)] {
+            let path = temp_path(name);
+            film.write(&path, OutputFormat::Exr(precision), &ToneMapper::default()).unwrap();
+            let image = exr::prelude::read_first_flat_layer_from_file(&path).unwrap();
+            let actual = image::open(&path).unwrap().to_rgb32f();
+            std::fs::remove_file(&path).unwrap();
+
+            let channels = &image.layer_data.channel_data.list;
+            let names: Vec<String> = channels.iter().map(|c| c.name.to_string()).collect();
+            assert_eq!(vec!["A", "B", "G", "N.X", "N.Y", "N.Z", "R", "Z"], names, "{}", name);
+            let channel = |name: &str| channels.iter().find(|c| c.name == *name).unwrap();
+            for (i, surface) in surfaces.iter().enumerate() {
+                assert_eq!(surface.alpha, channel("A").sample_data.value_by_flat_index(i).to_f32());
+                assert_eq!(surface.depth, channel("Z").sample_data.value_by_flat_index(i).to_f32());
+                assert_eq!(surface.normal.z, channel("N.Z").sample_data.value_by_flat_index(i).to_f32());
+            }
+            for y in 0..film.height {
+                for x in 0..film.width {
+                    let expected = film.pixel(x, y).0;
+                    assert_eq!([expected.x, expected.y, expected.z], actual.get_pixel(x as u32, y as u32).0, "{}", name);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_hdr_keeps_radiance_above_one() {
+        let film = gradient();
+        let path = temp_path("a.hdr");
+        film.write(&path, OutputFormat::Hdr, &ToneMapper::default()).unwrap();
+        let actual = image::open(&path).unwrap().to_rgb32f();
+        std::fs::remove_file(&path).unwrap();
+
+        // RGBE shares one exponent between the channels, so only expect the brightest one to be exact
+        for y in 0..film.height {
+            for x in 0..film.width {
+                assert_eq!(actual.get_pixel(x as u32, y as u32).0[2], 2.);
+            }
+        }
+    }
+
+    #[test]
+    fn test_pfm_layout() {
+        let film = gradient();
+        let mut out = Vec::new();
+        film.write_pfm(&mut out).unwrap();
+
+        let header = b"PF\n4 3\n-1.0\n";
+        assert!(out.starts_with(header));
+        assert_eq!(out.len(), header.len() + 4 * 3 * 3 * 4);
+
+        // the first row written is the bottom one
+        let sample = |i: usize| f32::from_le_bytes([out[header.len() + 4 * i], out[header.len() + 4 * i + 1],
+                                                     out[header.len() + 4 * i + 2], out[header.len() + 4 * i + 3]]);
+        let bottom_right = film.pixel(3, 2).0;
+        assert_eq!([sample(9), sample(10), sample(11)], [bottom_right.x, bottom_right.y, bottom_right.z]);
+    }
+}
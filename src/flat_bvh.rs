@@ -0,0 +1,424 @@
+use std::cmp::Ordering;
+use std::sync::Arc;
+
+use crate::aabb::{AABB, surrounding_box};
+use crate::bvh::{BvhStats, TRAVERSAL_COST, INTERSECTION_COST};
+use crate::hittable::{HitRecord, Hittable};
+use crate::hittable_list::HittableList;
+use crate::ray::Ray;
+use crate::sampler::Sampler;
+use crate::vec3::{Point3, Vec3};
+
+use rand::Rng;
+
+const BIN_COUNT: usize = 12;
+const MAX_LEAF_SIZE: usize = 4;
+// past this depth SAH falls back to median splits, which keeps the traversal stack bounded
+const MAX_SAH_DEPTH: usize = 32;
+const STACK_SIZE: usize = 64;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SplitMethod {
+    Median,
+    Sah,
+}
+
+// interior nodes keep the first child right after themselves and store the index
+// of the second child in `offset`, leaves store the range of their primitives
+struct LinearNode {
+    bbox: AABB,
+    offset: usize,
+    count: usize,
+    axis: usize,
+}
+
+struct PrimitiveInfo {
+    index: usize,
+    bbox: AABB,
+    centroid: Point3,
+}
+
+#[derive(Clone)]
+struct Bin {
+    count: usize,
+    bbox: Option<AABB>,
+}
+
+// bounding volume hierarchy stored depth first in one contiguous array
+pub struct FlatBvh {
+    nodes: Vec<LinearNode>,
+    primitives: Vec<Arc<dyn Hittable>>,
+    // objects without a bounding box, such as infinite planes, tested one by one next to the tree
+    unbounded: Vec<Arc<dyn Hittable>>,
+    pub stats: BvhStats,
+}
+
+impl FlatBvh {
+    pub fn new(list: &HittableList, method: SplitMethod, time0: f32, time1: f32) -> Self {
+        let mut info: Vec<PrimitiveInfo> = Vec::with_capacity(list.objects.len());
+        let mut unbounded = Vec::new();
+        for (index, object) in list.objects.iter().enumerate() {
+            match object.bounding_box(time0, time1) {
+                Some(bbox) => {
+                    let centroid = 0.5 * (bbox.min + bbox.max);
+                    info.push(PrimitiveInfo { index, bbox, centroid });
+                },
+                None => unbounded.push(object.clone()),
+            }
+        }
+
+        let mut bvh = FlatBvh {
+            nodes: Vec::with_capacity(2 * info.len()),
+            primitives: Vec::with_capacity(info.len()),
+            unbounded,
+            stats: BvhStats::default(),
+        };
+
+        if !info.is_empty() {
+            bvh.build(&list.objects, &mut info, method, 0);
+            let root_area = bvh.nodes[0].bbox.surface_area();
+            bvh.stats.finish(root_area);
+        }
+
+        bvh
+    }
+
+    fn build(&mut self, objects: &[Arc<dyn Hittable>], info: &mut [PrimitiveInfo], method: SplitMethod, depth: usize) -> usize {
+        let bbox = info.iter().skip(1)
+            .fold(info[0].bbox.clone(), |b, p| surrounding_box(&b, &p.bbox));
+        let centroid_box = info.iter().skip(1)
+            .fold(AABB::new(info[0].centroid, info[0].centroid), |b, p| surrounding_box(&b, &AABB::new(p.centroid, p.centroid)));
+        let axis = longest_axis(&centroid_box);
+
+        let node_index = self.nodes.len();
+        self.nodes.push(LinearNode { bbox: bbox.clone(), offset: 0, count: 0, axis });
+
+        let n = info.len();
+        let flat = centroid_box.max[axis] <= centroid_box.min[axis];
+        let mid = if n == 1 || (flat && n <= MAX_LEAF_SIZE) {
+            None
+        } else if flat {
+            // coincident centroids, instances placed at the same point or degenerate triangles,
+            // cannot be told apart, so they are halved in the order they come
+            Some(n / 2)
+        } else if method == SplitMethod::Sah && depth < MAX_SAH_DEPTH {
+            sah_split(info, &bbox, &centroid_box, axis)
+        } else if n <= MAX_LEAF_SIZE {
+            None
+        } else {
+            Some(median_split(info, axis))
+        };
+
+        match mid {
+            None => {
+                self.nodes[node_index].offset = self.primitives.len();
+                self.nodes[node_index].count = n;
+                self.primitives.extend(info.iter().map(|p| objects[p.index].clone()));
+                self.stats.add_leaf(depth, n, bbox.surface_area());
+            },
+            Some(mid) => {
+                let (left, right) = info.split_at_mut(mid);
+                self.build(objects, left, method, depth + 1);
+                let second = self.build(objects, right, method, depth + 1);
+                self.nodes[node_index].offset = second;
+                self.stats.add_interior(depth, bbox.surface_area());
+            },
+        }
+
+        node_index
+    }
+}
+
+impl FlatBvh {
+    // every primitive in a leaf whose box the ray crosses between t_min and t_max, until `visit`
+    // returns false
+    pub fn for_each_crossed(&self, r: &Ray, t_min: f32, t_max: f32, mut visit: impl FnMut(&Arc<dyn Hittable>) -> bool) {
+        for object in &self.unbounded {
+            if !visit(object) {
+                return;
+            }
+        }
+        if self.nodes.is_empty() {
+            return;
+        }
+
+        let mut stack = [0usize; STACK_SIZE];
+        let mut to_visit = 0;
+        let mut current = 0;
+        loop {
+            let node = &self.nodes[current];
+            if node.bbox.hit(r, t_min, t_max) {
+                if node.count > 0 {
+                    for object in &self.primitives[node.offset..node.offset + node.count] {
+                        if !visit(object) {
+                            return;
+                        }
+                    }
+                } else {
+                    stack[to_visit] = node.offset;
+                    to_visit += 1;
+                    current += 1;
+                    continue;
+                }
+            }
+
+            if to_visit == 0 {
+                break;
+            }
+            to_visit -= 1;
+            current = stack[to_visit];
+        }
+    }
+}
+
+fn longest_axis(bbox: &AABB) -> usize {
+    let d = bbox.max - bbox.min;
+    if d.x >= d.y && d.x >= d.z {
+        0
+    } else if d.y >= d.z {
+        1
+    } else {
+        2
+    }
+}
+
+fn median_split(info: &mut [PrimitiveInfo], axis: usize) -> usize {
+    let mid = info.len() / 2;
+    info.select_nth_unstable_by(mid, |a, b| {
+        a.centroid[axis].partial_cmp(&b.centroid[axis]).unwrap_or(Ordering::Equal)
+    });
+    mid
+}
+
+// buckets the centroids along `axis` and partitions at the cheapest bucket boundary,
+// returns None when keeping all primitives in one leaf is cheaper
+fn sah_split(info: &mut [PrimitiveInfo], bbox: &AABB, centroid_box: &AABB, axis: usize) -> Option<usize> {
+    let n = info.len();
+    let lo = centroid_box.min[axis];
+    let extent = centroid_box.max[axis] - lo;
+    let bin_of = |c: &Point3| (((c[axis] - lo) / extent * BIN_COUNT as f32) as usize).min(BIN_COUNT - 1);
+
+    let mut bins = vec![Bin { count: 0, bbox: None }; BIN_COUNT];
+    for p in info.iter() {
+        let bin = &mut bins[bin_of(&p.centroid)];
+        bin.count += 1;
+        bin.bbox = Some(merge(&bin.bbox, &p.bbox));
+    }
+
+    // sweep from the right to get the area and count on the far side of every boundary
+    let mut right_area = [0.; BIN_COUNT];
+    let mut right_count = [0; BIN_COUNT];
+    let mut acc: Option<AABB> = None;
+    let mut count = 0;
+    for i in (1..BIN_COUNT).rev() {
+        if let Some(b) = &bins[i].bbox {
+            acc = Some(merge(&acc, b));
+        }
+        count += bins[i].count;
+        right_area[i] = acc.as_ref().map_or(0., |b| b.surface_area());
+        right_count[i] = count;
+    }
+
+    let mut best_cost = f32::INFINITY;
+    let mut best_split = 0;
+    let mut acc: Option<AABB> = None;
+    let mut count = 0;
+    for i in 0..BIN_COUNT - 1 {
+        if let Some(b) = &bins[i].bbox {
+            acc = Some(merge(&acc, b));
+        }
+        count += bins[i].count;
+        let left_area = acc.as_ref().map_or(0., |b| b.surface_area());
+        let cost = TRAVERSAL_COST + INTERSECTION_COST
+            * (count as f32 * left_area + right_count[i + 1] as f32 * right_area[i + 1]) / bbox.surface_area();
+        if count > 0 && right_count[i + 1] > 0 && cost < best_cost {
+            best_cost = cost;
+            best_split = i;
+        }
+    }
+
+    let leaf_cost = INTERSECTION_COST * n as f32;
+    if n <= MAX_LEAF_SIZE && leaf_cost <= best_cost {
+        return None;
+    }
+    if best_cost == f32::INFINITY {
+        return Some(median_split(info, axis));
+    }
+
+    // partition in place, primitives left of the chosen boundary first
+    let mut mid = 0;
+    for i in 0..n {
+        if bin_of(&info[i].centroid) <= best_split {
+            info.swap(i, mid);
+            mid += 1;
+        }
+    }
+
+    if mid == 0 || mid == n {
+        Some(median_split(info, axis))
+    } else {
+        Some(mid)
+    }
+}
+
+fn merge(acc: &Option<AABB>, b: &AABB) -> AABB {
+    match acc {
+        Some(a) => surrounding_box(a, b),
+        None => b.clone(),
+    }
+}
+
+impl Hittable for FlatBvh {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        let mut closest_so_far = t_max;
+        let mut temp_rec = None;
+        for object in &self.unbounded {
+            if let Some(rec) = object.hit(r, t_min, closest_so_far) {
+                closest_so_far = rec.t;
+                temp_rec = Some(rec);
+            }
+        }
+        if self.nodes.is_empty() {
+            return temp_rec;
+        }
+
+        let dir_is_neg = [r.direction.x < 0., r.direction.y < 0., r.direction.z < 0.];
+
+        let mut stack = [0usize; STACK_SIZE];
+        let mut to_visit = 0;
+        let mut current = 0;
+
+        loop {
+            let node = &self.nodes[current];
+            if node.bbox.hit(r, t_min, closest_so_far) {
+                if node.count > 0 {
+                    for object in &self.primitives[node.offset..node.offset + node.count] {
+                        if let Some(rec) = object.hit(r, t_min, closest_so_far) {
+                            closest_so_far = rec.t;
+                            temp_rec = Some(rec);
+                        }
+                    }
+                } else {
+                    // visit the near child first so the far one can be culled by closest_so_far
+                    if dir_is_neg[node.axis] {
+                        stack[to_visit] = current + 1;
+                        current = node.offset;
+                    } else {
+                        stack[to_visit] = node.offset;
+                        current += 1;
+                    }
+                    to_visit += 1;
+                    continue;
+                }
+            }
+
+            if to_visit == 0 {
+                break;
+            }
+            to_visit -= 1;
+            current = stack[to_visit];
+        }
+
+        temp_rec
+    }
+
+    fn bounding_box(&self, _t0: f32, _t1: f32) -> Option<AABB> {
+        if !self.unbounded.is_empty() {
+            return None;
+        }
+        self.nodes.first().map(|node| node.bbox.clone())
+    }
+
+    fn transmittance(&self, r: &Ray, t_min: f32, t_max: f32) -> f32 {
+        let mut transmittance = 1.;
+        self.for_each_crossed(r, t_min, t_max, |object| {
+            transmittance *= object.transmittance(r, t_min, t_max);
+            transmittance > 0.
+        });
+        transmittance
+    }
+
+    // the primitives are picked equally often, like in a list
+    fn pdf_value(&self, r: &Ray, t_min: f32, t_max: f32) -> f32 {
+        let mut sum = 0.;
+        self.for_each_crossed(r, t_min, t_max, |object| {
+            sum += object.pdf_value(r, t_min, t_max);
+            true
+        });
+        let count = self.primitives.len() + self.unbounded.len();
+        if count == 0 { 0. } else { sum / count as f32 }
+    }
+
+    fn random(&self, origin: &Point3, rng: &mut Sampler) -> Vec3 {
+        let count = self.primitives.len() + self.unbounded.len();
+        if count == 0 {
+            return Vec3::new(1., 0., 0.);
+        }
+        match rng.gen_range(0, count) {
+            index if index < self.primitives.len() => self.primitives[index].random(origin, rng),
+            index => self.unbounded[index - self.primitives.len()].random(origin, rng),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::bvh::assert_same_hits;
+    use crate::camera::Camera;
+    use crate::vec3::Vec3;
+    use crate::sampler::Sampler;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_random_scene_hits() {
+        let world = crate::random_scene(&mut Sampler::seed_from_u64(1));
+        let cam = Camera::new(Vec3::new(13., 2., 3.), Vec3::new(0., 0., 0.), Vec3::new(0., 1., 0.),
+                              20., 16. / 9., 0.1, 10., 0., 1.);
+
+        for &method in &[SplitMethod::Median, SplitMethod::Sah] {
+            let bvh = FlatBvh::new(&world, method, 0., 1.);
+            assert_same_hits(&world, &bvh, &cam, Point3::new(-12., 0.1, -12.), Point3::new(12., 3., 12.));
+        }
+    }
+
+    #[test]
+    fn test_cornell_box_hits() {
+        let (world, _) = crate::cornell_box();
+        let cam = Camera::new(Vec3::new(278., 278., -800.), Vec3::new(278., 278., 0.), Vec3::new(0., 1., 0.),
+                              40., 1., 0., 10., 0., 1.);
+
+        for &method in &[SplitMethod::Median, SplitMethod::Sah] {
+            let bvh = FlatBvh::new(&world, method, 0., 1.);
+            assert_same_hits(&world, &bvh, &cam, Point3::new(1., 331., 1.), Point3::new(554., 554., 554.));
+        }
+    }
+
+    #[test]
+    fn test_stats() {
+        let world = crate::random_scene(&mut Sampler::seed_from_u64(1));
+        let n = world.objects.len();
+
+        for &method in &[SplitMethod::Median, SplitMethod::Sah] {
+            let stats = FlatBvh::new(&world, method, 0., 1.).stats;
+            assert_eq!(n, stats.primitive_count);
+            assert_eq!(2 * stats.leaf_count - 1, stats.node_count);
+            assert!(stats.max_leaf_size <= MAX_LEAF_SIZE);
+            assert!(stats.max_depth < STACK_SIZE);
+        }
+
+        let median = FlatBvh::new(&world, SplitMethod::Median, 0., 1.).stats;
+        let sah = FlatBvh::new(&world, SplitMethod::Sah, 0., 1.).stats;
+        assert!(sah.sah_cost <= median.sah_cost);
+
+        // copies of one sphere still end up in small leaves
+        let sphere = world.objects[0].clone();
+        let coincident = HittableList { objects: vec![sphere; 100] };
+        for &method in &[SplitMethod::Median, SplitMethod::Sah] {
+            let stats = FlatBvh::new(&coincident, method, 0., 1.).stats;
+            assert_eq!(100, stats.primitive_count);
+            assert!(stats.max_leaf_size <= MAX_LEAF_SIZE);
+        }
+    }
+}
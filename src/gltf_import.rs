@@ -0,0 +1,454 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use gltf::camera::Projection;
+use gltf::image::Format;
+use gltf::mesh::Mode;
+use gltf::texture::WrappingMode;
+
+use crate::color::Color;
+use crate::hittable_list::HittableList;
+use crate::material::Material;
+use crate::matrix::{Matrix4, Transform};
+use crate::principled::{Parameter, Principled};
+use crate::texture::{SolidColor, Texture};
+use crate::triangle::{Mesh, TriangleMesh};
+use crate::vec3::{Point3, Vec3};
+
+#[derive(Debug)]
+pub enum GltfError {
+    Gltf(PathBuf, gltf::Error),
+    Unsupported(PathBuf, String),
+}
+
+impl Display for GltfError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            GltfError::Gltf(path, e) => write!(f, "{}: {}", path.display(), e),
+            GltfError::Unsupported(path, message) => write!(f, "{}: {}", path.display(), message),
+        }
+    }
+}
+
+impl Error for GltfError {}
+
+// the first perspective camera of the scene, in world space
+pub struct GltfCamera {
+    pub look_from: Point3,
+    pub look_at: Point3,
+    pub vup: Vec3,
+    // degrees
+    pub vfov: f32,
+    pub aspect_ratio: Option<f32>,
+}
+
+pub struct GltfModel {
+    // one mesh per primitive, in world space
+    pub objects: HittableList,
+    pub camera: Option<GltfCamera>,
+}
+
+// texels converted to linear floats once, shared by every texture sampling the image
+struct Image {
+    width: usize,
+    height: usize,
+    texels: Vec<Color>,
+}
+
+impl Image {
+    fn new(data: &gltf::image::Data, srgb: bool) -> Self {
+        let (channels, size) = match data.format {
+            Format::R8 => (1, 1),
+            Format::R8G8 => (2, 1),
+            Format::R8G8B8 => (3, 1),
+            Format::R8G8B8A8 => (4, 1),
+            Format::R16 => (1, 2),
+            Format::R16G16 => (2, 2),
+            Format::R16G16B16 => (3, 2),
+            Format::R16G16B16A16 => (4, 2),
+            Format::R32G32B32FLOAT => (3, 4),
+            Format::R32G32B32A32FLOAT => (4, 4),
+        };
+        let sample = |bytes: &[u8]| match size {
+            1 => bytes[0] as f32 / 255.,
+            2 => u16::from_le_bytes([bytes[0], bytes[1]]) as f32 / 65535.,
+            _ => f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+        };
+        let decode = |x: f32| if srgb { srgb_to_linear(x) } else { x };
+
+        let texels = data.pixels
+            .chunks_exact(channels * size)
+            .map(|texel| {
+                let r = sample(&texel[..size]);
+                // gray images repeat their only channel
+                let (g, b) = if channels < 3 { (r, r) } else { (sample(&texel[size..]), sample(&texel[2 * size..])) };
+                Color(Vec3::new(decode(r), decode(g), decode(b)))
+            })
+            .collect();
+
+        Self { width: data.width as usize, height: data.height as usize, texels }
+    }
+}
+
+fn srgb_to_linear(x: f32) -> f32 {
+    if x <= 0.04045 {
+        x / 12.92
+    } else {
+        ((x + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn wrap(x: f32, mode: WrappingMode) -> f32 {
+    match mode {
+        WrappingMode::ClampToEdge => x.clamp(0., 1.),
+        WrappingMode::Repeat => x - x.floor(),
+        WrappingMode::MirroredRepeat => {
+            let x = x.rem_euclid(2.);
+            if x > 1. { 2. - x } else { x }
+        },
+    }
+}
+
+// a glTF factor times the nearest texel of its optional texture, v goes down the image
+struct GltfTexture {
+    factor: Color,
+    image: Option<(Arc<Image>, [WrappingMode; 2])>,
+}
+
+impl Texture for GltfTexture {
+    fn value(&self, u: f32, v: f32, _p: &Point3) -> Color {
+        match &self.image {
+            None => self.factor,
+            Some((image, [wrap_s, wrap_t])) => {
+                let i = ((wrap(u, *wrap_s) * image.width as f32) as usize).min(image.width - 1);
+                let j = ((wrap(v, *wrap_t) * image.height as f32) as usize).min(image.height - 1);
+                Color(self.factor.0 * image.texels[j * image.width + i].0)
+            },
+        }
+    }
+
+    // a black factor darkens any texture
+    fn is_black(&self) -> bool {
+        self.factor.0 == Vec3::new(0., 0., 0.)
+    }
+}
+
+struct Importer<'a> {
+    path: &'a Path,
+    buffers: Vec<gltf::buffer::Data>,
+    images: Vec<gltf::image::Data>,
+    // by image index and whether it holds sRGB colors
+    decoded: HashMap<(usize, bool), Arc<Image>>,
+    materials: HashMap<Option<usize>, Arc<dyn Material>>,
+    // replaces every material of the file when set
+    material_override: Option<Arc<dyn Material>>,
+    objects: HittableList,
+    camera: Option<GltfCamera>,
+}
+
+impl Importer<'_> {
+    fn texture(&mut self, info: Option<gltf::texture::Texture>, factor: Color, srgb: bool) -> Box<dyn Texture> {
+        let texture = match info {
+            Some(texture) => texture,
+            None => return Box::new(SolidColor::new_from_color(factor)),
+        };
+        let index = texture.source().index();
+        let images = &self.images;
+        let image = self.decoded.entry((index, srgb)).or_insert_with(|| Arc::new(Image::new(&images[index], srgb)));
+        let sampler = texture.sampler();
+
+        Box::new(GltfTexture { factor, image: Some((image.clone(), [sampler.wrap_s(), sampler.wrap_t()])) })
+    }
+
+    // only the first set of texture coordinates is read, so every texture uses it
+    fn material(&mut self, material: gltf::Material) -> Arc<dyn Material> {
+        if let Some(material) = &self.material_override {
+            return material.clone();
+        }
+        if let Some(material) = self.materials.get(&material.index()) {
+            return material.clone();
+        }
+
+        let pbr = material.pbr_metallic_roughness();
+        let [r, g, b, _] = pbr.base_color_factor();
+        let base_color = self.texture(pbr.base_color_texture().map(|i| i.texture()), Color(Vec3::new(r, g, b)), true);
+        let mut principled = Principled::new(base_color);
+
+        // roughness in green and metalness in blue, like glTF packs them
+        let packed = pbr.metallic_roughness_texture().map(|i| i.texture());
+        let factor = Color(Vec3::new(1., pbr.roughness_factor(), pbr.metallic_factor()));
+        principled.roughness = Parameter::from_texture(self.texture(packed.clone(), factor, false), 1);
+        principled.metallic = Parameter::from_texture(self.texture(packed, factor, false), 2);
+
+        // the reflectance of dielectrics at normal incidence follows from the index of refraction
+        principled.ior = material.ior().unwrap_or(1.5);
+        let reflectance = ((principled.ior - 1.) / (principled.ior + 1.)).powi(2);
+        principled.specular = Parameter::constant(reflectance / 0.08);
+        if let Some(transmission) = material.transmission() {
+            let factor = transmission.transmission_factor();
+            let texture = self.texture(transmission.transmission_texture().map(|i| i.texture()),
+                                       Color(Vec3::new(factor, factor, factor)), false);
+            principled.transmission = Parameter::from_texture(texture, 0);
+        }
+
+        let [r, g, b] = material.emissive_factor();
+        principled.emission = self.texture(material.emissive_texture().map(|i| i.texture()), Color(Vec3::new(r, g, b)), true);
+        let normal = material.normal_texture();
+        principled.normal_scale = normal.as_ref().map_or(1., |n| n.scale());
+        principled.normal_map = normal.map(|n| self.texture(Some(n.texture()), Color(Vec3::new(1., 1., 1.)), false));
+
+        let built: Arc<dyn Material> = Arc::new(principled);
+        self.materials.insert(material.index(), built.clone());
+        built
+    }
+
+    fn node(&mut self, node: gltf::Node, parent: &Matrix4) -> Result<(), GltfError> {
+        let world = *parent * Matrix4::from_cols(node.transform().matrix());
+
+        if let Some(mesh) = node.mesh() {
+            for primitive in mesh.primitives() {
+                self.primitive(&primitive, &world)?;
+            }
+        }
+
+        if let (None, Some(camera)) = (&self.camera, node.camera()) {
+            if let Projection::Perspective(perspective) = camera.projection() {
+                // glTF cameras look down -z with +y up
+                let look_from = world.transform_point(Point3::new(0., 0., 0.));
+                self.camera = Some(GltfCamera {
+                    look_from,
+                    look_at: look_from + world.transform_vector(Vec3::new(0., 0., -1.)),
+                    vup: world.transform_vector(Vec3::new(0., 1., 0.)),
+                    vfov: perspective.yfov().to_degrees(),
+                    aspect_ratio: perspective.aspect_ratio(),
+                });
+            }
+        }
+
+        for child in node.children() {
+            self.node(child, &world)?;
+        }
+        Ok(())
+    }
+
+    // bakes the world transform of the node into the vertices
+    fn primitive(&mut self, primitive: &gltf::Primitive, world: &Matrix4) -> Result<(), GltfError> {
+        // nodes scaled to nothing have no surface to render
+        let transform = match Transform::new(*world) {
+            Some(transform) => transform,
+            None => return Ok(()),
+        };
+
+        let path = self.path;
+        let unsupported = |message: String| GltfError::Unsupported(path.to_path_buf(), message);
+        let buffers = &self.buffers;
+        let reader = primitive.reader(|buffer| buffers.get(buffer.index()).map(|data| &data.0[..]));
+
+        let positions: Vec<Point3> = reader.read_positions()
+            .ok_or_else(|| unsupported("primitive without positions".to_string()))?
+            .map(|p| transform.point(Point3::new(p[0], p[1], p[2])))
+            .collect();
+        let normals = reader.read_normals()
+            .map(|normals| normals
+                .map(|n| transform.normal(Vec3::new(n[0], n[1], n[2])).normalize())
+                .collect())
+            .unwrap_or_default();
+        let uvs = reader.read_tex_coords(0).map(|uvs| uvs.into_f32().collect()).unwrap_or_default();
+        let colors = reader.read_colors(0)
+            .map(|colors| colors.into_rgb_f32().map(|c| Color(Vec3::new(c[0], c[1], c[2]))).collect())
+            .unwrap_or_default();
+        let corners: Vec<u32> = match reader.read_indices() {
+            Some(indices) => indices.into_u32().collect(),
+            None => (0..positions.len() as u32).collect(),
+        };
+
+        let mut indices: Vec<[u32; 3]> = match primitive.mode() {
+            Mode::Triangles => corners.chunks_exact(3).map(|c| [c[0], c[1], c[2]]).collect(),
+            // every other strip triangle is flipped to keep the winding
+            Mode::TriangleStrip => (2..corners.len())
+                .map(|i| if i % 2 == 0 {
+                    [corners[i - 2], corners[i - 1], corners[i]]
+                } else {
+                    [corners[i - 1], corners[i - 2], corners[i]]
+                })
+                .collect(),
+            Mode::TriangleFan => (2..corners.len()).map(|i| [corners[0], corners[i - 1], corners[i]]).collect(),
+            // points and lines have no surface to render
+            _ => return Ok(()),
+        };
+        // neither do primitives of fewer than three vertices
+        if indices.is_empty() {
+            return Ok(());
+        }
+        // mirroring transforms flip the winding
+        if world.determinant() < 0. {
+            for triangle in &mut indices {
+                triangle.swap(1, 2);
+            }
+        }
+
+        let material = self.material(primitive.material());
+        let mesh = Mesh { positions, normals, uvs, colors, indices, material };
+        mesh.validate().map_err(unsupported)?;
+        self.objects.add(Arc::new(TriangleMesh::new(mesh)));
+        Ok(())
+    }
+}
+
+// imports the default scene of a .gltf or .glb file, with its own materials unless one is given
+pub fn load<P: AsRef<Path>>(path: P, material: Option<&Arc<dyn Material>>) -> Result<GltfModel, GltfError> {
+    let path = path.as_ref();
+    let (document, buffers, images) = gltf::import(path).map_err(|e| GltfError::Gltf(path.to_path_buf(), e))?;
+
+    let mut importer = Importer {
+        path,
+        buffers,
+        images,
+        decoded: HashMap::new(),
+        materials: HashMap::new(),
+        material_override: material.cloned(),
+        objects: HittableList { objects: Vec::new() },
+        camera: None,
+    };
+
+    let scene = document.default_scene().or_else(|| document.scenes().next())
+        .ok_or_else(|| GltfError::Unsupported(path.to_path_buf(), "no scene".to_string()))?;
+    for node in scene.nodes() {
+        importer.node(node, &Matrix4::identity())?;
+    }
+    if importer.objects.objects.is_empty() {
+        return Err(GltfError::Unsupported(path.to_path_buf(), "no triangles".to_string()));
+    }
+
+    Ok(GltfModel { objects: importer.objects, camera: importer.camera })
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::hittable::Hittable;
+    use crate::ray::Ray;
+    use std::fs;
+
+    // one triangle in the xy plane, instanced by a node translated by 5 along x whose child is scaled
+    // by 2 and mirrored along z, plus a camera at z = 10 looking back at the origin
+    const DOCUMENT: &str = r#"{
+        "asset": { "version": "2.0" },
+        "scene": 0,
+        "scenes": [{ "nodes": [0, 2] }],
+        "nodes": [
+            { "translation": [5, 0, 0], "children": [1] },
+            { "scale": [2, 2, -2], "mesh": 0 },
+            { "translation": [0, 0, 10], "camera": 0 }
+        ],
+        "cameras": [{ "type": "perspective", "perspective": { "yfov": 0.5, "aspectRatio": 2.0, "znear": 0.1 } }],
+        "meshes": [{ "primitives": [{ "attributes": { "POSITION": 0 }, "indices": 1, "material": 0 }] }],
+        "materials": [{ "pbrMetallicRoughness": { "baseColorFactor": [1, 0, 0, 1], "metallicFactor": 0 },
+                        "emissiveFactor": [0, 0, 2] }],
+        "accessors": [
+            { "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+              "min": [0, 0, 0], "max": [1, 1, 0] },
+            { "bufferView": 1, "componentType": 5123, "count": 3, "type": "SCALAR" }
+        ],
+        "bufferViews": [
+            { "buffer": 0, "byteOffset": 0, "byteLength": 36 },
+            { "buffer": 0, "byteOffset": 36, "byteLength": 6 }
+        ],
+        "buffers": [{ "uri": "triangle.bin", "byteLength": 42 }]
+    }"#;
+
+    fn binary_chunk() -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for x in [0f32, 0., 0., 1., 0., 0., 0., 1., 0.] {
+            bytes.extend_from_slice(&x.to_le_bytes());
+        }
+        for i in [0u16, 1, 2] {
+            bytes.extend_from_slice(&i.to_le_bytes());
+        }
+        bytes
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("gltf-{}-{}", std::process::id(), name));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn padded(mut bytes: Vec<u8>, padding: u8) -> Vec<u8> {
+        while !bytes.len().is_multiple_of(4) {
+            bytes.push(padding);
+        }
+        bytes
+    }
+
+    // the same document with its buffer embedded in a binary container
+    fn glb() -> Vec<u8> {
+        let json = padded(DOCUMENT.replace(r#""uri": "triangle.bin", "#, "").into_bytes(), b' ');
+        let bin = padded(binary_chunk(), 0);
+
+        let mut glb = Vec::new();
+        glb.extend_from_slice(b"glTF");
+        glb.extend_from_slice(&2u32.to_le_bytes());
+        glb.extend_from_slice(&((12 + 8 + json.len() + 8 + bin.len()) as u32).to_le_bytes());
+        glb.extend_from_slice(&(json.len() as u32).to_le_bytes());
+        glb.extend_from_slice(b"JSON");
+        glb.extend_from_slice(&json);
+        glb.extend_from_slice(&(bin.len() as u32).to_le_bytes());
+        glb.extend_from_slice(b"BIN\0");
+        glb.extend_from_slice(&bin);
+        glb
+    }
+
+    fn assert_model(model: &GltfModel) {
+        assert_eq!(model.objects.objects.len(), 1);
+
+        // the triangle spans [5, 7] x [0, 2] in the z = 0 plane
+        let r = Ray::new_without_move(Point3::new(5.5, 0.5, 3.), Vec3::new(0., 0., -1.));
+        let rec = model.objects.hit(&r, 0.001, f32::MAX).unwrap();
+        assert_eq!(rec.t, 3.);
+        assert_eq!(rec.p, Point3::new(5.5, 0.5, 0.));
+        // the mirror along z turns the front face of the triangle towards -z
+        assert!(!rec.is_front_face);
+        assert_eq!(rec.material.emitted(rec.u, rec.v, &rec.p).0, Vec3::new(0., 0., 2.));
+
+        let miss = Ray::new_without_move(Point3::new(0.5, 0.5, 3.), Vec3::new(0., 0., -1.));
+        assert!(model.objects.hit(&miss, 0.001, f32::MAX).is_none());
+
+        let camera = model.camera.as_ref().unwrap();
+        assert_eq!(camera.look_from, Point3::new(0., 0., 10.));
+        assert_eq!(camera.look_at, Point3::new(0., 0., 9.));
+        assert_eq!(camera.vup, Vec3::new(0., 1., 0.));
+        assert!((camera.vfov - 0.5f32.to_degrees()).abs() < 1e-4);
+        assert_eq!(camera.aspect_ratio, Some(2.));
+    }
+
+    #[test]
+    fn test_load_gltf_with_bin() {
+        let dir = temp_dir("separate");
+        fs::write(dir.join("triangle.gltf"), DOCUMENT).unwrap();
+        fs::write(dir.join("triangle.bin"), binary_chunk()).unwrap();
+
+        assert_model(&load(dir.join("triangle.gltf"), None).unwrap());
+    }
+
+    #[test]
+    fn test_load_glb() {
+        let dir = temp_dir("binary");
+        fs::write(dir.join("triangle.glb"), glb()).unwrap();
+
+        assert_model(&load(dir.join("triangle.glb"), None).unwrap());
+    }
+
+    #[test]
+    fn test_missing_buffer() {
+        let dir = temp_dir("missing");
+        fs::write(dir.join("triangle.gltf"), DOCUMENT).unwrap();
+        let _ = fs::remove_file(dir.join("triangle.bin"));
+
+        assert!(matches!(load(dir.join("triangle.gltf"), None), Err(GltfError::Gltf(..))));
+    }
+}
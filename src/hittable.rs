@@ -1,179 +1,306 @@
-use std::rc::Rc;
+use std::sync::Arc;
 
+use crate::color::Color;
 use crate::ray::Ray;
 use crate::vec3::{Point3, Vec3};
 use crate::material::Material;
 use crate::aabb::AABB;
+use crate::matrix::Transform;
+use crate::sampler::Sampler;
 
 pub struct HitRecord {
     pub p: Point3,
     pub normal: Vec3,
-    pub material: Rc<dyn Material>,
+    pub material: Arc<dyn Material>,
     pub t: f32,
     pub u: f32,
     pub v: f32,
     pub is_front_face: bool,
+    // interpolated vertex color of meshes that have one
+    pub color: Option<Color>,
+    // directions of increasing u and v on textured triangles, to orient normal maps
+    pub tangents: Option<(Vec3, Vec3)>,
 }
 
-pub trait Hittable {
+pub trait Hittable: Send + Sync {
     fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord>;
     fn bounding_box(&self, t0: f32, t1: f32) -> Option<AABB>;
+
+    // the fraction of light passing along the ray between t_min and t_max; surfaces block all of it,
+    // and a medium lets through as much as the rays it does not scatter
+    fn transmittance(&self, r: &Ray, t_min: f32, t_max: f32) -> f32 {
+        if self.hit(r, t_min, t_max).is_some() { 0. } else { 1. }
+    }
+
+    // the density, over the directions seen from the origin of the ray, with which `random` picks the
+    // point where the ray hits between t_min and t_max; zero for objects no light is sampled on
+    fn pdf_value(&self, _r: &Ray, _t_min: f32, _t_max: f32) -> f32 {
+        0.
+    }
+
+    // the vector from `origin` to a random point of the object, reached at t = 1
+    fn random(&self, _origin: &Point3, _rng: &mut Sampler) -> Vec3 {
+        Vec3::new(1., 0., 0.)
+    }
+
+    // whether the whole object gives off light, so that it can be sampled as one light
+    fn emits(&self) -> bool {
+        false
+    }
+
+    // the parts giving off light of objects made of several materials, like imported models
+    fn emitting_parts(&self) -> Vec<Arc<dyn Hittable>> {
+        Vec::new()
+    }
+}
+
+// the lights of the object, the object itself when all of it gives off light
+pub fn emitters(object: &Arc<dyn Hittable>) -> Vec<Arc<dyn Hittable>> {
+    if object.emits() {
+        vec![object.clone()]
+    } else {
+        object.emitting_parts()
+    }
 }
 
 impl HitRecord {
-    pub fn new(t: f32, spot: Point3, u: f32, v: f32, is_front_face: bool, outward_normal: Vec3, material: &Rc<dyn Material>) -> Self {
+    pub fn new(t: f32, spot: Point3, u: f32, v: f32, is_front_face: bool, outward_normal: Vec3, material: &Arc<dyn Material>) -> Self {
         HitRecord {
-            t: t,
+            t,
             p: spot,
-            u: u,
-            v: v,
-            is_front_face: is_front_face,
+            u,
+            v,
+            is_front_face,
             normal: if is_front_face {
                 outward_normal
             } else {
                 -outward_normal
             },
-            material: Rc::clone(material),
+            material: Arc::clone(material),
+            color: None,
+            tangents: None,
         }
     }
 }
 
-pub struct Translate {
-    pub object: Box<dyn Hittable>,
-    pub offset: Vec3,
+// an object placed in the world by an affine transform, rays are intersected in its own space;
+// the object can be shared by many instances, each optionally overriding its materials
+pub struct Transformed {
+    pub object: Arc<dyn Hittable>,
+    pub transform: Transform,
+    pub material: Option<Arc<dyn Material>>,
 }
 
-impl Hittable for Translate {
+impl Transformed {
+    pub fn new(object: Arc<dyn Hittable>, transform: Transform) -> Self {
+        Self { object, transform, material: None }
+    }
 
+    pub fn with_material(self, material: Arc<dyn Material>) -> Self {
+        Self { material: Some(material), ..self }
+    }
+}
+
+impl Hittable for Transformed {
+
+    // the box around the eight transformed corners of the object's box
     fn bounding_box(&self, time0: f32, time1: f32) -> Option<AABB> {
-        if let Some(output_box) = self.object.bounding_box(time0, time1) {
-            Some(AABB {
-                min: output_box.min + self.offset,
-                max: output_box.max + self.offset,
-            })
-        } else {
-            None
-        }
+        self.object.bounding_box(time0, time1).map(|b| {
+            let mut min = Vec3::new(f32::INFINITY, f32::INFINITY, f32::INFINITY);
+            let mut max = Vec3::new(f32::NEG_INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY);
+
+            for corner in 0..8 {
+                let x = if corner & 1 == 0 { b.min.x } else { b.max.x };
+                let y = if corner & 2 == 0 { b.min.y } else { b.max.y };
+                let z = if corner & 4 == 0 { b.min.z } else { b.max.z };
+                let p = self.transform.point(Point3::new(x, y, z));
+
+                min = Vec3::new(min.x.min(p.x), min.y.min(p.y), min.z.min(p.z));
+                max = Vec3::new(max.x.max(p.x), max.y.max(p.y), max.z.max(p.z));
+            }
+
+            AABB { min, max }
+        })
     }
 
+    // the object space direction is not normalized, so t is the same in both spaces
     fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
-        let moved_r = Ray {
-            origin: r.origin - self.offset,
-            direction: r.direction,
-            time: r.time
+        let object_r = Ray {
+            origin: self.transform.inverse_point(r.origin),
+            direction: self.transform.inverse_vector(r.direction),
+            time: r.time,
         };
 
-        if let Some(mut hit_record) = self.object.hit(&moved_r, t_min, t_max) {
-            hit_record.p += self.offset;
-            let is_front_face = moved_r.direction.dot(hit_record.normal) < 0.;
-            hit_record.is_front_face = is_front_face;
-            hit_record.normal = if is_front_face { hit_record.normal } else { -hit_record.normal };
+        self.object.hit(&object_r, t_min, t_max).map(|mut record| {
+            record.p = self.transform.point(record.p);
+            // the normal keeps its side of the surface, and so the face the ray hits
+            record.normal = self.transform.normal(record.normal).normalize();
+            record.tangents = record.tangents.map(|(dpdu, dpdv)| (self.transform.vector(dpdu), self.transform.vector(dpdv)));
+            if let Some(material) = &self.material {
+                record.material = material.clone();
+            }
+            record
+        })
+    }
 
-            Some(hit_record)
-        } else {
-            None
+    fn transmittance(&self, r: &Ray, t_min: f32, t_max: f32) -> f32 {
+        let object_r = Ray {
+            origin: self.transform.inverse_point(r.origin),
+            direction: self.transform.inverse_vector(r.direction),
+            time: r.time,
+        };
+        self.object.transmittance(&object_r, t_min, t_max)
+    }
+
+    // a linear map M turns the unit direction w into M w / |M w|, stretching solid angles by
+    // |det M| / |M w|^3, and M w is the world space direction when w is the object space one
+    fn pdf_value(&self, r: &Ray, t_min: f32, t_max: f32) -> f32 {
+        let object_r = Ray {
+            origin: self.transform.inverse_point(r.origin),
+            direction: self.transform.inverse_vector(r.direction),
+            time: r.time,
+        };
+        let pdf = self.object.pdf_value(&object_r, t_min, t_max);
+        if pdf == 0. {
+            return 0.;
         }
+
+        let stretch = r.direction.length() / object_r.direction.length();
+        pdf * stretch * stretch * stretch / self.transform.matrix.determinant().abs()
     }
-}
 
-pub struct RotateY {
-    object: Box<dyn Hittable>,
-    sin_theta: f32,
-    cos_theta: f32,
-    bbox: Option<AABB>,
-}
+    fn random(&self, origin: &Point3, rng: &mut Sampler) -> Vec3 {
+        self.transform.vector(self.object.random(&self.transform.inverse_point(*origin), rng))
+    }
 
-impl RotateY {
-    pub fn new(p: Box<dyn Hittable>, angle: f32) -> Self {
-        let radians = angle.to_radians();
-        let sin_theta = radians.sin();
-        let cos_theta = radians.cos();
-        let bbox = p.bounding_box(0., 1.);
-
-        if let Some(b) = bbox {
-            let mut min = Vec3{ x: f32::INFINITY, y: f32::INFINITY, z: f32::INFINITY };
-            let mut max = Vec3{ x: f32::NEG_INFINITY, y: f32::NEG_INFINITY, z: f32::NEG_INFINITY };
-
-            for i in 0..2 {
-                for j in 0..2 {
-                    for k in 0..2 {
-                        let x = (i as f32) * b.max.x + ((1 - i) as f32) * b.min.x;
-                        let y = (j as f32) * b.max.y + ((1 - j) as f32) * b.min.y;
-                        let z = (k as f32) * b.max.z + ((1 - k) as f32) * b.min.z;
-
-                        let new_x = cos_theta * x + sin_theta * z;
-                        let new_z = -sin_theta * x + cos_theta * z;
-
-                        let tester = Vec3 { x: new_x, y: y, z: new_z };
-
-                        min.x = min.x.min(tester.x);
-                        min.y = min.y.min(tester.y);
-                        min.z = min.z.min(tester.z);
-
-                        max.x = max.x.max(tester.x);
-                        max.y = max.y.max(tester.y);
-                        max.z = max.z.max(tester.z);
-                    }
-                }
-            }
+    fn emits(&self) -> bool {
+        match &self.material {
+            Some(material) => material.emits(),
+            None => self.object.emits(),
+        }
+    }
 
-            Self {
-                object: p,
-                sin_theta: sin_theta,
-                cos_theta: cos_theta,
-                bbox: Some(AABB { min: min, max: max }),
-            }
-        } else {
-            Self {
-                object: p,
-                sin_theta: sin_theta,
-                cos_theta: cos_theta,
-                bbox: None
-            }
+    // the emitting parts of the object placed like it, none when a material replaces theirs
+    fn emitting_parts(&self) -> Vec<Arc<dyn Hittable>> {
+        if self.material.is_some() {
+            return Vec::new();
         }
+        self.object.emitting_parts().into_iter()
+            .map(|part| Arc::new(Transformed::new(part, self.transform)) as Arc<dyn Hittable>)
+            .collect()
     }
 }
 
-impl Hittable for RotateY {
+#[cfg(test)]
+mod tests {
 
-    fn bounding_box(&self, _: f32, _: f32) -> Option<AABB> {
-        if let Some(bbox) = &self.bbox {
-            Some(AABB { min: bbox.min, max: bbox.max })
-        } else {
-            None
-        }
+    use super::*;
+    use crate::material::Lambertian;
+    use crate::matrix::Matrix4;
+    use crate::sphere::{Sphere, random_unit_vector};
+    use crate::aarect::XZRect;
+    use crate::r#box::Box;
+    use crate::triangle::Triangle;
+    use rand::SeedableRng;
+
+    fn assert_close(a: Vec3, b: Vec3) {
+        assert!((a - b).length() < 1e-4, "{} != {}", a, b);
     }
 
-    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
-        let mut origin = r.origin;
-        let mut direction = r.direction;
+    // a unit sphere stretched to an ellipsoid of radius 2 along x, then turned a quarter about z
+    fn ellipsoid() -> Transformed {
+        let material: Arc<dyn Material> = Arc::new(Lambertian::new_from_color(Color(Vec3::new(0.5, 0.5, 0.5))));
+        let sphere = Sphere { center: Point3::new(0., 0., 0.), radius: 1., material };
+        let matrix = Matrix4::rotation(Vec3::new(0., 0., 1.), 90.) * Matrix4::scaling(Vec3::new(2., 1., 1.));
 
-        origin.x = self.cos_theta * r.origin[0] - self.sin_theta * r.origin[2];
-        origin.z = self.sin_theta * r.origin[0] + self.cos_theta * r.origin[2];
+        Transformed::new(Arc::new(sphere), Transform::new(matrix).unwrap())
+    }
 
-        direction.x = self.cos_theta * r.direction[0] - self.sin_theta * r.direction[2];
-        direction.z = self.sin_theta * r.direction[0] + self.cos_theta * r.direction[2];
+    #[test]
+    fn test_transformed_hit() {
+        let object = ellipsoid();
 
-        let rotated_r = Ray { origin: origin, direction: direction, time: r.time };
+        // the long axis now lies along y
+        let rec = object.hit(&Ray::new_without_move(Point3::new(0., 5., 0.), Vec3::new(0., -1., 0.)), 0.001, f32::MAX).unwrap();
+        assert!((rec.t - 3.).abs() < 1e-4);
+        assert_close(rec.p, Point3::new(0., 2., 0.));
+        assert_close(rec.normal, Vec3::new(0., 1., 0.));
+        assert!(rec.is_front_face);
 
-        if let Some(mut record) = self.object.hit(&rotated_r, t_min, t_max) {
-            let mut p = record.p;
-            let mut normal = record.normal;
+        // off axis the normal follows the inverse transpose, not the stretched object space normal
+        let rec = object.hit(&Ray::new_without_move(Point3::new(0.3, 1.2, 5.), Vec3::new(0., 0., -1.)), 0.001, f32::MAX).unwrap();
+        assert_close(rec.normal, Vec3::new(rec.p.x, rec.p.y / 4., rec.p.z).normalize());
 
-            p.x = self.cos_theta * record.p[0] + self.sin_theta * record.p[2];
-            p.z = - self.sin_theta  * record.p[0] + self.cos_theta * record.p[2];
+        // from inside the normal faces the ray
+        let rec = object.hit(&Ray::new_without_move(Point3::new(0., 0., 0.), Vec3::new(1., 0., 0.)), 0.001, f32::MAX).unwrap();
+        assert_close(rec.normal, Vec3::new(-1., 0., 0.));
+        assert!(!rec.is_front_face);
+    }
 
-            normal.x = self.cos_theta * record.normal[0] + self.sin_theta * record.normal[2];
-            normal.z = - self.sin_theta * record.normal[0] + self.cos_theta * record.normal[2];
+    #[test]
+    fn test_transformed_bounding_box() {
+        let bbox = ellipsoid().bounding_box(0., 1.).unwrap();
+        assert_close(bbox.min, Vec3::new(-1., -2., -1.));
+        assert_close(bbox.max, Vec3::new(1., 2., 1.));
 
-            record.p = p;
-            let is_front_face = rotated_r.direction.dot(record.normal) < 0.;
-            record.is_front_face = is_front_face;
-            record.normal = if is_front_face { normal } else { -normal };
+        let material: Arc<dyn Material> = Arc::new(Lambertian::new_from_color(Color(Vec3::new(0.5, 0.5, 0.5))));
+        let sphere = Sphere { center: Point3::new(1., 0., 0.), radius: 1., material };
+        let matrix = Matrix4::translation(Vec3::new(0., 3., 0.)) * Matrix4::rotation(Vec3::new(0., 1., 0.), 90.);
+        let bbox = Transformed::new(Arc::new(sphere), Transform::new(matrix).unwrap()).bounding_box(0., 1.).unwrap();
+        assert_close(bbox.min, Vec3::new(-1., 2., -2.));
+        assert_close(bbox.max, Vec3::new(1., 4., 0.));
+    }
 
-            Some(record)
-        } else {
-            None
+    #[test]
+    fn test_shared_instances() {
+        let white: Arc<dyn Material> = Arc::new(Lambertian::new_from_color(Color(Vec3::new(0.7, 0.7, 0.7))));
+        let red: Arc<dyn Material> = Arc::new(Lambertian::new_from_color(Color(Vec3::new(0.7, 0.1, 0.1))));
+        let sphere: Arc<dyn Hittable> = Arc::new(Sphere { center: Point3::new(0., 0., 0.), radius: 1., material: white.clone() });
+
+        let left = Transformed::new(sphere.clone(), Transform::new(Matrix4::translation(Vec3::new(-2., 0., 0.))).unwrap());
+        let right = Transformed::new(sphere.clone(), Transform::new(Matrix4::translation(Vec3::new(2., 0., 0.))).unwrap())
+            .with_material(red.clone());
+        assert_eq!(3, Arc::strong_count(&sphere));
+
+        let down = Vec3::new(0., -1., 0.);
+        let rec = left.hit(&Ray::new_without_move(Point3::new(-2., 5., 0.), down), 0.001, f32::MAX).unwrap();
+        assert!(Arc::ptr_eq(&rec.material, &white));
+        let rec = right.hit(&Ray::new_without_move(Point3::new(2., 5., 0.), down), 0.001, f32::MAX).unwrap();
+        assert!(Arc::ptr_eq(&rec.material, &red));
+        assert!(right.hit(&Ray::new_without_move(Point3::new(-2., 5., 0.), down), 0.001, f32::MAX).is_none());
+    }
+
+    // the density over directions integrates to one, and the points sampled are found again with it
+    fn assert_light_pdf(object: &dyn Hittable, origin: Point3) {
+        let mut rng = Sampler::seed_from_u64(3);
+
+        let n = 200000;
+        let total: f32 = (0..n)
+            .map(|_| object.pdf_value(&Ray::new_without_move(origin, random_unit_vector(&mut rng)), 0.001, f32::MAX))
+            .sum();
+        let integral = 4. * std::f32::consts::PI * total / n as f32;
+        assert!((integral - 1.).abs() < 0.05, "pdf integrates to {}", integral);
+
+        for _ in 0..100 {
+            let r = Ray::new_without_move(origin, object.random(&origin, &mut rng));
+            assert!(object.hit(&r, 0.999, 1.001).is_some());
+            assert!(object.pdf_value(&r, 0.001, f32::MAX) > 0.);
         }
     }
+
+    #[test]
+    fn test_light_pdfs() {
+        let material: Arc<dyn Material> = Arc::new(Lambertian::new_from_color(Color(Vec3::new(0.5, 0.5, 0.5))));
+        let origin = Point3::new(0.3, -0.2, 0.1);
+
+        let rect = XZRect { x0: -1., x1: 2., z0: -1., z1: 1., k: 1.5, material: material.clone() };
+        assert_light_pdf(&rect, origin);
+        assert_light_pdf(&Sphere { center: Point3::new(1., 2., -1.), radius: 1.5, material: material.clone() }, origin);
+        assert_light_pdf(&Triangle::new(Point3::new(-1., 1., 1.), Point3::new(2., 1., 0.), Point3::new(0., 2., -1.),
+                                        material.clone()), origin);
+        assert_light_pdf(&Box::new(Point3::new(1., 1., 1.), Point3::new(2., 3., 2.), material.clone()), origin);
+        assert_light_pdf(&Transformed::new(Arc::new(rect), Transform::new(Matrix4::translation(Vec3::new(0., 1., 0.))
+            * Matrix4::rotation(Vec3::new(1., 0., 1.), 30.) * Matrix4::scaling(Vec3::new(0.5, 1., 2.))).unwrap()), origin);
+        assert_light_pdf(&ellipsoid(), Point3::new(3., 1., -2.));
+    }
 }
@@ -1,14 +1,20 @@
-use crate::hittable::{HitRecord, Hittable};
+use std::sync::Arc;
+
+use rand::Rng;
+
+use crate::hittable::{HitRecord, Hittable, emitters};
 use crate::ray::Ray;
+use crate::sampler::Sampler;
+use crate::vec3::{Point3, Vec3};
 use crate::aabb::AABB;
 use crate::aabb::surrounding_box;
 
 pub struct HittableList {
-    pub objects: Vec<Box<dyn Hittable>>,
+    pub objects: Vec<Arc<dyn Hittable>>,
 }
 
 impl HittableList {
-    pub fn add(&mut self, object: Box<dyn Hittable>) {
+    pub fn add(&mut self, object: Arc<dyn Hittable>) {
         self.objects.push(object);
     }
 }
@@ -25,7 +31,7 @@ impl Hittable for HittableList {
             }
         }
 
-        return temp_rec;
+        temp_rec
     }
 
     fn bounding_box(&self, t0: f32, t1: f32) -> Option<AABB> {
@@ -51,4 +57,34 @@ impl Hittable for HittableList {
             output_box
         }
     }
+
+    fn transmittance(&self, r: &Ray, t_min: f32, t_max: f32) -> f32 {
+        let mut transmittance = 1.;
+        for object in &self.objects {
+            transmittance *= object.transmittance(r, t_min, t_max);
+            if transmittance == 0. {
+                break;
+            }
+        }
+        transmittance
+    }
+
+    // the objects are picked equally often
+    fn pdf_value(&self, r: &Ray, t_min: f32, t_max: f32) -> f32 {
+        if self.objects.is_empty() {
+            return 0.;
+        }
+        self.objects.iter().map(|object| object.pdf_value(r, t_min, t_max)).sum::<f32>() / self.objects.len() as f32
+    }
+
+    fn random(&self, origin: &Point3, rng: &mut Sampler) -> Vec3 {
+        if self.objects.is_empty() {
+            return Vec3::new(1., 0., 0.);
+        }
+        self.objects[rng.gen_range(0, self.objects.len())].random(origin, rng)
+    }
+
+    fn emitting_parts(&self) -> Vec<Arc<dyn Hittable>> {
+        self.objects.iter().flat_map(emitters).collect()
+    }
 }
@@ -1,85 +1,64 @@
 use crate::perlin::Perlin;
 use crate::material::Dielectric;
 use crate::color::Color;
-use std::io::{self, Write};
-use std::rc::Rc;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::process;
+use std::sync::Arc;
 
 mod aabb;
-// mod bvh;
+mod bvh;
+mod cli;
+mod film;
+mod flat_bvh;
+mod gltf_import;
 mod camera;
 mod color;
+mod constant_medium;
+mod heterogeneous_medium;
 mod hittable;
 mod hittable_list;
 mod ray;
 mod sphere;
 mod vec3;
+mod volume;
+mod volume_file;
 mod material;
+mod matrix;
+mod microfacet;
+mod obj;
 mod texture;
+mod tonemap;
+mod triangle;
 mod perlin;
+mod ply;
+mod principled;
 mod aarect;
 mod r#box;
+mod render;
+mod sampler;
+mod scene;
 
 use crate::hittable_list::HittableList;
+use cli::Args;
+use bvh::BvhBuilder;
 use camera::Camera;
-use hittable::{Hittable, Translate, RotateY};
-use sphere::{Sphere, MovingSphere, random_in_hemisphere};
+use film::{ExrPrecision, Film, OutputFormat};
+use hittable::{Hittable, Transformed};
+use matrix::{Matrix4, Transform};
+use sphere::{Sphere, MovingSphere};
 use vec3::Vec3;
 use ray::Ray;
 use texture::{CheckerTexture, NoiseTexture, ImageTexture};
-use material::{Lambertian, Metal, DiffuseLight};
-use rand::Rng;
+use material::{Lambertian, Metal, DiffuseLight, Isotropic, Material};
+use constant_medium::ConstantMedium;
+use flat_bvh::{FlatBvh, SplitMethod};
+use rand::{Rng, SeedableRng};
 use aarect::{XYRect, YZRect, XZRect};
-
-fn ray_color(r: &ray::Ray, background: Color,  w: &HittableList, depth: i32) -> Color {
-    if depth <= 0 {
-        return color::Color(Vec3::new(0., 0., 0.));
-    }
-
-
-    if let Some(rec) = w.hit(&r, 0.001, f32::MAX) {
-        let emitted = rec.material.emitted(rec.u, rec.v, &rec.p);
-
-        if let Some((scattered, attenuation)) = rec.material.scatter(&r, &rec) {
-            Color(emitted.0 + attenuation.0 * ray_color(&scattered, background, w, depth - 1).0)
-        } else {
-            emitted
-        }
-    } else {
-        background
-    }
-}
-
-fn clamp(x: f32, min: f32, max: f32) -> f32 {
-    if x < min {
-        return min;
-    }
-    if x > max {
-        return max;
-    }
-    return x;
-}
-
-fn write_color(out: &std::io::Stdout, pixel_color: Color, samples_per_pixel: i32) {
-    let mut handle = out.lock();
-
-    let mut r = pixel_color.0.x;
-    let mut g = pixel_color.0.y;
-    let mut b = pixel_color.0.z;
-
-    let scale = 1.0 / samples_per_pixel as f32;
-    r = (r * scale).sqrt();
-    g = (g * scale).sqrt();
-    b = (b * scale).sqrt();
-
-    write!(
-        handle,
-        "{} {} {}\n",
-        (256. * clamp(r, 0.0, 0.999)) as u8,
-        (256. * clamp(g, 0.0, 0.999)) as u8,
-        (256. * clamp(b, 0.0, 0.999)) as u8,
-    )
-    .unwrap();
-}
+use render::{render, RenderSettings};
+use sampler::Sampler;
+use scene::Scene;
+use tonemap::ToneMapper;
 
 fn two_spheres() -> HittableList {
     let mut spheres = HittableList{
@@ -87,14 +66,14 @@ fn two_spheres() -> HittableList {
     };
 
     let checker_texture = CheckerTexture::new(Color(Vec3::new(0.2, 0.3, 0.1)), Color(Vec3::new(0.9, 0.9, 0.9)));
-    let checker_material = Rc::new(Lambertian::new_from_texture(Box::new(checker_texture)));
+    let checker_material = Arc::new(Lambertian::new_from_texture(Box::new(checker_texture)));
 
-    spheres.add(Box::new(Sphere {
+    spheres.add(Arc::new(Sphere {
         center: Vec3::new(0., -10., 0.),
         radius: 10.,
         material: checker_material.clone(),
     }));
-    spheres.add(Box::new(Sphere {
+    spheres.add(Arc::new(Sphere {
         center: Vec3::new(0., 10., 0.),
         radius: 10.,
         material: checker_material.clone(),
@@ -103,20 +82,20 @@ fn two_spheres() -> HittableList {
     spheres
 }
 
-fn two_perlin_spheres() -> HittableList {
+fn two_perlin_spheres(rng: &mut Sampler) -> HittableList {
     let mut spheres = HittableList{
         objects: Vec::new(),
     };
 
-    let texture = NoiseTexture{ noise: Perlin::new(), scale: 4., };
-    let material = Rc::new(Lambertian::new_from_texture(Box::new(texture)));
+    let texture = NoiseTexture{ noise: Perlin::new(rng), scale: 4., };
+    let material = Arc::new(Lambertian::new_from_texture(Box::new(texture)));
 
-    spheres.add(Box::new(Sphere {
+    spheres.add(Arc::new(Sphere {
         center: Vec3::new(0., -1000., 0.),
         radius: 1000.,
         material: material.clone(),
     }));
-    spheres.add(Box::new(Sphere {
+    spheres.add(Arc::new(Sphere {
         center: Vec3::new(0., 2., 0.),
         radius: 2.,
         material: material.clone(),
@@ -127,7 +106,7 @@ fn two_perlin_spheres() -> HittableList {
 
 fn earth() -> HittableList {
     let earth_texture = ImageTexture::new("./img/earthmap.jpg");
-    let earth_surface = Rc::new(Lambertian::new_from_texture(Box::new(earth_texture)));
+    let earth_surface = Arc::new(Lambertian::new_from_texture(Box::new(earth_texture)));
     let globe = Sphere {
         center: Vec3::new(0., 0., 0.),
         radius: 2.,
@@ -135,93 +114,227 @@ fn earth() -> HittableList {
     };
 
     HittableList {
-        objects: vec![Box::new(globe)],
+        objects: vec![Arc::new(globe)],
     }
 }
 
-fn simple_light() -> HittableList {
+// the world and the lights in it
+fn simple_light(rng: &mut Sampler) -> (HittableList, HittableList) {
     let mut objects = HittableList {
         objects: Vec::new(),
     };
 
-    let texture = NoiseTexture{ noise: Perlin::new(), scale: 4., };
-    let material = Rc::new(Lambertian::new_from_texture(Box::new(texture)));
+    let texture = NoiseTexture{ noise: Perlin::new(rng), scale: 4., };
+    let material = Arc::new(Lambertian::new_from_texture(Box::new(texture)));
 
-    objects.add(Box::new(Sphere {
+    objects.add(Arc::new(Sphere {
         center: Vec3::new(0., -1000., 0.),
         radius: 1000.,
         material: material.clone(),
     }));
-    objects.add(Box::new(Sphere {
+    objects.add(Arc::new(Sphere {
         center: Vec3::new(0., 2., 0.),
         radius: 2.,
         material: material.clone(),
     }));
-    objects.add(Box::new(XYRect {
+    let light: Arc<dyn Hittable> = Arc::new(XYRect {
         x0: 3., x1: 5., y0: 1., y1: 3., k: -2.,
-        material: Rc::new(DiffuseLight::new(Color(Vec3::new(4., 4., 4.)))),
+        material: Arc::new(DiffuseLight::new(Color(Vec3::new(4., 4., 4.)))),
+    });
+    objects.add(light.clone());
+
+    (objects, HittableList { objects: vec![light] })
+}
+
+fn cornell_box() -> (HittableList, HittableList) {
+    let mut objects = HittableList {
+        objects: Vec::new(),
+    };
+
+    let red = Arc::new(Lambertian::new_from_color(Color(Vec3::new(0.65, 0.05, 0.05))));
+    let white = Arc::new(Lambertian::new_from_color(Color(Vec3::new(0.73, 0.73, 0.73))));
+    let green = Arc::new(Lambertian::new_from_color(Color(Vec3::new(0.12, 0.45, 0.15))));
+    let light = Arc::new(DiffuseLight::new(Color(Vec3::new(15., 15., 15.))));
+
+    objects.add(Arc::new(YZRect {
+        y0: 0., y1: 555., z0: 0., z1: 555., k: 555., material: green.clone(),
+    }));
+    objects.add(Arc::new(YZRect {
+        y0: 0., y1: 555., z0: 0., z1: 555., k: 0., material: red.clone(),
+    }));
+    let light: Arc<dyn Hittable> = Arc::new(XZRect {
+        x0: 213., x1: 343., z0: 227., z1: 332., k: 554., material: light,
+    });
+    objects.add(light.clone());
+    objects.add(Arc::new(XZRect {
+        x0: 0., x1: 555., z0: 0., z1: 555., k: 0., material: white.clone(),
+    }));
+    objects.add(Arc::new(XZRect {
+        x0: 0., x1: 555., z0: 0., z1: 555., k: 555., material: white.clone(),
+    }));
+    objects.add(Arc::new(XYRect {
+        x0: 0., x1: 555., y0: 0., y1: 555., k: 555., material: white.clone(),
     }));
 
+    // both boxes are instances of the same cube, the tall one stretched to twice its height
+    let cube: Arc<dyn Hittable> = Arc::new(r#box::Box::new(Vec3::new(0., 0., 0.), Vec3::new(165., 165., 165.), white.clone()));
+    let box1 = Transformed::new(cube.clone(), Transform::new(Matrix4::translation(Vec3::new(265., 0., 295.))
+        * (Matrix4::rotation(Vec3::new(0., 1., 0.), 15.) * Matrix4::scaling(Vec3::new(1., 2., 1.)))).unwrap());
+    objects.add(Arc::new(box1));
+    let box2 = Transformed::new(cube, Transform::new(Matrix4::translation(Vec3::new(130., 0., 65.))
+        * Matrix4::rotation(Vec3::new(0., 1., 0.), -18.)).unwrap());
+    objects.add(Arc::new(box2));
 
-    objects
+    (objects, HittableList { objects: vec![light] })
 }
 
-fn cornell_box() -> HittableList {
+fn cornell_smoke() -> (HittableList, HittableList) {
     let mut objects = HittableList {
         objects: Vec::new(),
     };
 
-    let red = Rc::new(Lambertian::new_from_color(Color(Vec3::new(0.65, 0.05, 0.05))));
-    let white = Rc::new(Lambertian::new_from_color(Color(Vec3::new(0.73, 0.73, 0.73))));
-    let green = Rc::new(Lambertian::new_from_color(Color(Vec3::new(0.12, 0.45, 0.15))));
-    let light = Rc::new(DiffuseLight::new(Color(Vec3::new(15., 15., 15.))));
+    let red = Arc::new(Lambertian::new_from_color(Color(Vec3::new(0.65, 0.05, 0.05))));
+    let white = Arc::new(Lambertian::new_from_color(Color(Vec3::new(0.73, 0.73, 0.73))));
+    let green = Arc::new(Lambertian::new_from_color(Color(Vec3::new(0.12, 0.45, 0.15))));
+    let light = Arc::new(DiffuseLight::new(Color(Vec3::new(7., 7., 7.))));
 
-    objects.add(Box::new(YZRect {
+    objects.add(Arc::new(YZRect {
         y0: 0., y1: 555., z0: 0., z1: 555., k: 555., material: green.clone(),
     }));
-    objects.add(Box::new(YZRect {
+    objects.add(Arc::new(YZRect {
         y0: 0., y1: 555., z0: 0., z1: 555., k: 0., material: red.clone(),
     }));
-    objects.add(Box::new(XZRect {
-        x0: 213., x1: 343., z0: 227., z1: 332., k: 554., material: light.clone(),
-    }));
-    objects.add(Box::new(XZRect {
+    let light: Arc<dyn Hittable> = Arc::new(XZRect {
+        x0: 113., x1: 443., z0: 127., z1: 432., k: 554., material: light,
+    });
+    objects.add(light.clone());
+    objects.add(Arc::new(XZRect {
         x0: 0., x1: 555., z0: 0., z1: 555., k: 0., material: white.clone(),
     }));
-    objects.add(Box::new(XZRect {
+    objects.add(Arc::new(XZRect {
         x0: 0., x1: 555., z0: 0., z1: 555., k: 555., material: white.clone(),
     }));
-    objects.add(Box::new(XYRect {
+    objects.add(Arc::new(XYRect {
         x0: 0., x1: 555., y0: 0., y1: 555., k: 555., material: white.clone(),
     }));
 
-    let box1 = Translate {
-        object: Box::new(RotateY::new(Box::new(r#box::Box::new(Vec3::new(0., 0., 0.), Vec3::new(165., 330., 165.), white.clone())), 15.)),
-        offset: Vec3::new(265., 0., 295.)};
-    objects.add(Box::new(box1));
-    let box2 = Translate {
-        object: Box::new(RotateY::new(Box::new(r#box::Box::new(Vec3::new(0., 0., 0.), Vec3::new(165., 165., 165.), white.clone())), -18.)),
-        offset: Vec3::new(130., 0., 65.),};
-    objects.add(Box::new(box2));
+    // the boxes of the Cornell box filled with dark and light smoke
+    let cube: Arc<dyn Hittable> = Arc::new(r#box::Box::new(Vec3::new(0., 0., 0.), Vec3::new(165., 165., 165.), white.clone()));
+    let box1 = Transformed::new(cube.clone(), Transform::new(Matrix4::translation(Vec3::new(265., 0., 295.))
+        * (Matrix4::rotation(Vec3::new(0., 1., 0.), 15.) * Matrix4::scaling(Vec3::new(1., 2., 1.)))).unwrap());
+    let box2 = Transformed::new(cube, Transform::new(Matrix4::translation(Vec3::new(130., 0., 65.))
+        * Matrix4::rotation(Vec3::new(0., 1., 0.), -18.)).unwrap());
+    objects.add(Arc::new(ConstantMedium::new(Box::new(box1), 0.01, Arc::new(Isotropic::new(Color(Vec3::new(0., 0., 0.)))))));
+    objects.add(Arc::new(ConstantMedium::new(Box::new(box2), 0.01, Arc::new(Isotropic::new(Color(Vec3::new(1., 1., 1.)))))));
 
-    objects
+    (objects, HittableList { objects: vec![light] })
 }
 
-fn random_scene() -> HittableList {
+// the cover of The Next Week
+fn final_scene(rng: &mut Sampler) -> (HittableList, HittableList) {
+    let mut objects = HittableList {
+        objects: Vec::new(),
+    };
+
+    let ground = Arc::new(Lambertian::new_from_color(Color(Vec3::new(0.48, 0.83, 0.53))));
+    let boxes_per_side = 20;
+    for i in 0..boxes_per_side {
+        for j in 0..boxes_per_side {
+            let w = 100.0;
+            let x0 = -1000.0 + i as f32 * w;
+            let z0 = -1000.0 + j as f32 * w;
+            let y1 = rng.gen_range(1., 101.);
+            objects.add(Arc::new(r#box::Box::new(Vec3::new(x0, 0., z0), Vec3::new(x0 + w, y1, z0 + w), ground.clone())));
+        }
+    }
+
+    let light: Arc<dyn Hittable> = Arc::new(XZRect {
+        x0: 123., x1: 423., z0: 147., z1: 412., k: 554., material: Arc::new(DiffuseLight::new(Color(Vec3::new(7., 7., 7.)))),
+    });
+    objects.add(light.clone());
+
+    let center1 = Vec3::new(400., 400., 200.);
+    objects.add(Arc::new(MovingSphere {
+        center0: center1,
+        center1: center1 + Vec3::new(30., 0., 0.),
+        time0: 0.,
+        time1: 1.,
+        radius: 50.,
+        material: Arc::new(Lambertian::new_from_color(Color(Vec3::new(0.7, 0.3, 0.1)))),
+    }));
+
+    objects.add(Arc::new(Sphere {
+        center: Vec3::new(260., 150., 45.),
+        radius: 50.,
+        material: Arc::new(Dielectric::new(1.5)),
+    }));
+    objects.add(Arc::new(Sphere {
+        center: Vec3::new(0., 150., 145.),
+        radius: 50.,
+        material: Arc::new(Metal::new(Color(Vec3::new(0.8, 0.8, 0.9)), 1.0)),
+    }));
+
+    // a glass ball filled with blue smoke, and a thin mist over everything
+    let boundary = Sphere {
+        center: Vec3::new(360., 150., 145.),
+        radius: 70.,
+        material: Arc::new(Dielectric::new(1.5)),
+    };
+    objects.add(Arc::new(Sphere { material: boundary.material.clone(), ..boundary }));
+    objects.add(Arc::new(ConstantMedium::new(Box::new(boundary), 0.2, Arc::new(Isotropic::new(Color(Vec3::new(0.2, 0.4, 0.9)))))));
+    let boundary = Sphere {
+        center: Vec3::new(0., 0., 0.),
+        radius: 5000.,
+        material: Arc::new(Dielectric::new(1.5)),
+    };
+    objects.add(Arc::new(ConstantMedium::new(Box::new(boundary), 0.0001, Arc::new(Isotropic::new(Color(Vec3::new(1., 1., 1.)))))));
+
+    let earth_texture = ImageTexture::new("./img/earthmap.jpg");
+    objects.add(Arc::new(Sphere {
+        center: Vec3::new(400., 200., 400.),
+        radius: 100.,
+        material: Arc::new(Lambertian::new_from_texture(Box::new(earth_texture))),
+    }));
+    let texture = NoiseTexture{ noise: Perlin::new(rng), scale: 0.1, };
+    objects.add(Arc::new(Sphere {
+        center: Vec3::new(220., 280., 300.),
+        radius: 80.,
+        material: Arc::new(Lambertian::new_from_texture(Box::new(texture))),
+    }));
+
+    // a cube of small spheres, moved as one under its own BVH
+    let white: Arc<dyn Material> = Arc::new(Lambertian::new_from_color(Color(Vec3::new(0.73, 0.73, 0.73))));
+    let mut spheres = HittableList {
+        objects: Vec::new(),
+    };
+    for _ in 0..1000 {
+        spheres.add(Arc::new(Sphere {
+            center: Vec3::new(rng.gen_range(0., 165.), rng.gen_range(0., 165.), rng.gen_range(0., 165.)),
+            radius: 10.,
+            material: white.clone(),
+        }));
+    }
+    let spheres = FlatBvh::new(&spheres, SplitMethod::Sah, 0., 1.);
+    objects.add(Arc::new(Transformed::new(Arc::new(spheres), Transform::new(Matrix4::translation(Vec3::new(-100., 270., 395.))
+        * Matrix4::rotation(Vec3::new(0., 1., 0.), 15.)).unwrap())));
+
+    (objects, HittableList { objects: vec![light] })
+}
+
+fn random_scene(rng: &mut Sampler) -> HittableList {
     let mut world = HittableList {
         objects: Vec::new(),
     };
 
-    // let material_ground = Rc::new(Lambertian::new_from_color(Color(Vec3::new(0.5, 0.5, 0.5))));
-    let material_checker = Rc::new(Lambertian::new_from_texture(
+    // let material_ground = Arc::new(Lambertian::new_from_color(Color(Vec3::new(0.5, 0.5, 0.5))));
+    let material_checker = Arc::new(Lambertian::new_from_texture(
         Box::new(CheckerTexture::new(Color(Vec3::new(0.2, 0.3, 0.1)), Color(Vec3::new(0.9, 0.9, 0.9))))));
-    world.add(Box::new(Sphere {
+    world.add(Arc::new(Sphere {
         center: Vec3::new(0., -1000., 0.),
         radius: 1000.,
         material: material_checker,
     }));
 
-    let mut rng = rand::thread_rng();
     for a in -11..11 {
         for b in -11..11 {
             let choose_mat = rng.gen::<f32>();
@@ -235,29 +348,29 @@ fn random_scene() -> HittableList {
                     // diffuse
                     let albedo = Color(rng.gen::<Vec3>() * rng.gen::<Vec3>());
                     let center2 = center + Vec3::new(0., rng.gen_range(0., 0.5), 0.);
-                    world.add(Box::new(MovingSphere {
+                    world.add(Arc::new(MovingSphere {
                         center0: center,
                         center1: center2,
                         time0: 0.0,
                         time1: 1.0,
                         radius: 0.2,
-                        material: Rc::new(Lambertian::new_from_color(albedo)),
+                        material: Arc::new(Lambertian::new_from_color(albedo)),
                     }));
                 } else if choose_mat < 0.95 {
                     // metal
                     let albedo = Color(rng.gen::<Vec3>());
                     let fuzz = rng.gen();
-                    world.add(Box::new(Sphere {
-                        center: center,
+                    world.add(Arc::new(Sphere {
+                        center,
                         radius: 0.2,
-                        material: Rc::new(Metal::new(albedo, fuzz)),
+                        material: Arc::new(Metal::new(albedo, fuzz)),
                     }));
                 } else {
                     // glass
-                    world.add(Box::new(Sphere {
-                        center: center,
+                    world.add(Arc::new(Sphere {
+                        center,
                         radius: 0.2,
-                        material: Rc::new(Dielectric{ ref_idx: 1.5 }),
+                        material: Arc::new(Dielectric::new(1.5)),
                     }));
                 }
 
@@ -265,22 +378,22 @@ fn random_scene() -> HittableList {
         }
     }
 
-    let material1 = Rc::new(Dielectric{ ref_idx: 1.5 });
-    world.add(Box::new(Sphere {
+    let material1 = Arc::new(Dielectric::new(1.5));
+    world.add(Arc::new(Sphere {
         center: Vec3::new(0., 1., 0.),
         radius: 1.,
         material: material1,
     }));
 
-    let material2 = Rc::new(Lambertian::new_from_color(Color(Vec3::new(0.4, 0.2, 0.1))));
-    world.add(Box::new(Sphere {
+    let material2 = Arc::new(Lambertian::new_from_color(Color(Vec3::new(0.4, 0.2, 0.1))));
+    world.add(Arc::new(Sphere {
         center: Vec3::new(-4., 1., 0.),
         radius: 1.,
         material: material2,
     }));
 
-    let material3 = Rc::new(Metal::new(Color(Vec3::new(0.7, 0.6, 0.5)), 0.));
-    world.add(Box::new(Sphere {
+    let material3 = Arc::new(Metal::new(Color(Vec3::new(0.7, 0.6, 0.5)), 0.));
+    world.add(Arc::new(Sphere {
         center: Vec3::new(4., 1., 0.),
         radius: 1.,
         material: material3,
@@ -289,63 +402,63 @@ fn random_scene() -> HittableList {
     world
 }
 
-fn main() {
-    let mut rng = rand::thread_rng();
+const BUILTIN_SCENES: [&str; 8] = ["random_scene", "two_spheres", "two_perlin_spheres", "earth", "simple_light", "cornell_box",
+                                   "cornell_smoke", "final_scene"];
 
-    // Image
+fn builtin_scene(name: &str, bvh: BvhBuilder, rng: &mut Sampler) -> Option<Scene> {
     let mut aspect_ratio: f32 = 16.0 / 9.0;
     let mut image_width: usize = 400;
     let mut image_height: usize = (image_width as f32 / aspect_ratio) as usize;
     let mut samples_per_pixel = 100;
-    let max_depth = 50;
 
     let world: HittableList;
+    let mut lights = HittableList { objects: Vec::new() };
     let look_from;
     let look_at;
     let vfov;
     let mut aperture = 0.0;
-    let mut background = Color(Vec3::new(0., 0., 0.));
+    let background;
 
-    match 0 {
-        1 => {
-            world = random_scene();
+    match name {
+        "random_scene" => {
+            world = random_scene(rng);
             background = Color(Vec3::new(0.7, 0.8, 1.));
             look_from = Vec3::new(13., 2., 3.);
             look_at = Vec3::new(0., 0., 0.);
             vfov = 20.0;
             aperture = 0.1;
         },
-        2 => {
+        "two_spheres" => {
             world = two_spheres();
             background = Color(Vec3::new(0.7, 0.8, 1.));
             look_from = Vec3::new(13., 2., 3.);
             look_at = Vec3::new(0., 0., 0.);
             vfov = 20.0;
         },
-        3 => {
-            world = two_perlin_spheres();
+        "two_perlin_spheres" => {
+            world = two_perlin_spheres(rng);
             background = Color(Vec3::new(0.7, 0.8, 1.));
             look_from = Vec3::new(13., 2., 3.);
             look_at = Vec3::new(0., 0., 0.);
             vfov = 20.0;
         },
-        4 => {
+        "earth" => {
             world = earth();
             background = Color(Vec3::new(0.7, 0.8, 1.));
             look_from = Vec3::new(13., 2., 3.);
             look_at = Vec3::new(0., 0., 0.);
             vfov = 20.;
         },
-        5 => {
-            world = simple_light();
+        "simple_light" => {
+            (world, lights) = simple_light(rng);
             samples_per_pixel = 400;
             background = Color(Vec3::new(0., 0., 0.));
             look_from = Vec3::new(26., 3., 6.);
             look_at = Vec3::new(0., 2., 0.);
             vfov = 20.;
         },
-        _ => {
-            world = cornell_box();
+        "cornell_box" => {
+            (world, lights) = cornell_box();
             aspect_ratio = 1.;
             image_width = 600;
             image_height = (image_width as f32 / aspect_ratio) as usize;
@@ -354,37 +467,142 @@ fn main() {
             look_from = Vec3::new(278., 278., -800.);
             look_at = Vec3::new(278., 278., 0.);
             vfov = 40.;
-        }
+        },
+        "cornell_smoke" => {
+            (world, lights) = cornell_smoke();
+            aspect_ratio = 1.;
+            image_width = 600;
+            image_height = (image_width as f32 / aspect_ratio) as usize;
+            samples_per_pixel = 200;
+            background = Color(Vec3::new(0., 0., 0.));
+            look_from = Vec3::new(278., 278., -800.);
+            look_at = Vec3::new(278., 278., 0.);
+            vfov = 40.;
+        },
+        "final_scene" => {
+            (world, lights) = final_scene(rng);
+            aspect_ratio = 1.;
+            image_width = 800;
+            image_height = (image_width as f32 / aspect_ratio) as usize;
+            samples_per_pixel = 1000;
+            background = Color(Vec3::new(0., 0., 0.));
+            look_from = Vec3::new(478., 278., -600.);
+            look_at = Vec3::new(278., 278., 0.);
+            vfov = 40.;
+        },
+        _ => return None,
     }
 
     // Camera
     let vup = Vec3::new(0., 1., 0.);
     let dist_to_focus = 10.0;
     let cam = Camera::new(look_from, look_at, vup, vfov, aspect_ratio, aperture, dist_to_focus, 0., 1.);
+    let (world, bvh_stats) = bvh.build(&world, 0., 1.);
+
+    Some(Scene {
+        world,
+        bvh_stats,
+        lights,
+        camera: cam,
+        background,
+        image_width,
+        image_height,
+        samples_per_pixel,
+    })
+}
 
-    let stdout = io::stdout();
-    let mut handle = stdout.lock();
+fn load_scene(name: &str, bvh: BvhBuilder, rng: &mut Sampler) -> Result<Scene, String> {
+    if let Some(scene) = builtin_scene(name, bvh, rng) {
+        return Ok(scene);
+    }
 
-    let stderr = io::stderr();
-    let mut err_handle = stderr.lock();
+    if !Path::new(name).exists() {
+        return Err(format!("unknown scene `{}`, expected a scene file or one of: {}", name, BUILTIN_SCENES.join(", ")));
+    }
+    match Path::new(name).extension().and_then(|e| e.to_str()).map(|e| e.to_lowercase()).as_deref() {
+        Some("gltf") | Some("glb") => scene::load_gltf(name, bvh),
+        _ => scene::load(name, bvh, rng),
+    }
+    .map_err(|e| e.to_string())
+}
 
-    write!(handle, "P3\n{} {}\n255\n", image_width, image_height).unwrap();
+// `None` writes PPM to stdout
+fn output_format(path: &Path, exr_precision: ExrPrecision) -> Result<Option<OutputFormat>, String> {
+    if path == Path::new("-") {
+        return Ok(None);
+    }
 
-    for j in (0..image_height).rev() {
-        write!(err_handle, "\rScanlines remaining: {} ", j).unwrap();
-        err_handle.flush().unwrap();
-        for i in 0..image_width {
-            let mut v_pixel_color = Vec3::new(0., 0., 0.);
-            for _s in 0..samples_per_pixel {
-                let u = ((i as f32) + rng.gen::<f32>()) / ((image_width - 1) as f32);
-                let v = ((j as f32) + rng.gen::<f32>()) / (image_height - 1) as f32;
-                let r = cam.get_ray(u, v);
-                v_pixel_color += ray_color(&r, background, &world, max_depth).0;
-            }
+    match OutputFormat::from_path(path) {
+        Some(OutputFormat::Exr(_)) => Ok(Some(OutputFormat::Exr(exr_precision))),
+        Some(format) => Ok(Some(format)),
+        None => Err(format!("{}: unsupported output format, expected one of: .{}",
+                            path.display(), OutputFormat::EXTENSIONS.join(", ."))),
+    }
+}
 
-            write_color(&stdout, Color(v_pixel_color), samples_per_pixel);
-        }
+fn write_output(film: &Film, path: &Path, format: Option<OutputFormat>, tone_mapper: &ToneMapper) -> Result<(), String> {
+    match format {
+        Some(format) => film.write(path, format, tone_mapper),
+        None => {
+            let mut out = BufWriter::new(io::stdout().lock());
+            film.write_ppm(&mut out, tone_mapper).and_then(|_| Ok(out.flush()?))
+        },
     }
+    .map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+fn fail(message: String) -> ! {
+    eprintln!("error: {}", message);
+    process::exit(1);
+}
+
+fn main() {
+    let args = Args::parse_with_scenes(&BUILTIN_SCENES);
+    let mut rng = Sampler::seed_from_u64(args.seed);
+
+    let scene = load_scene(&args.scene, args.bvh, &mut rng).unwrap_or_else(|e| fail(e));
+    let format = output_format(&args.output, args.exr_precision).unwrap_or_else(|e| fail(e));
+
+    let Scene { world, bvh_stats, lights, camera: mut cam, background, mut image_width, mut image_height, samples_per_pixel } = scene;
+    let aspect_ratio = image_width as f32 / image_height as f32;
+    match (args.width, args.height) {
+        (Some(width), Some(height)) => {
+            image_width = width;
+            image_height = height;
+            cam = cam.with_aspect_ratio(width as f32 / height as f32);
+        },
+        (Some(width), None) => {
+            image_width = width;
+            image_height = ((width as f32 / aspect_ratio) as usize).max(2);
+        },
+        (None, Some(height)) => {
+            image_width = ((height as f32 * aspect_ratio) as usize).max(2);
+            image_height = height;
+        },
+        (None, None) => {},
+    }
+    let samples_per_pixel = args.spp.unwrap_or(samples_per_pixel);
+
+    eprintln!("BVH ({:?}): {}", args.bvh, bvh_stats);
+
+    let settings = RenderSettings {
+        image_width,
+        image_height,
+        samples_per_pixel,
+        max_depth: args.depth,
+        background,
+        threads: args.threads,
+        seed: args.seed,
+        surfaces: matches!(format, Some(OutputFormat::Exr(_))),
+    };
+    let film = render(world.as_ref(), &lights, &cam, &settings);
+
+    let tone_mapper = ToneMapper {
+        operator: args.tonemap,
+        exposure: args.exposure,
+        white_point: args.white_point,
+    };
+    write_output(&film, &args.output, format, &tone_mapper).unwrap_or_else(|e| fail(e));
 
-    write!(err_handle, "\nDone.\n").unwrap();
+    eprint!("\nDone.\n");
 }
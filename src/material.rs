@@ -1,17 +1,53 @@
 use crate::texture::{Texture, SolidColor};
-use crate::sphere::random_in_unit_sphere;
 use crate::vec3::{Point3, Vec3};
 use crate::sphere::random_unit_vector;
-use crate::ray::Ray;
 use crate::hittable::HitRecord;
 use crate::color::Color;
+use crate::sampler::Sampler;
+use crate::microfacet::{Frame, Ggx, fresnel_conductor, fresnel_dielectric, fresnel_thin_film};
 
-pub trait Material {
+use rand::Rng;
+
+pub trait Material: Send + Sync {
     fn emitted(&self, _u: f32, _v: f32, _p: &Point3) -> Color {
         Color(Vec3::new(0., 0., 0.))
     }
 
-    fn scatter(&self, spot: &Ray, rec: &HitRecord) -> Option<(Ray, Color)>;
+    // whether `emitted` can be anything but black, making the objects of the material lights
+    fn emits(&self) -> bool {
+        false
+    }
+
+    // directions are unit vectors pointing away from rec.p: `wo` back along the incoming ray, towards
+    // where the light goes, and `wi` towards where it comes from
+
+    // the fraction of the light arriving along wi that leaves along wo, times the cosine of wi to the
+    // normal for surfaces
+    fn eval(&self, _rec: &HitRecord, _wi: Vec3, _wo: Vec3) -> Color {
+        Color(Vec3::new(0., 0., 0.))
+    }
+
+    // a direction wi to follow, the weight `eval / pdf` of the light coming from it and its density,
+    // or None when the light is absorbed; for delta materials the density is the probability of the
+    // lobe picked, and `eval` and `pdf` are zero since no other direction would ever find it
+    fn sample(&self, rec: &HitRecord, wo: Vec3, rng: &mut Sampler) -> Option<(Vec3, Color, f32)>;
+
+    // the density with which `sample` picks wi over the sphere of directions
+    fn pdf(&self, _rec: &HitRecord, _wi: Vec3, _wo: Vec3) -> f32 {
+        0.
+    }
+
+    // whether the material only scatters into a few directions, mirrors and glass, which light
+    // sampling cannot find
+    fn is_delta(&self) -> bool {
+        false
+    }
+
+    // the fraction of the light left after travelling `distance` through the material up to rec, when
+    // rec is on the inside of its boundary
+    fn interior_transmittance(&self, _rec: &HitRecord, _distance: f32) -> Color {
+        Color(Vec3::new(1., 1., 1.))
+    }
 }
 
 pub struct Lambertian {
@@ -30,87 +66,397 @@ impl Lambertian {
 
 impl Material for Lambertian {
 
-    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Ray, Color)> {
-        let scatter_direction = rec.normal + random_unit_vector();
-        let scattered = Ray::new(rec.p, scatter_direction, r_in.time);
-        let attenuation = self.albedo.value(rec.u, rec.v, &rec.p);
-        Some((scattered, attenuation))
+    fn eval(&self, rec: &HitRecord, wi: Vec3, wo: Vec3) -> Color {
+        Color(self.albedo.value_at(rec).0 * self.pdf(rec, wi, wo))
+    }
+
+    // cosine weighted around the normal, the weight is then the albedo
+    fn sample(&self, rec: &HitRecord, _wo: Vec3, rng: &mut Sampler) -> Option<(Vec3, Color, f32)> {
+        let direction = rec.normal + random_unit_vector(rng);
+        // the unit vector may have landed right against the normal
+        let wi = if direction.length_squared() > 1e-8 { direction.normalize() } else { rec.normal };
+        Some((wi, self.albedo.value_at(rec), rec.normal.dot(wi).max(0.) / std::f32::consts::PI))
+    }
+
+    fn pdf(&self, rec: &HitRecord, wi: Vec3, _wo: Vec3) -> f32 {
+        rec.normal.dot(wi).max(0.) / std::f32::consts::PI
     }
 }
 
-fn reflect(v: &Vec3, n: &Vec3) -> Vec3 {
+pub fn reflect(v: &Vec3, n: &Vec3) -> Vec3 {
     (*v) - 2. * (*v).dot(*n) * (*n)
 }
 
+// how much of the light a metal reflects at each angle
+pub enum Reflectance {
+    // Schlick's approximation from the color at normal incidence
+    Schlick(Color),
+    // the complex index of refraction eta + ik of each channel, as measured for real metals
+    Conductor { eta: Color, k: Color },
+}
+
+impl Reflectance {
+    fn at(&self, cos_theta: f32) -> Color {
+        match self {
+            Reflectance::Schlick(color) => {
+                let f = (1. - cos_theta.clamp(0., 1.)).powi(5);
+                Color(color.0 + f * (Vec3::new(1., 1., 1.) - color.0))
+            },
+            Reflectance::Conductor { eta, k } => {
+                let channel = |i: usize| fresnel_conductor(cos_theta, eta.0[i], k.0[i]);
+                Color(Vec3::new(channel(0), channel(1), channel(2)))
+            },
+        }
+    }
+}
+
+// the complex indices of refraction of metals at the red, green and blue wavelengths
+pub fn conductor_ior(name: &str) -> Option<(Color, Color)> {
+    let (eta, k) = match name {
+        "gold" => ([0.143, 0.374, 1.442], [3.983, 2.386, 1.603]),
+        "copper" => ([0.200, 0.924, 1.102], [3.912, 2.452, 2.142]),
+        "aluminium" => ([1.657, 0.880, 0.521], [9.224, 6.270, 4.837]),
+        "silver" => ([0.155, 0.117, 0.138], [4.828, 3.122, 2.147]),
+        _ => return None,
+    };
+    let color = |c: [f32; 3]| Color(Vec3::new(c[0], c[1], c[2]));
+    Some((color(eta), color(k)))
+}
+
+// the bands of wavelengths in nanometers a thin film is averaged over for the red, green and blue channels
+const CHANNEL_BANDS: [(f32, f32); 3] = [(580., 700.), (490., 580.), (400., 490.)];
+const BAND_SAMPLES: usize = 8;
+
+// a thin transparent layer over a surface, soap, oil or a lens coating, whose reflections interfere
+// into colors changing with its thickness and the angle it is seen at
+pub struct ThinFilm {
+    pub ior: f32,
+    // in nanometers
+    pub thickness: f32,
+    // scales the thickness by its red channel, for films thinning and thickening over the surface
+    pub thickness_map: Option<Box<dyn Texture>>,
+}
+
+impl ThinFilm {
+    pub fn new(ior: f32, thickness: f32) -> Self {
+        ThinFilm { ior, thickness, thickness_map: None }
+    }
+
+    fn thickness_at(&self, rec: &HitRecord) -> f32 {
+        match &self.thickness_map {
+            Some(map) => self.thickness * map.value_at(rec).0.x.max(0.),
+            None => self.thickness,
+        }
+    }
+
+    // the reflectance of `channel` through a film of that thickness over a substrate of complex index
+    // eta + ik, lit from a medium of index `outside`
+    fn reflectance(&self, thickness: f32, cos_theta_i: f32, outside: f32, eta: f32, k: f32, channel: usize) -> f32 {
+        let (shortest, longest) = CHANNEL_BANDS[channel];
+        let sum: f32 = (0..BAND_SAMPLES)
+            .map(|i| shortest + (longest - shortest) * (i as f32 + 0.5) / BAND_SAMPLES as f32)
+            .map(|wavelength| fresnel_thin_film(cos_theta_i, outside, self.ior, eta, k, thickness, wavelength))
+            .sum();
+        sum / BAND_SAMPLES as f32
+    }
+}
+
+// a rough conductor of GGX microfacets reflecting once, a mirror when smooth
 pub struct Metal {
-    pub albedo: Color,
-    pub fuzzy: f32,
+    pub reflectance: Reflectance,
+    pub distribution: Ggx,
+    pub film: Option<ThinFilm>,
 }
 
 impl Metal {
+    // a tinted metal, the fuzz of older scenes read as its roughness
     pub fn new(albedo: Color, fuzzy: f32) -> Self {
         Metal {
-            albedo: albedo,
-            fuzzy: fuzzy.min(1.0),
+            reflectance: Reflectance::Schlick(albedo),
+            distribution: Ggx::isotropic(fuzzy.min(1.0)),
+            film: None,
         }
     }
+
+    pub fn conductor(eta: Color, k: Color, distribution: Ggx) -> Self {
+        Metal {
+            reflectance: Reflectance::Conductor { eta, k },
+            distribution,
+            film: None,
+        }
+    }
+
+    // directions in the frame of the microfacets, anisotropy following the u of textured surfaces
+    fn frame(&self, rec: &HitRecord) -> Frame {
+        Frame::new(rec.normal, rec.tangents.map(|(dpdu, _)| dpdu))
+    }
+
+    // the reflectance at the cosine to the (micro)normal, through the film when coated
+    fn fresnel(&self, rec: &HitRecord, cos_theta: f32) -> Color {
+        let film = match &self.film {
+            Some(film) => film,
+            None => return self.reflectance.at(cos_theta),
+        };
+        let thickness = film.thickness_at(rec);
+        let channel = |i: usize| {
+            let (eta, k) = match &self.reflectance {
+                Reflectance::Conductor { eta, k } => (eta.0[i], k.0[i]),
+                // the real index reflecting as much at normal incidence
+                Reflectance::Schlick(color) => {
+                    let r = color.0[i].clamp(0., 0.99).sqrt();
+                    ((1. + r) / (1. - r), 0.)
+                },
+            };
+            film.reflectance(thickness, cos_theta, 1., eta, k, i)
+        };
+        Color(Vec3::new(channel(0), channel(1), channel(2)))
+    }
 }
 
 impl Material for Metal {
 
-    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Ray, Color)> {
-        let reflected = reflect(&r_in.direction.normalize(), &rec.normal);
-        let scattered = Ray::new_without_move(rec.p, reflected + self.fuzzy * random_in_unit_sphere());
-        if scattered.direction.dot(rec.normal) > 0. {
-            Some((scattered, self.albedo))
-        } else {
-            None
+    fn eval(&self, rec: &HitRecord, wi: Vec3, wo: Vec3) -> Color {
+        let frame = self.frame(rec);
+        let (wi, wo) = (frame.to_local(wi), frame.to_local(wo));
+        if self.distribution.is_smooth() || wi.z <= 0. || wo.z <= 0. {
+            return Color(Vec3::new(0., 0., 0.));
+        }
+
+        let wm = (wi + wo).normalize();
+        let ggx = &self.distribution;
+        Color(self.fresnel(rec, wo.dot(wm)).0 * (ggx.d(wm) * ggx.g(wo, wi) / (4. * wo.z)))
+    }
+
+    // the normals seen from wo, which leaves the masking of wi and the Fresnel term as the weight
+    fn sample(&self, rec: &HitRecord, wo: Vec3, rng: &mut Sampler) -> Option<(Vec3, Color, f32)> {
+        if self.distribution.is_smooth() {
+            let wi = reflect(&-wo, &rec.normal);
+            return Some((wi, self.fresnel(rec, wo.dot(rec.normal)), 1.));
+        }
+
+        let frame = self.frame(rec);
+        let wo_local = frame.to_local(wo);
+        if wo_local.z <= 0. {
+            return None;
+        }
+        let wm = self.distribution.sample_visible(wo_local, rng.gen(), rng.gen());
+        let wi_local = reflect(&-wo_local, &wm);
+        if wi_local.z <= 0. {
+            return None;
         }
+
+        let ggx = &self.distribution;
+        let weight = self.fresnel(rec, wo_local.dot(wm)).0 * (ggx.g(wo_local, wi_local) / ggx.g1(wo_local));
+        let wi = frame.to_world(wi_local);
+        Some((wi, Color(weight), self.pdf(rec, wi, wo)))
+    }
+
+    fn pdf(&self, rec: &HitRecord, wi: Vec3, wo: Vec3) -> f32 {
+        let frame = self.frame(rec);
+        let (wi, wo) = (frame.to_local(wi), frame.to_local(wo));
+        if self.distribution.is_smooth() || wi.z <= 0. || wo.z <= 0. {
+            return 0.;
+        }
+
+        let wm = (wi + wo).normalize();
+        self.distribution.d_visible(wo, wm) / (4. * wo.dot(wm))
+    }
+
+    fn is_delta(&self) -> bool {
+        self.distribution.is_smooth()
     }
 }
 
-fn refract(uv: Vec3, n: Vec3, etai_over_etat: f32) -> Vec3 {
+pub fn refract(uv: Vec3, n: Vec3, etai_over_etat: f32) -> Vec3 {
     let cos_theta = (-uv).dot(n);
     let r_out_perp = etai_over_etat * (uv + cos_theta * n);
     let r_out_parallel = -((1.0 - r_out_perp.length_squared()).abs().sqrt() * n);
     r_out_perp + r_out_parallel
 }
 
-fn schlick(cosine: f32, ref_idx: f32) -> f32 {
-    let r0 = (1. - ref_idx) / (1. + ref_idx);
-    let r0 = r0 * r0;
+// the wavelengths in micrometers at which the red, green and blue channels see a dispersive index
+const CHANNEL_WAVELENGTHS: [f32; 3] = [0.630, 0.532, 0.465];
+
+// an index of refraction varying with the wavelength in micrometers
+#[derive(Clone, Copy, Debug)]
+pub enum Dispersion {
+    // n = a + b / λ²
+    Cauchy { a: f32, b: f32 },
+    // n² = 1 + Σ b λ² / (λ² - c)
+    Sellmeier { b: [f32; 3], c: [f32; 3] },
+}
 
-    r0 + (1. - r0) * (1. - cosine).powi(5)
+impl Dispersion {
+    pub fn ior(&self, wavelength: f32) -> f32 {
+        let l2 = wavelength * wavelength;
+        match self {
+            Dispersion::Cauchy { a, b } => a + b / l2,
+            Dispersion::Sellmeier { b, c } => (1. + (0..3).map(|i| b[i] * l2 / (l2 - c[i])).sum::<f32>()).sqrt(),
+        }
+    }
 }
 
+// the Sellmeier coefficients of common optical glasses, from their Schott and Malitson datasheets
+pub fn glass_dispersion(name: &str) -> Option<Dispersion> {
+    let (b, c) = match name {
+        "bk7" => ([1.03961, 0.231792, 1.01047], [0.00600070, 0.0200179, 103.561]),
+        "fused_silica" => ([0.696166, 0.407943, 0.897479], [0.00467915, 0.0135121, 97.9340]),
+        "sf11" => ([1.73760, 0.313747, 1.89878], [0.0131887, 0.0623068, 155.236]),
+        _ => return None,
+    };
+    Some(Dispersion::Sellmeier { b, c })
+}
+
+// glass, water and other clear materials, reflecting or refracting through GGX microfacets when
+// rough (Walter et al. 2007) and as a mirror and a window when smooth; light travelling inside
+// is absorbed by the Beer-Lambert law, and splits into colors when the index is dispersive
 pub struct Dielectric {
     pub ref_idx: f32,
+    pub distribution: Ggx,
+    // per unit of length, for the red, green and blue channels
+    pub absorption: Color,
+    // replaces `ref_idx` with one index for the wavelength of every channel
+    pub dispersion: Option<Dispersion>,
+    // coats the outside of the surface, a soap bubble being a film over a dielectric of index 1
+    pub film: Option<ThinFilm>,
+}
+
+impl Dielectric {
+    pub fn new(ref_idx: f32) -> Self {
+        Self::rough(ref_idx, Ggx::isotropic(0.))
+    }
+
+    pub fn rough(ref_idx: f32, distribution: Ggx) -> Self {
+        Dielectric { ref_idx, distribution, absorption: Color(Vec3::new(0., 0., 0.)), dispersion: None, film: None }
+    }
+
+    // `ref_idx` is then the index of the yellow helium line glasses are usually listed by
+    pub fn dispersive(dispersion: Dispersion, distribution: Ggx) -> Self {
+        Dielectric { dispersion: Some(dispersion), ..Self::rough(dispersion.ior(0.5876), distribution) }
+    }
+
+    fn iors(&self) -> [f32; 3] {
+        match &self.dispersion {
+            Some(dispersion) => CHANNEL_WAVELENGTHS.map(|wavelength| dispersion.ior(wavelength)),
+            None => [self.ref_idx; 3],
+        }
+    }
+
+    // the indices of refraction of the inside over the ones of the side the normal faces, which is
+    // the side the ray comes from, outside when it hits the front face; one for every channel
+    fn etas(&self, rec: &HitRecord) -> [f32; 3] {
+        self.iors().map(|ior| if rec.is_front_face { ior } else { 1. / ior })
+    }
+
+    // the reflectance of `channel` at the cosine to the (micro)normal, through the film of that
+    // thickness when coated
+    fn fresnel(&self, rec: &HitRecord, thickness: f32, channel: usize, cos_theta: f32) -> f32 {
+        let ior = self.iors()[channel];
+        match &self.film {
+            Some(film) if rec.is_front_face => film.reflectance(thickness, cos_theta, 1., ior, 0., channel),
+            Some(film) => film.reflectance(thickness, cos_theta, ior, 1., 0., channel),
+            None => fresnel_dielectric(cos_theta, self.etas(rec)[channel]),
+        }
+    }
+
+    // the reflectance sampling follows: the one of the channel a dispersive glass picked, or the mean
+    // of the channels a film colors
+    fn sampled_fresnel(&self, rec: &HitRecord, thickness: f32, channel: Option<usize>, cos_theta: f32) -> f32 {
+        match (channel, &self.film) {
+            (Some(channel), _) => self.fresnel(rec, thickness, channel, cos_theta),
+            (None, Some(_)) => (0..3).map(|i| self.fresnel(rec, thickness, i, cos_theta)).sum::<f32>() / 3.,
+            (None, None) => self.fresnel(rec, thickness, 0, cos_theta),
+        }
+    }
+
+    fn thickness(&self, rec: &HitRecord) -> f32 {
+        self.film.as_ref().map_or(0., |film| film.thickness_at(rec))
+    }
 }
 
 impl Material for Dielectric {
 
-    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> std::option::Option<(Ray, Color)> {
-        let attenuation = Color(Vec3::new(1., 1., 1.));
-        let etai_over_etat = if rec.is_front_face { 1.0 / self.ref_idx } else { self.ref_idx };
+    fn eval(&self, rec: &HitRecord, wi: Vec3, wo: Vec3) -> Color {
+        if self.distribution.is_smooth() {
+            return Color(Vec3::new(0., 0., 0.));
+        }
+        let frame = Frame::new(rec.normal, rec.tangents.map(|(dpdu, _)| dpdu));
+        let (wi, wo) = (frame.to_local(wi), frame.to_local(wo));
+        let (etas, thickness) = (self.etas(rec), self.thickness(rec));
+        let channel = |i: usize| self.distribution.dielectric_eval(wi, wo, etas[i], |cos| self.fresnel(rec, thickness, i, cos));
+        Color(Vec3::new(channel(0), channel(1), channel(2)))
+    }
 
-        let unit_direction = r_in.direction.normalize();
+    // reflects with the probability given by the Fresnel term, refracts otherwise; a dispersive glass
+    // first picks the channel whose wavelength refracts, the other channels going elsewhere
+    fn sample(&self, rec: &HitRecord, wo: Vec3, rng: &mut Sampler) -> Option<(Vec3, Color, f32)> {
+        let (etas, thickness) = (self.etas(rec), self.thickness(rec));
+        let channel = if self.dispersion.is_some() { Some(rng.gen_range(0, 3)) } else { None };
+        let eta = etas[channel.unwrap_or(0)];
+        if self.distribution.is_smooth() {
+            let cos_theta = wo.dot(rec.normal);
+            let reflectance = self.sampled_fresnel(rec, thickness, channel, cos_theta);
+            let [r, g, b] = [0, 1, 2].map(|i| self.fresnel(rec, thickness, i, cos_theta));
+            if rng.gen::<f32>() < reflectance {
+                // every channel reflects the same way, each by its own Fresnel term
+                let chosen = if channel.is_some() { (r + g + b) / 3. } else { reflectance };
+                return Some((reflect(&-wo, &rec.normal), Color(Vec3::new(r, g, b) / chosen), chosen));
+            }
 
-        let cos_theta = (-unit_direction).dot(rec.normal).min(1.0);
-        let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
-        if etai_over_etat * sin_theta > 1.0 {
-            let reflected = reflect(&unit_direction, &rec.normal);
-            let scattered = Ray::new_without_move(rec.p, reflected);
-            Some((scattered, attenuation))
-        } else if rand::random::<f32>() < schlick(cos_theta, etai_over_etat) {
-            let reflected = reflect(&unit_direction, &rec.normal);
-            let scattered = Ray::new_without_move(rec.p, reflected);
-            Some((scattered, attenuation))
-        } else {
-            let refracted = refract(unit_direction, rec.normal, etai_over_etat);
-            let scattered = Ray::new_without_move(rec.p, refracted);
-            Some((scattered, attenuation))
+            // radiance is scaled by the square of the ratio of the indices of refraction when crossing over
+            let wi = refract(-wo, rec.normal, 1. / eta).normalize();
+            return match channel {
+                None => {
+                    let transmitted = Vec3::new(1. - r, 1. - g, 1. - b) / (1. - reflectance);
+                    Some((wi, Color(transmitted / (eta * eta)), 1. - reflectance))
+                },
+                Some(channel) => {
+                    let mut weight = [0.; 3];
+                    weight[channel] = 3. / (eta * eta);
+                    Some((wi, Color(Vec3::new(weight[0], weight[1], weight[2])), (1. - reflectance) / 3.))
+                },
+            };
         }
 
+        let frame = Frame::new(rec.normal, rec.tangents.map(|(dpdu, _)| dpdu));
+        let wo_local = frame.to_local(wo);
+        if wo_local.z <= 0. {
+            return None;
+        }
+        let fresnel = |cos| self.sampled_fresnel(rec, thickness, channel, cos);
+        let wi = frame.to_world(self.distribution.dielectric_sample(wo_local, eta, fresnel, [rng.gen(), rng.gen(), rng.gen()])?);
+        let pdf = self.pdf(rec, wi, wo);
+        if pdf <= 0. {
+            return None;
+        }
+        Some((wi, Color(self.eval(rec, wi, wo).0 / pdf), pdf))
+    }
+
+    // averaged over the channels `sample` picks from
+    fn pdf(&self, rec: &HitRecord, wi: Vec3, wo: Vec3) -> f32 {
+        if self.distribution.is_smooth() {
+            return 0.;
+        }
+        let frame = Frame::new(rec.normal, rec.tangents.map(|(dpdu, _)| dpdu));
+        let (wi, wo) = (frame.to_local(wi), frame.to_local(wo));
+        let (etas, thickness) = (self.etas(rec), self.thickness(rec));
+        if self.dispersion.is_none() {
+            return self.distribution.dielectric_pdf(wi, wo, etas[0], |cos| self.sampled_fresnel(rec, thickness, None, cos));
+        }
+        let channel = |i: usize| self.distribution.dielectric_pdf(wi, wo, etas[i], |cos| self.sampled_fresnel(rec, thickness, Some(i), cos));
+        (channel(0) + channel(1) + channel(2)) / 3.
+    }
+
+    fn is_delta(&self) -> bool {
+        self.distribution.is_smooth()
+    }
+
+    fn interior_transmittance(&self, rec: &HitRecord, distance: f32) -> Color {
+        if rec.is_front_face {
+            return Color(Vec3::new(1., 1., 1.));
+        }
+        let a = self.absorption.0;
+        Color(Vec3::new((-a.x * distance).exp(), (-a.y * distance).exp(), (-a.z * distance).exp()))
     }
 }
 
@@ -124,6 +470,12 @@ impl DiffuseLight {
             emit: Box::new(SolidColor::new_from_color(c)),
         }
     }
+
+    pub fn new_from_texture(texture: Box<dyn Texture>) -> Self {
+        Self {
+            emit: texture,
+        }
+    }
 }
 
 impl Material for DiffuseLight {
@@ -132,8 +484,377 @@ impl Material for DiffuseLight {
         self.emit.value(u, v, p)
     }
 
-    fn scatter(&self, _: &Ray, _: &HitRecord) -> Option<(Ray, Color)> {
+    fn emits(&self) -> bool {
+        !self.emit.is_black()
+    }
+
+    fn sample(&self, _: &HitRecord, _: Vec3, _: &mut Sampler) -> Option<(Vec3, Color, f32)> {
         None
     }
 
 }
+
+// the phase function of volumes scattering equally in every direction
+pub struct Isotropic {
+    pub albedo: Box<dyn Texture>,
+}
+
+impl Isotropic {
+    pub fn new(c: Color) -> Self {
+        Self {
+            albedo: Box::new(SolidColor::new_from_color(c)),
+        }
+    }
+
+    pub fn new_from_texture(texture: Box<dyn Texture>) -> Self {
+        Self {
+            albedo: texture,
+        }
+    }
+}
+
+impl Material for Isotropic {
+
+    fn eval(&self, rec: &HitRecord, _wi: Vec3, _wo: Vec3) -> Color {
+        Color(self.albedo.value_at(rec).0 / (4. * std::f32::consts::PI))
+    }
+
+    fn sample(&self, rec: &HitRecord, _wo: Vec3, rng: &mut Sampler) -> Option<(Vec3, Color, f32)> {
+        Some((random_unit_vector(rng), self.albedo.value_at(rec), 1. / (4. * std::f32::consts::PI)))
+    }
+
+    fn pdf(&self, _rec: &HitRecord, _wi: Vec3, _wo: Vec3) -> f32 {
+        1. / (4. * std::f32::consts::PI)
+    }
+}
+
+// two unit vectors completing n into an orthonormal basis (Duff et al. 2017)
+pub fn orthonormal_basis(n: Vec3) -> (Vec3, Vec3) {
+    let sign = 1f32.copysign(n.z);
+    let a = -1. / (sign + n.z);
+    let b = n.x * n.y * a;
+    (Vec3::new(1. + sign * n.x * n.x * a, sign * b, -sign * n.x), Vec3::new(b, sign + n.y * n.y * a, -n.y))
+}
+
+// the Henyey-Greenstein phase function, scattering forward for g > 0 and backward for g < 0
+pub struct HenyeyGreenstein {
+    pub albedo: Color,
+    // the mean cosine of the scattering angle, in (-1, 1)
+    pub g: f32,
+}
+
+impl HenyeyGreenstein {
+    // the cosine between the incoming and scattered directions
+    fn sample_cos_theta(&self, xi: f32) -> f32 {
+        let g = self.g;
+        if g.abs() < 1e-3 {
+            return 1. - 2. * xi;
+        }
+
+        let s = (1. - g * g) / (1. - g + 2. * g * xi);
+        ((1. + g * g - s * s) / (2. * g)).clamp(-1., 1.)
+    }
+}
+
+impl Material for HenyeyGreenstein {
+
+    // sampled exactly, the weight is the albedo
+    fn eval(&self, rec: &HitRecord, wi: Vec3, wo: Vec3) -> Color {
+        Color(self.albedo.0 * self.pdf(rec, wi, wo))
+    }
+
+    fn sample(&self, rec: &HitRecord, wo: Vec3, rng: &mut Sampler) -> Option<(Vec3, Color, f32)> {
+        let forward = -wo;
+        let cos_theta = self.sample_cos_theta(rng.gen());
+        let sin_theta = (1. - cos_theta * cos_theta).max(0.).sqrt();
+        let phi = rng.gen_range(0., 2. * std::f32::consts::PI);
+
+        let (t, b) = orthonormal_basis(forward);
+        let wi = sin_theta * phi.cos() * t + sin_theta * phi.sin() * b + cos_theta * forward;
+        Some((wi, self.albedo, self.pdf(rec, wi, wo)))
+    }
+
+    fn pdf(&self, _rec: &HitRecord, wi: Vec3, wo: Vec3) -> f32 {
+        let g = self.g;
+        let cos_theta = -wo.dot(wi);
+        let denominator = 1. + g * g - 2. * g * cos_theta;
+        (1. - g * g) / (4. * std::f32::consts::PI * denominator * denominator.sqrt())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use rand::SeedableRng;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_orthonormal_basis() {
+        for n in &[Vec3::new(0., 0., 1.), Vec3::new(0., 0., -1.), Vec3::new(1., 2., -3.).normalize()] {
+            let (t, b) = orthonormal_basis(*n);
+            assert!(t.dot(*n).abs() < 1e-6 && b.dot(*n).abs() < 1e-6 && t.dot(b).abs() < 1e-6);
+            assert!((t.length() - 1.).abs() < 1e-6 && (b.length() - 1.).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn test_henyey_greenstein_mean_cosine() {
+        let mut rng = Sampler::seed_from_u64(0);
+        let material: Arc<dyn Material> = Arc::new(Lambertian::new_from_color(Color(Vec3::new(1., 1., 1.))));
+        let rec = HitRecord::new(1., Point3::new(0., 0., 0.), 0., 0., true, Vec3::new(0., 1., 0.), &material);
+        let forward = Vec3::new(1., 1., 0.).normalize();
+
+        for &g in &[-0.7, 0., 0.3, 0.9] {
+            let phase = HenyeyGreenstein { albedo: Color(Vec3::new(1., 1., 1.)), g };
+            let n = 100000;
+            let mean = (0..n)
+                .map(|_| phase.sample(&rec, -forward, &mut rng).unwrap().0.dot(forward))
+                .sum::<f32>() / n as f32;
+            assert!((mean - g).abs() < 0.01, "g = {}: mean cosine {}", g, mean);
+        }
+    }
+
+    #[test]
+    fn test_sample_agrees_with_eval_and_pdf() {
+        let mut rng = Sampler::seed_from_u64(1);
+        let white = Color(Vec3::new(0.8, 0.6, 0.4));
+        let material: Arc<dyn Material> = Arc::new(Lambertian::new_from_color(white));
+        let rec = HitRecord::new(1., Point3::new(0., 0., 0.), 0., 0., true, Vec3::new(0., 1., 0.), &material);
+        let wo = Vec3::new(1., 2., -0.5).normalize();
+
+        let materials: Vec<Box<dyn Material>> = vec![
+            Box::new(Lambertian::new_from_color(white)),
+            Box::new(Isotropic::new(white)),
+            Box::new(HenyeyGreenstein { albedo: white, g: 0.6 }),
+        ];
+        for material in &materials {
+            for _ in 0..1000 {
+                let (wi, weight, pdf) = material.sample(&rec, wo, &mut rng).unwrap();
+                assert!((wi.length() - 1.).abs() < 1e-4);
+                assert!((pdf - material.pdf(&rec, wi, wo)).abs() < 1e-3 * pdf);
+                assert!((weight.0 * pdf - material.eval(&rec, wi, wo).0).length() < 1e-3 * pdf);
+            }
+
+            // the density integrates to one over the sphere of directions
+            let n = 200000;
+            let total: f32 = (0..n).map(|_| material.pdf(&rec, random_unit_vector(&mut rng), wo)).sum();
+            let integral = 4. * std::f32::consts::PI * total / n as f32;
+            assert!((integral - 1.).abs() < 0.02, "pdf integrates to {}", integral);
+        }
+    }
+
+    #[test]
+    fn test_delta_materials() {
+        let mut rng = Sampler::seed_from_u64(2);
+        let material: Arc<dyn Material> = Arc::new(Metal::new(Color(Vec3::new(0.9, 0.9, 0.9)), 0.));
+        let rec = HitRecord::new(1., Point3::new(0., 0., 0.), 0., 0., true, Vec3::new(0., 1., 0.), &material);
+        let wo = Vec3::new(1., 1., 0.).normalize();
+
+        assert!(material.is_delta());
+        let (wi, _, pdf) = material.sample(&rec, wo, &mut rng).unwrap();
+        assert!((wi - Vec3::new(-1., 1., 0.).normalize()).length() < 1e-6);
+        assert_eq!(pdf, 1.);
+        assert_eq!(material.eval(&rec, wi, wo).0, Vec3::new(0., 0., 0.));
+        assert_eq!(material.pdf(&rec, wi, wo), 0.);
+
+        let glass = Dielectric::new(1.5);
+        assert!(glass.is_delta());
+        for _ in 0..100 {
+            let (wi, weight, pdf) = glass.sample(&rec, wo, &mut rng).unwrap();
+            let reflectance = fresnel_dielectric(wo.dot(rec.normal), 1.5);
+            assert_eq!(pdf, if wi.y > 0. { reflectance } else { 1. - reflectance });
+            assert_eq!(weight.0.x, if wi.y > 0. { 1. } else { 1. / (1.5 * 1.5) });
+        }
+        assert!(!Lambertian::new_from_color(Color(Vec3::new(0.5, 0.5, 0.5))).is_delta());
+    }
+
+    #[test]
+    fn test_rough_metal() {
+        let mut rng = Sampler::seed_from_u64(3);
+        let material: Arc<dyn Material> = Arc::new(Lambertian::new_from_color(Color(Vec3::new(0.5, 0.5, 0.5))));
+        let rec = HitRecord::new(1., Point3::new(0., 0., 0.), 0., 0., true, Vec3::new(0., 1., 0.), &material);
+        let wo = Vec3::new(1., 2., -0.5).normalize();
+
+        let (eta, k) = conductor_ior("gold").unwrap();
+        let gold = Metal::conductor(eta, k, Ggx::new(0.3, 0.6));
+        assert!(!gold.is_delta());
+        for _ in 0..1000 {
+            if let Some((wi, weight, pdf)) = gold.sample(&rec, wo, &mut rng) {
+                assert!(wi.dot(rec.normal) > 0.);
+                assert!((pdf - gold.pdf(&rec, wi, wo)).abs() < 1e-3 * pdf);
+                assert!((weight.0 * pdf - gold.eval(&rec, wi, wo).0).length() < 1e-3 * pdf);
+            }
+        }
+
+        // only the reflections going below the surface are lost
+        let n = 200000;
+        let total: f64 = (0..n).map(|_| gold.pdf(&rec, random_unit_vector(&mut rng), wo) as f64).sum();
+        let integral = 4. * std::f64::consts::PI * total / n as f64;
+        assert!(integral > 0.9 && integral < 1.02, "pdf integrates to {}", integral);
+
+        // a white metal reflects nearly everything it does not send below the surface
+        let white = Metal::new(Color(Vec3::new(1., 1., 1.)), 0.3);
+        let reflected: f64 = (0..n).map(|_| white.sample(&rec, wo, &mut rng).map_or(0., |(_, weight, _)| weight.0.x as f64)).sum();
+        let albedo = reflected / n as f64;
+        assert!(albedo > 0.95 && albedo <= 1., "albedo {}", albedo);
+    }
+
+    #[test]
+    fn test_rough_dielectric() {
+        let mut rng = Sampler::seed_from_u64(4);
+        let material: Arc<dyn Material> = Arc::new(Lambertian::new_from_color(Color(Vec3::new(0.5, 0.5, 0.5))));
+        let wo = Vec3::new(1., 2., -0.5).normalize();
+        let glass = Dielectric::rough(1.5, Ggx::isotropic(0.4));
+        assert!(!glass.is_delta());
+        assert!(Dielectric::rough(1.5, Ggx::isotropic(0.02)).is_delta());
+
+        // entering the glass and leaving it, well within the critical angle
+        for &is_front_face in &[true, false] {
+            let outward = Vec3::new(0., if is_front_face { 1. } else { -1. }, 0.);
+            let rec = HitRecord::new(1., Point3::new(0., 0., 0.), 0., 0., is_front_face, outward, &material);
+            let eta = if is_front_face { 1.5 } else { 1. / 1.5 };
+
+            let n = 200000;
+            let mut transmitted = 0;
+            let mut energy = 0f64;
+            for _ in 0..n {
+                if let Some((wi, weight, pdf)) = glass.sample(&rec, wo, &mut rng) {
+                    assert!((pdf - glass.pdf(&rec, wi, wo)).abs() < 1e-3 * pdf);
+                    assert!((weight.0 * pdf - glass.eval(&rec, wi, wo).0).length() < 1e-3 * pdf);
+                    // undo the scaling of radiance by the change of medium
+                    let scale = if wi.y < 0. { transmitted += 1; eta * eta } else { 1. };
+                    energy += (weight.0.x * scale) as f64;
+                }
+            }
+            assert!(transmitted > n / 2);
+            let energy = energy / n as f64;
+            assert!(energy > 0.9 && energy < 1.01, "front face {}: energy {}", is_front_face, energy);
+
+            // over both the reflected and the transmitted directions
+            let total: f64 = (0..10 * n).map(|_| glass.pdf(&rec, random_unit_vector(&mut rng), wo) as f64).sum();
+            let integral = 4. * std::f64::consts::PI * total / (10 * n) as f64;
+            assert!(integral > 0.9 && integral < 1.02, "front face {}: pdf integrates to {}", is_front_face, integral);
+        }
+    }
+
+    #[test]
+    fn test_dispersion() {
+        // the catalogue index of BK7 at the helium d line
+        let bk7 = Dielectric::dispersive(glass_dispersion("bk7").unwrap(), Ggx::isotropic(0.));
+        assert!((bk7.ref_idx - 1.5168).abs() < 1e-4, "{}", bk7.ref_idx);
+
+        for dispersion in &[glass_dispersion("sf11").unwrap(), Dispersion::Cauchy { a: 1.5, b: 0.01 }] {
+            let [r, g, b] = CHANNEL_WAVELENGTHS.map(|wavelength| dispersion.ior(wavelength));
+            assert!(r < g && g < b, "{} {} {}", r, g, b);
+        }
+        assert!(glass_dispersion("crown").is_none());
+    }
+
+    #[test]
+    fn test_dispersive_glass() {
+        let mut rng = Sampler::seed_from_u64(5);
+        let material: Arc<dyn Material> = Arc::new(Lambertian::new_from_color(Color(Vec3::new(0.5, 0.5, 0.5))));
+        let rec = HitRecord::new(1., Point3::new(0., 0., 0.), 0., 0., true, Vec3::new(0., 1., 0.), &material);
+        let wo = Vec3::new(1., 1., 0.).normalize();
+
+        // every channel is reflected and refracted as by a glass of its own index, the refracted
+        // directions fanning out
+        let sf11 = Dielectric::dispersive(glass_dispersion("sf11").unwrap(), Ggx::isotropic(0.));
+        let etas = sf11.etas(&rec);
+        let n = 100000;
+        let mut energy = [0f64; 3];
+        let mut refracted = [None; 3];
+        for _ in 0..n {
+            let (wi, weight, _) = sf11.sample(&rec, wo, &mut rng).unwrap();
+            for (channel, energy) in energy.iter_mut().enumerate() {
+                *energy += weight.0[channel] as f64;
+                if wi.y < 0. && weight.0[channel] > 0. {
+                    refracted[channel] = Some(wi);
+                }
+            }
+        }
+        for channel in 0..3 {
+            let eta = etas[channel];
+            let reflectance = fresnel_dielectric(wo.dot(rec.normal), eta);
+            let expected = (reflectance + (1. - reflectance) / (eta * eta)) as f64;
+            let energy = energy[channel] / n as f64;
+            assert!((energy - expected).abs() < 0.01 * expected, "channel {}: {} != {}", channel, energy, expected);
+            let wi = refracted[channel].unwrap();
+            assert!((wi - refract(-wo, rec.normal, 1. / eta).normalize()).length() < 1e-5);
+        }
+        // red bends the least towards the normal
+        assert!(refracted[0].unwrap().x < refracted[2].unwrap().x);
+
+        // rough, the density is the one of the channel picked averaged over the three
+        let frosted = Dielectric::dispersive(glass_dispersion("sf11").unwrap(), Ggx::isotropic(0.4));
+        for _ in 0..1000 {
+            if let Some((wi, weight, pdf)) = frosted.sample(&rec, wo, &mut rng) {
+                assert!((pdf - frosted.pdf(&rec, wi, wo)).abs() < 1e-3 * pdf);
+                assert!((weight.0 * pdf - frosted.eval(&rec, wi, wo).0).length() < 1e-3 * pdf);
+            }
+        }
+    }
+
+    #[test]
+    fn test_absorption() {
+        let material: Arc<dyn Material> = Arc::new(Lambertian::new_from_color(Color(Vec3::new(0.5, 0.5, 0.5))));
+        let mut glass = Dielectric::new(1.5);
+        glass.absorption = Color(Vec3::new(0., 0.5, 2.));
+
+        // only the light that reached the inside of the boundary crossed the glass
+        let outside = HitRecord::new(2., Point3::new(0., 0., 0.), 0., 0., true, Vec3::new(0., 1., 0.), &material);
+        assert_eq!(glass.interior_transmittance(&outside, 2.).0, Vec3::new(1., 1., 1.));
+        let inside = HitRecord::new(2., Point3::new(0., 0., 0.), 0., 0., false, Vec3::new(0., 1., 0.), &material);
+        assert_eq!(glass.interior_transmittance(&inside, 2.).0, Vec3::new(1., (-1f32).exp(), (-4f32).exp()));
+    }
+
+    #[test]
+    fn test_thin_film() {
+        let mut rng = Sampler::seed_from_u64(6);
+        let material: Arc<dyn Material> = Arc::new(Lambertian::new_from_color(Color(Vec3::new(0.5, 0.5, 0.5))));
+        let rec = HitRecord::new(1., Point3::new(0., 0., 0.), 0., 0., true, Vec3::new(0., 1., 0.), &material);
+        let wo = Vec3::new(1., 2., 0.).normalize();
+
+        // a film of no thickness leaves the metal bare
+        let (eta, k) = conductor_ior("copper").unwrap();
+        let mut copper = Metal::conductor(eta, k, Ggx::isotropic(0.));
+        let bare = copper.sample(&rec, wo, &mut rng).unwrap().1;
+        copper.film = Some(ThinFilm::new(1.5, 0.));
+        let coated = copper.sample(&rec, wo, &mut rng).unwrap().1;
+        assert!((bare.0 - coated.0).length() < 1e-4);
+
+        // a soap bubble colors what it reflects, and lets the rest through
+        let mut bubble = Dielectric::new(1.);
+        bubble.film = Some(ThinFilm::new(1.33, 400.));
+        let n = 100000;
+        let mut reflected = [0f64; 3];
+        let mut energy = [0f64; 3];
+        for _ in 0..n {
+            let (wi, weight, _) = bubble.sample(&rec, wo, &mut rng).unwrap();
+            for channel in 0..3 {
+                energy[channel] += weight.0[channel] as f64;
+                if wi.y > 0. {
+                    reflected[channel] += weight.0[channel] as f64;
+                }
+            }
+        }
+        assert!(energy.iter().all(|&e| (e / n as f64 - 1.).abs() < 1e-3), "{:?}", energy);
+        let (min, max) = reflected.iter().fold((f64::MAX, 0f64), |(min, max), &r| (min.min(r), max.max(r)));
+        assert!(max > 1.5 * min, "{:?}", reflected);
+
+        // the thickness follows the map, and rough coated glass samples what it evaluates
+        let mut frosted = Dielectric::rough(1.5, Ggx::isotropic(0.4));
+        frosted.film = Some(ThinFilm {
+            thickness_map: Some(Box::new(SolidColor::new_from_color(Color(Vec3::new(0.5, 0., 0.))))),
+            ..ThinFilm::new(1.33, 600.)
+        });
+        assert_eq!(frosted.thickness(&rec), 300.);
+        for _ in 0..1000 {
+            if let Some((wi, weight, pdf)) = frosted.sample(&rec, wo, &mut rng) {
+                assert!((pdf - frosted.pdf(&rec, wi, wo)).abs() < 1e-3 * pdf);
+                assert!((weight.0 * pdf - frosted.eval(&rec, wi, wo).0).length() < 1e-3 * pdf);
+            }
+        }
+    }
+}
@@ -0,0 +1,218 @@
+use std::ops::Mul;
+
+use crate::vec3::{Point3, Vec3};
+
+// row major 4x4 matrix of an affine transform, the last row is always 0 0 0 1
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Matrix4 {
+    pub m: [[f32; 4]; 4],
+}
+
+impl Matrix4 {
+    pub fn identity() -> Self {
+        Matrix4 { m: [[1., 0., 0., 0.], [0., 1., 0., 0.], [0., 0., 1., 0.], [0., 0., 0., 1.]] }
+    }
+
+    // column major arrays, like glTF and OpenGL store them
+    pub fn from_cols(cols: [[f32; 4]; 4]) -> Self {
+        let mut m = [[0.; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = cols[j][i];
+            }
+        }
+        Matrix4 { m }
+    }
+
+    pub fn translation(offset: Vec3) -> Self {
+        let mut matrix = Self::identity();
+        for i in 0..3 {
+            matrix.m[i][3] = offset[i];
+        }
+        matrix
+    }
+
+    pub fn scaling(factors: Vec3) -> Self {
+        let mut matrix = Self::identity();
+        for i in 0..3 {
+            matrix.m[i][i] = factors[i];
+        }
+        matrix
+    }
+
+    // counterclockwise in degrees when the axis points towards the viewer
+    pub fn rotation(axis: Vec3, angle: f32) -> Self {
+        let Vec3 { x, y, z } = axis.normalize();
+        let (sin, cos) = angle.to_radians().sin_cos();
+        let t = 1. - cos;
+
+        Matrix4 { m: [
+            [t * x * x + cos, t * x * y - sin * z, t * x * z + sin * y, 0.],
+            [t * x * y + sin * z, t * y * y + cos, t * y * z - sin * x, 0.],
+            [t * x * z - sin * y, t * y * z + sin * x, t * z * z + cos, 0.],
+            [0., 0., 0., 1.],
+        ] }
+    }
+
+    pub fn transpose(&self) -> Self {
+        let mut m = [[0.; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = self.m[j][i];
+            }
+        }
+        Matrix4 { m }
+    }
+
+    // of the linear part, negative when the transform mirrors
+    pub fn determinant(&self) -> f32 {
+        let m = &self.m;
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    }
+
+    // `None` for transforms that flatten space
+    pub fn inverse(&self) -> Option<Self> {
+        let det = self.determinant();
+        if det == 0. || !det.is_finite() {
+            return None;
+        }
+
+        // the inverse of the linear part is its adjugate over the determinant
+        let m = &self.m;
+        let cofactor = |i: usize, j: usize| {
+            let (r0, r1) = ((i + 1) % 3, (i + 2) % 3);
+            let (c0, c1) = ((j + 1) % 3, (j + 2) % 3);
+            m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0]
+        };
+        let mut inverse = Self::identity();
+        for i in 0..3 {
+            for j in 0..3 {
+                inverse.m[i][j] = cofactor(j, i) / det;
+            }
+        }
+        let offset = inverse.transform_vector(Vec3::new(m[0][3], m[1][3], m[2][3]));
+        for i in 0..3 {
+            inverse.m[i][3] = -offset[i];
+        }
+        Some(inverse)
+    }
+
+    pub fn transform_point(&self, p: Point3) -> Point3 {
+        self.transform_vector(p) + Vec3::new(self.m[0][3], self.m[1][3], self.m[2][3])
+    }
+
+    pub fn transform_vector(&self, v: Vec3) -> Vec3 {
+        let m = &self.m;
+        Vec3::new(m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z,
+                  m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z,
+                  m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z)
+    }
+}
+
+impl Mul for Matrix4 {
+    type Output = Self;
+
+    fn mul(self, other: Self) -> Self {
+        let mut m = [[0.; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = (0..4).map(|k| self.m[i][k] * other.m[k][j]).sum();
+            }
+        }
+        Matrix4 { m }
+    }
+}
+
+// an invertible matrix kept together with its inverse, mapping object space to world space
+#[derive(Debug, Copy, Clone)]
+pub struct Transform {
+    pub matrix: Matrix4,
+    pub inverse: Matrix4,
+}
+
+impl Transform {
+    pub fn new(matrix: Matrix4) -> Option<Self> {
+        matrix.inverse().map(|inverse| Transform { matrix, inverse })
+    }
+
+    pub fn point(&self, p: Point3) -> Point3 {
+        self.matrix.transform_point(p)
+    }
+
+    pub fn vector(&self, v: Vec3) -> Vec3 {
+        self.matrix.transform_vector(v)
+    }
+
+    // normals stay perpendicular to the surface under the inverse transpose, unnormalized
+    pub fn normal(&self, n: Vec3) -> Vec3 {
+        self.inverse.transpose().transform_vector(n)
+    }
+
+    pub fn inverse_point(&self, p: Point3) -> Point3 {
+        self.inverse.transform_point(p)
+    }
+
+    pub fn inverse_vector(&self, v: Vec3) -> Vec3 {
+        self.inverse.transform_vector(v)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn assert_close(a: Vec3, b: Vec3) {
+        assert!((a - b).length() < 1e-5, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn test_rotation() {
+        // counterclockwise about each axis
+        assert_close(Matrix4::rotation(Vec3::new(1., 0., 0.), 90.).transform_vector(Vec3::new(0., 1., 0.)), Vec3::new(0., 0., 1.));
+        assert_close(Matrix4::rotation(Vec3::new(0., 1., 0.), 90.).transform_vector(Vec3::new(0., 0., 1.)), Vec3::new(1., 0., 0.));
+        assert_close(Matrix4::rotation(Vec3::new(0., 0., 2.), 90.).transform_vector(Vec3::new(1., 0., 0.)), Vec3::new(0., 1., 0.));
+
+        // a third of a turn about the diagonal cycles the axes
+        let diagonal = Matrix4::rotation(Vec3::new(1., 1., 1.), 120.);
+        assert_close(diagonal.transform_vector(Vec3::new(1., 0., 0.)), Vec3::new(0., 1., 0.));
+    }
+
+    #[test]
+    fn test_inverse() {
+        let matrix = Matrix4::translation(Vec3::new(1., 2., 3.))
+            * Matrix4::rotation(Vec3::new(1., 2., 0.), 30.)
+            * Matrix4::scaling(Vec3::new(2., -1., 0.5));
+        let inverse = matrix.inverse().unwrap();
+        let p = Point3::new(0.3, -4., 7.);
+
+        assert_close(inverse.transform_point(matrix.transform_point(p)), p);
+        assert_close(matrix.transform_point(inverse.transform_point(p)), p);
+        assert!(matrix.determinant() < 0.);
+        assert!(Matrix4::scaling(Vec3::new(1., 0., 1.)).inverse().is_none());
+    }
+
+    #[test]
+    fn test_from_cols() {
+        let cols = [[1., 0., 0., 0.], [0., 1., 0., 0.], [0., 0., 1., 0.], [4., 5., 6., 1.]];
+        assert_eq!(Matrix4::from_cols(cols), Matrix4::translation(Vec3::new(4., 5., 6.)));
+    }
+
+    #[test]
+    fn test_transform() {
+        let transform = Transform::new(Matrix4::translation(Vec3::new(0., 0., 5.))
+            * Matrix4::rotation(Vec3::new(0., 0., 1.), 90.)
+            * Matrix4::scaling(Vec3::new(2., 1., 1.))).unwrap();
+
+        assert_close(transform.point(Point3::new(1., 0., 0.)), Point3::new(0., 2., 5.));
+        assert_close(transform.inverse_point(Point3::new(0., 2., 5.)), Point3::new(1., 0., 0.));
+        assert_close(transform.vector(Vec3::new(1., 0., 0.)), Vec3::new(0., 2., 0.));
+
+        // the normal of the plane x + y = 1 stays perpendicular to it after a stretch along x
+        let normal = transform.normal(Vec3::new(1., 1., 0.));
+        let along = transform.vector(Vec3::new(1., -1., 0.));
+        assert!(normal.dot(along).abs() < 1e-5);
+    }
+}
@@ -0,0 +1,416 @@
+use std::ops::{Add, Div, Mul, Sub};
+
+use crate::material::{orthonormal_basis, reflect, refract};
+use crate::vec3::Vec3;
+
+// an orthonormal basis around a shading normal, in which the normal is +z and the tangent +x
+pub struct Frame {
+    pub x: Vec3,
+    pub y: Vec3,
+    pub z: Vec3,
+}
+
+impl Frame {
+    // the tangent is made orthogonal to the normal; without one, or with one along the normal, it runs
+    // around the y axis like the u of spheres, or any way on surfaces facing straight up or down
+    pub fn new(normal: Vec3, tangent: Option<Vec3>) -> Self {
+        let around_y = Vec3::new(-normal.z, 0., normal.x);
+        let x = tangent.map(|t| t - t.dot(normal) * normal)
+            .filter(|t| t.length_squared() > 1e-8)
+            .or_else(|| Some(around_y).filter(|t| t.length_squared() > 1e-8))
+            .map_or_else(|| orthonormal_basis(normal).0, |t| t.normalize());
+        Frame { x, y: normal.cross(x), z: normal }
+    }
+
+    pub fn to_local(&self, v: Vec3) -> Vec3 {
+        Vec3::new(v.dot(self.x), v.dot(self.y), v.dot(self.z))
+    }
+
+    pub fn to_world(&self, v: Vec3) -> Vec3 {
+        v.x * self.x + v.y * self.y + v.z * self.z
+    }
+}
+
+// the GGX or Trowbridge-Reitz distribution of microfacet normals, with the Smith height-correlated
+// masking-shadowing, over the local frame of a surface
+#[derive(Clone, Copy, Debug)]
+pub struct Ggx {
+    // widths along the tangent and the bitangent
+    pub alpha_x: f32,
+    pub alpha_y: f32,
+}
+
+impl Ggx {
+    // from perceptual roughnesses in [0, 1], whose squares are the widths
+    pub fn new(roughness_x: f32, roughness_y: f32) -> Self {
+        let alpha = |roughness: f32| (roughness.clamp(0., 1.) * roughness.clamp(0., 1.)).max(1e-4);
+        Ggx { alpha_x: alpha(roughness_x), alpha_y: alpha(roughness_y) }
+    }
+
+    pub fn isotropic(roughness: f32) -> Self {
+        Self::new(roughness, roughness)
+    }
+
+    // too narrow to sample, such surfaces are mirrors
+    pub fn is_smooth(&self) -> bool {
+        self.alpha_x.max(self.alpha_y) < 1e-3
+    }
+
+    // the density of microfacet normals wm per area of the macro surface
+    pub fn d(&self, wm: Vec3) -> f32 {
+        if wm.z <= 0. {
+            return 0.;
+        }
+        let cos2_theta = wm.z * wm.z;
+        let e = ((wm.x / self.alpha_x).powi(2) + (wm.y / self.alpha_y).powi(2)) / cos2_theta;
+        1. / (std::f32::consts::PI * self.alpha_x * self.alpha_y * cos2_theta * cos2_theta * (1. + e) * (1. + e))
+    }
+
+    fn lambda(&self, w: Vec3) -> f32 {
+        if w.z == 0. {
+            return f32::INFINITY;
+        }
+        let alpha2_tan2_theta = ((self.alpha_x * w.x).powi(2) + (self.alpha_y * w.y).powi(2)) / (w.z * w.z);
+        ((1. + alpha2_tan2_theta).sqrt() - 1.) / 2.
+    }
+
+    // the fraction of microfacets seen from w
+    pub fn g1(&self, w: Vec3) -> f32 {
+        1. / (1. + self.lambda(w))
+    }
+
+    // the fraction seen from both wo and wi
+    pub fn g(&self, wo: Vec3, wi: Vec3) -> f32 {
+        1. / (1. + self.lambda(wo) + self.lambda(wi))
+    }
+
+    // the density of the normals seen from w, which `sample_visible` follows
+    pub fn d_visible(&self, w: Vec3, wm: Vec3) -> f32 {
+        self.g1(w) / w.z.abs() * self.d(wm) * w.dot(wm).max(0.)
+    }
+
+    // a microfacet normal seen from w above the surface, from two uniform numbers (Heitz 2018)
+    pub fn sample_visible(&self, w: Vec3, u1: f32, u2: f32) -> Vec3 {
+        // stretch the view to the one of a hemisphere
+        let vh = Vec3::new(self.alpha_x * w.x, self.alpha_y * w.y, w.z).normalize();
+        let length2 = vh.x * vh.x + vh.y * vh.y;
+        let t1 = if length2 > 0. { Vec3::new(-vh.y, vh.x, 0.) / length2.sqrt() } else { Vec3::new(1., 0., 0.) };
+        let t2 = vh.cross(t1);
+
+        // a point of the projected hemisphere, the part hidden behind it folded onto the visible one
+        let r = u1.sqrt();
+        let phi = 2. * std::f32::consts::PI * u2;
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1. + vh.z);
+        let p2 = (1. - s) * (1. - p1 * p1).max(0.).sqrt() + s * r * phi.sin();
+        let nh = p1 * t1 + p2 * t2 + (1. - p1 * p1 - p2 * p2).max(0.).sqrt() * vh;
+
+        Vec3::new(self.alpha_x * nh.x, self.alpha_y * nh.y, nh.z.max(1e-6)).normalize()
+    }
+
+    // the rest of the surface reflecting and refracting through the microfacets of a dielectric (Walter et
+    // al. 2007), seen from wo above it; eta is the index of refraction below the surface over the one above
+
+    // the normal of the microfacet taking wo to wi, and the ratio of the indices of refraction of the side
+    // of wi over the one of wo; None for microfacets seen from behind
+    fn dielectric_half_vector(wi: Vec3, wo: Vec3, eta: f32) -> Option<(Vec3, f32)> {
+        let etap = if wi.z > 0. { 1. } else { eta };
+        let wm = wo + etap * wi;
+        if wi.z == 0. || wo.z <= 0. || wm.length_squared() == 0. {
+            return None;
+        }
+        let wm = if wm.z < 0. { -wm.normalize() } else { wm.normalize() };
+        if wm.dot(wi) * wi.z < 0. || wm.dot(wo) <= 0. {
+            return None;
+        }
+        Some((wm, etap))
+    }
+
+    // the light scattered from wi to wo times the cosine of wi, radiance being scaled by the square of the
+    // ratio of the indices of refraction when crossing over; `fresnel` is the reflectance of a microfacet
+    // at the cosine of wo to it, fresnel_dielectric for bare interfaces
+    pub fn dielectric_eval(&self, wi: Vec3, wo: Vec3, eta: f32, fresnel: impl Fn(f32) -> f32) -> f32 {
+        let (wm, etap) = match Self::dielectric_half_vector(wi, wo, eta) {
+            Some(half) => half,
+            None => return 0.,
+        };
+
+        let f = fresnel(wo.dot(wm));
+        if wi.z > 0. {
+            self.d(wm) * self.g(wo, wi) * f / (4. * wo.z)
+        } else {
+            let denominator = wi.dot(wm) + wo.dot(wm) / etap;
+            self.d(wm) * self.g(wo, wi) * (1. - f) * (wi.dot(wm) * wo.dot(wm)).abs()
+                / (wo.z * denominator * denominator * etap * etap)
+        }
+    }
+
+    // the density of `dielectric_sample` picking wi
+    pub fn dielectric_pdf(&self, wi: Vec3, wo: Vec3, eta: f32, fresnel: impl Fn(f32) -> f32) -> f32 {
+        let (wm, etap) = match Self::dielectric_half_vector(wi, wo, eta) {
+            Some(half) => half,
+            None => return 0.,
+        };
+
+        let visible = self.d_visible(wo, wm);
+        let f = fresnel(wo.dot(wm));
+        if wi.z > 0. {
+            f * visible / (4. * wo.dot(wm))
+        } else {
+            let denominator = wi.dot(wm) + wo.dot(wm) / etap;
+            (1. - f) * visible * wi.dot(wm).abs() / (denominator * denominator)
+        }
+    }
+
+    // reflects off a visible microfacet with the probability given by the Fresnel term, refracts otherwise
+    pub fn dielectric_sample(&self, wo: Vec3, eta: f32, fresnel: impl Fn(f32) -> f32, u: [f32; 3]) -> Option<Vec3> {
+        let wm = self.sample_visible(wo, u[0], u[1]);
+        let reflected = u[2] < fresnel(wo.dot(wm));
+        let wi = if reflected { reflect(&-wo, &wm) } else { refract(-wo, wm, 1. / eta).normalize() };
+        // past the macro surface the direction would be taken for one of the other lobe
+        if (wi.z > 0.) == reflected { Some(wi) } else { None }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Complex {
+    re: f32,
+    im: f32,
+}
+
+impl Complex {
+    fn real(re: f32) -> Self {
+        Complex { re, im: 0. }
+    }
+
+    fn norm(self) -> f32 {
+        self.re * self.re + self.im * self.im
+    }
+
+    fn exp(self) -> Self {
+        let scale = self.re.exp();
+        Complex { re: scale * self.im.cos(), im: scale * self.im.sin() }
+    }
+
+    // the principal square root
+    fn sqrt(self) -> Self {
+        let n = self.norm().sqrt();
+        if n == 0. {
+            return Complex::real(0.);
+        }
+        let t1 = (0.5 * (n + self.re.abs())).sqrt();
+        let t2 = 0.5 * self.im / t1;
+        if self.re >= 0. {
+            Complex { re: t1, im: t2 }
+        } else {
+            Complex { re: t2.abs(), im: t1.copysign(self.im) }
+        }
+    }
+}
+
+impl Add for Complex {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Complex { re: self.re + other.re, im: self.im + other.im }
+    }
+}
+
+impl Sub for Complex {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        Complex { re: self.re - other.re, im: self.im - other.im }
+    }
+}
+
+impl Mul for Complex {
+    type Output = Self;
+
+    fn mul(self, other: Self) -> Self {
+        Complex { re: self.re * other.re - self.im * other.im, im: self.re * other.im + self.im * other.re }
+    }
+}
+
+impl Div for Complex {
+    type Output = Self;
+
+    fn div(self, other: Self) -> Self {
+        let scale = 1. / other.norm();
+        Complex {
+            re: scale * (self.re * other.re + self.im * other.im),
+            im: scale * (self.im * other.re - self.re * other.im),
+        }
+    }
+}
+
+// the unpolarized reflectance of an interface between dielectrics, eta being the index of refraction of
+// the far side over the one of the side lit; a negative cosine lights it from the far side
+pub fn fresnel_dielectric(cos_theta_i: f32, eta: f32) -> f32 {
+    let (cos_i, eta) = if cos_theta_i < 0. { (-cos_theta_i, 1. / eta) } else { (cos_theta_i, eta) };
+    let cos_i = cos_i.min(1.);
+
+    let sin2_t = (1. - cos_i * cos_i) / (eta * eta);
+    if sin2_t >= 1. {
+        return 1.;
+    }
+    let cos_t = (1. - sin2_t).sqrt();
+
+    let r_parallel = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let r_perpendicular = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    (r_parallel * r_parallel + r_perpendicular * r_perpendicular) / 2.
+}
+
+// the unpolarized reflectance of a conductor of complex index of refraction eta + ik, lit at an angle
+// of cosine cos_theta_i
+pub fn fresnel_conductor(cos_theta_i: f32, eta: f32, k: f32) -> f32 {
+    let cos_i = Complex::real(cos_theta_i.clamp(0., 1.));
+    let eta = Complex { re: eta, im: k };
+
+    let sin2_t = Complex::real(1. - cos_i.re * cos_i.re) / (eta * eta);
+    let cos_t = (Complex::real(1.) - sin2_t).sqrt();
+
+    let r_parallel = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let r_perpendicular = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    (r_parallel.norm() + r_perpendicular.norm()) / 2.
+}
+
+// the unpolarized reflectance of a film of index `film` and `thickness` over a substrate of complex index
+// eta + ik, lit from a medium of index `outside` by light of `wavelength` in the same unit as the
+// thickness: the sum of the waves reflected back and forth inside the film (Airy), which interfere
+pub fn fresnel_thin_film(cos_theta_i: f32, outside: f32, film: f32, eta: f32, k: f32, thickness: f32, wavelength: f32) -> f32 {
+    let cos_1 = Complex::real(cos_theta_i.clamp(0., 1.));
+    let sin2_1 = 1. - cos_1.re * cos_1.re;
+    let (n1, n2, n3) = (Complex::real(outside), Complex::real(film), Complex { re: eta, im: k });
+    // Snell's law in every layer, the cosines turning complex past the critical angle or in a conductor
+    let cosine = |n: Complex| (Complex::real(1.) - Complex::real(outside * outside * sin2_1) / (n * n)).sqrt();
+    let (cos_2, cos_3) = (cosine(n2), cosine(n3));
+
+    // the phase the film adds to every round trip inside it
+    let phase = Complex::real(4. * std::f32::consts::PI * thickness / wavelength) * n2 * cos_2;
+    let delay = (Complex { re: 0., im: 1. } * phase).exp();
+
+    let one = Complex::real(1.);
+    let airy = |r12: Complex, r23: Complex| ((r12 + r23 * delay) / (one + r12 * r23 * delay)).norm();
+    let perpendicular = |ni: Complex, cos_i: Complex, nt: Complex, cos_t: Complex| (ni * cos_i - nt * cos_t) / (ni * cos_i + nt * cos_t);
+    let parallel = |ni: Complex, cos_i: Complex, nt: Complex, cos_t: Complex| (nt * cos_i - ni * cos_t) / (nt * cos_i + ni * cos_t);
+
+    let r_perpendicular = airy(perpendicular(n1, cos_1, n2, cos_2), perpendicular(n2, cos_2, n3, cos_3));
+    let r_parallel = airy(parallel(n1, cos_1, n2, cos_2), parallel(n2, cos_2, n3, cos_3));
+    ((r_perpendicular + r_parallel) / 2.).min(1.)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::sampler::Sampler;
+    use crate::sphere::random_unit_vector;
+    use rand::{Rng, SeedableRng};
+
+    // the projected areas of the microfacets add up to the one of the surface, from above and from w
+    #[test]
+    fn test_distribution_is_normalized() {
+        let mut rng = Sampler::seed_from_u64(0);
+        let ggx = Ggx::new(0.5, 0.8);
+        let w = Vec3::new(0.3, -0.5, 0.6).normalize();
+
+        let n = 400000;
+        let (mut projected, mut visible) = (0f64, 0f64);
+        for _ in 0..n {
+            let wm = random_unit_vector(&mut rng);
+            projected += (ggx.d(wm) * wm.z.max(0.)) as f64;
+            visible += ggx.d_visible(w, wm) as f64;
+        }
+        let sphere = 4. * std::f64::consts::PI / n as f64;
+        assert!((projected * sphere - 1.).abs() < 0.02, "{}", projected * sphere);
+        assert!((visible * sphere - 1.).abs() < 0.02, "{}", visible * sphere);
+    }
+
+    // the normals sampled are distributed like the visible ones, compared by their mean
+    #[test]
+    fn test_sample_visible() {
+        let mut rng = Sampler::seed_from_u64(1);
+        let ggx = Ggx::new(0.7, 0.3);
+        let w = Vec3::new(-0.6, 0.2, 0.5).normalize();
+
+        let n = 400000;
+        let add = |sum: &mut [f64; 3], v: Vec3| (0..3).for_each(|i| sum[i] += v[i] as f64 / n as f64);
+        let mut sampled = [0.; 3];
+        let mut expected = [0.; 3];
+        for _ in 0..n {
+            let wm = ggx.sample_visible(w, rng.gen(), rng.gen());
+            assert!(wm.z > 0. && w.dot(wm) > -1e-4);
+            add(&mut sampled, wm);
+
+            let wm = random_unit_vector(&mut rng);
+            add(&mut expected, 4. * std::f32::consts::PI * ggx.d_visible(w, wm) * wm);
+        }
+        assert!((0..3).all(|i| (sampled[i] - expected[i]).abs() < 0.01), "{:?} != {:?}", sampled, expected);
+    }
+
+    #[test]
+    fn test_fresnel_conductor() {
+        // at normal incidence, ((n - 1)^2 + k^2) / ((n + 1)^2 + k^2)
+        let (eta, k) = (0.2, 3.9);
+        let expected = ((eta - 1.) * (eta - 1.) + k * k) / ((eta + 1.) * (eta + 1.) + k * k);
+        assert!((fresnel_conductor(1., eta, k) - expected).abs() < 1e-5);
+
+        // every metal turns into a mirror at grazing angles
+        assert!((fresnel_conductor(0., eta, k) - 1.).abs() < 1e-5);
+
+        // without absorption the conductor is a dielectric, totally reflecting past the critical angle
+        assert!((fresnel_conductor(1., 1.5, 0.) - 0.04).abs() < 1e-5);
+        assert!((fresnel_conductor(0.5, 1. / 1.5, 0.) - 1.).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_fresnel_dielectric() {
+        assert!((fresnel_dielectric(1., 1.5) - 0.04).abs() < 1e-6);
+        assert!((fresnel_dielectric(0., 1.5) - 1.).abs() < 1e-6);
+        assert_eq!(fresnel_dielectric(0.5, 1. / 1.5), 1.);
+
+        // the same from either side of the interface, and the same as the conductor of no absorption
+        let cos_i: f32 = 0.7;
+        let cos_t = (1. - (1. - cos_i * cos_i) / (1.33 * 1.33)).sqrt();
+        assert!((fresnel_dielectric(cos_i, 1.33) - fresnel_dielectric(-cos_t, 1.33)).abs() < 1e-6);
+        assert!((fresnel_dielectric(cos_i, 1.33) - fresnel_dielectric(cos_t, 1. / 1.33)).abs() < 1e-6);
+        assert!((fresnel_dielectric(cos_i, 1.33) - fresnel_conductor(cos_i, 1.33, 0.)).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_fresnel_thin_film() {
+        // without thickness, or of the index of the outside, the film leaves the bare interface
+        for &cos_i in &[1f32, 0.7, 0.2] {
+            let bare = fresnel_conductor(cos_i, 0.2, 3.9);
+            assert!((fresnel_thin_film(cos_i, 1., 1.4, 0.2, 3.9, 0., 0.55) - bare).abs() < 1e-5);
+            assert!((fresnel_thin_film(cos_i, 1., 1., 0.2, 3.9, 0.3, 0.55) - bare).abs() < 1e-5);
+            let bare = fresnel_dielectric(cos_i, 1.5);
+            assert!((fresnel_thin_film(cos_i, 1., 1.33, 1.5, 0., 0., 0.55) - bare).abs() < 1e-5);
+        }
+
+        // a quarter wave coating of index the square root of the one of glass cancels its reflection
+        let film = 1.5f32.sqrt();
+        assert!(fresnel_thin_film(1., 1., film, 1.5, 0., 0.55 / (4. * film), 0.55) < 1e-5);
+        // and a half wave one reflects like bare glass, the round trip bringing the phase back
+        assert!((fresnel_thin_film(1., 1., film, 1.5, 0., 0.55 / (2. * film), 0.55) - 0.04).abs() < 1e-5);
+
+        // a soap film in air reflects some wavelengths far more than others
+        let reflectances: Vec<f32> = [0.45, 0.55, 0.65].iter().map(|&l| fresnel_thin_film(0.9, 1., 1.33, 1., 0., 0.4, l)).collect();
+        let (min, max) = reflectances.iter().fold((1f32, 0f32), |(min, max), &r| (min.min(r), max.max(r)));
+        assert!(max > 2. * min, "{:?}", reflectances);
+    }
+
+    #[test]
+    fn test_frame() {
+        let normal = Vec3::new(1., 2., 2.) / 3.;
+        for tangent in &[Some(Vec3::new(1., 0., 0.)), Some(normal), None] {
+            let frame = Frame::new(normal, *tangent);
+            let v = Vec3::new(0.3, -0.4, 0.5);
+            assert!((frame.to_world(frame.to_local(v)) - v).length() < 1e-6);
+            assert!((frame.to_local(normal) - Vec3::new(0., 0., 1.)).length() < 1e-6);
+        }
+        let up = Frame::new(Vec3::new(0., 1., 0.), None);
+        assert!((up.x.length() - 1.).abs() < 1e-6 && up.x.dot(up.z).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,583 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::{FromStr, SplitWhitespace};
+use std::sync::Arc;
+
+use image::ImageError;
+
+use crate::color::Color;
+use crate::hittable_list::HittableList;
+use crate::material::{Dielectric, DiffuseLight, Lambertian, Material, Metal};
+use crate::principled::{Parameter, Principled};
+use crate::texture::{ImageTexture, SolidColor, Texture};
+use crate::triangle::{Mesh, TriangleMesh};
+use crate::vec3::{Point3, Vec3};
+
+#[derive(Debug)]
+pub enum ObjError {
+    Io(PathBuf, io::Error),
+    // 1-based line number
+    Parse { path: PathBuf, line: usize, message: String },
+    Image(PathBuf, ImageError),
+    // no faces, which would leave nothing to bound
+    Empty(PathBuf),
+}
+
+impl Display for ObjError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ObjError::Io(path, e) => write!(f, "{}: {}", path.display(), e),
+            ObjError::Parse { path, line, message } => write!(f, "{}:{}: {}", path.display(), line, message),
+            ObjError::Image(path, e) => write!(f, "{}: {}", path.display(), e),
+            ObjError::Empty(path) => write!(f, "{}: no faces", path.display()),
+        }
+    }
+}
+
+impl Error for ObjError {}
+
+// position, texture coordinate and normal indices of a face corner, 0-based
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct Corner {
+    position: usize,
+    uv: Option<usize>,
+    normal: Option<usize>,
+}
+
+// the faces sharing one material
+struct Group {
+    material: Option<String>,
+    triangles: Vec<[Corner; 3]>,
+}
+
+// the file and line being parsed, to locate errors
+struct Location<'a> {
+    path: &'a Path,
+    line: usize,
+}
+
+impl Location<'_> {
+    fn error(&self, message: String) -> ObjError {
+        ObjError::Parse { path: self.path.to_path_buf(), line: self.line, message }
+    }
+
+    fn parse<T: FromStr>(&self, token: Option<&str>, what: &str) -> Result<T, ObjError> {
+        let token = token.ok_or_else(|| self.error(format!("missing {}", what)))?;
+        token.parse().map_err(|_| self.error(format!("invalid {} `{}`", what, token)))
+    }
+
+    fn vec3(&self, tokens: &mut SplitWhitespace) -> Result<Vec3, ObjError> {
+        Ok(Vec3::new(self.parse(tokens.next(), "x")?, self.parse(tokens.next(), "y")?, self.parse(tokens.next(), "z")?))
+    }
+
+    // OBJ indices start at 1, negative ones count back from the last element
+    fn index(&self, token: &str, count: usize, what: &str) -> Result<usize, ObjError> {
+        let i: i64 = token.parse().map_err(|_| self.error(format!("invalid {} index `{}`", what, token)))?;
+        let resolved = if i > 0 { i - 1 } else { count as i64 + i };
+        if i == 0 || resolved < 0 || resolved >= count as i64 {
+            return Err(self.error(format!("{} index {} out of {} {}s", what, i, count, what)));
+        }
+        Ok(resolved as usize)
+    }
+}
+
+// loads the meshes of an OBJ file, one per material, with the materials of the MTL libraries it uses
+// unless `material` overrides them
+pub fn load<P: AsRef<Path>>(path: P, material: Option<&Arc<dyn Material>>) -> Result<HittableList, ObjError> {
+    let path = path.as_ref();
+    let text = fs::read_to_string(path).map_err(|e| ObjError::Io(path.to_path_buf(), e))?;
+    let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+
+    let mut positions: Vec<Point3> = Vec::new();
+    let mut uvs: Vec<[f32; 2]> = Vec::new();
+    let mut normals: Vec<Vec3> = Vec::new();
+    let mut groups: Vec<Group> = vec![Group { material: None, triangles: Vec::new() }];
+    let mut current = 0;
+    let mut materials = HashMap::new();
+
+    for (i, line) in text.lines().enumerate() {
+        let at = Location { path, line: i + 1 };
+        let line = line.split('#').next().unwrap_or("");
+        let mut tokens = line.split_whitespace();
+
+        match tokens.next() {
+            Some("v") => positions.push(at.vec3(&mut tokens)?),
+            Some("vt") => {
+                let u = at.parse(tokens.next(), "u")?;
+                let v = tokens.next().map_or(Ok(0.), |token| at.parse(Some(token), "v"))?;
+                uvs.push([u, v]);
+            },
+            Some("vn") => {
+                // a normal without a direction would shade with NaNs
+                let normal = at.vec3(&mut tokens)?;
+                let length = normal.length();
+                if !(length > 0. && length.is_finite()) {
+                    return Err(at.error(format!("normal of length {}", length)));
+                }
+                normals.push(normal / length);
+            },
+            Some("f") => {
+                let corners = tokens
+                    .map(|token| {
+                        let mut indices = token.split('/');
+                        let position = at.index(indices.next().unwrap_or(""), positions.len(), "position")?;
+                        let uv = match indices.next() {
+                            Some("") | None => None,
+                            Some(index) => Some(at.index(index, uvs.len(), "uv")?),
+                        };
+                        let normal = match indices.next() {
+                            Some("") | None => None,
+                            Some(index) => Some(at.index(index, normals.len(), "normal")?),
+                        };
+                        Ok(Corner { position, uv, normal })
+                    })
+                    .collect::<Result<Vec<_>, ObjError>>()?;
+                if corners.len() < 3 {
+                    return Err(at.error(format!("face with {} vertices", corners.len())));
+                }
+                // polygons are assumed convex and split into a fan
+                for k in 1..corners.len() - 1 {
+                    groups[current].triangles.push([corners[0], corners[k], corners[k + 1]]);
+                }
+            },
+            Some("usemtl") => {
+                let name = tokens.next().ok_or_else(|| at.error("missing material name".to_string()))?;
+                if material.is_none() && !materials.contains_key(name) {
+                    return Err(at.error(format!("unknown material `{}`", name)));
+                }
+                current = match groups.iter().position(|g| g.material.as_deref() == Some(name)) {
+                    Some(index) => index,
+                    None => {
+                        groups.push(Group { material: Some(name.to_string()), triangles: Vec::new() });
+                        groups.len() - 1
+                    },
+                };
+            },
+            // an overriding material needs no libraries
+            Some("mtllib") if material.is_none() => {
+                for library in tokens {
+                    load_mtl(&base_dir.join(library), &mut materials)?;
+                }
+            },
+            // objects, groups, smoothing groups, lines, points, free-form curves and surfaces and
+            // display attributes do not affect the meshes
+            _ => {},
+        }
+    }
+
+    let default_material: Arc<dyn Material> = Arc::new(Lambertian::new_from_color(Color(Vec3::new(0.8, 0.8, 0.8))));
+    let mut meshes = HittableList { objects: Vec::new() };
+    for group in groups.into_iter().filter(|g| !g.triangles.is_empty()) {
+        let material = match (material, &group.material) {
+            (Some(material), _) => material.clone(),
+            (None, Some(name)) => materials[name].clone(),
+            (None, None) => default_material.clone(),
+        };
+        meshes.add(Arc::new(TriangleMesh::new(build_mesh(&group, &positions, &uvs, &normals, material))));
+    }
+    if meshes.objects.is_empty() {
+        return Err(ObjError::Empty(path.to_path_buf()));
+    }
+
+    Ok(meshes)
+}
+
+// merges the corners sharing the same indices into one vertex, normals and uvs are only kept
+// when every corner of the group has them
+fn build_mesh(group: &Group, positions: &[Point3], uvs: &[[f32; 2]], normals: &[Vec3], material: Arc<dyn Material>) -> Mesh {
+    let has_uvs = group.triangles.iter().flatten().all(|c| c.uv.is_some());
+    let has_normals = group.triangles.iter().flatten().all(|c| c.normal.is_some());
+
+    let mut mesh = Mesh {
+        positions: Vec::new(),
+        normals: Vec::new(),
+        uvs: Vec::new(),
+        colors: Vec::new(),
+        indices: Vec::new(),
+        material,
+    };
+    let mut vertices: HashMap<Corner, u32> = HashMap::new();
+    for triangle in &group.triangles {
+        let mut indices = [0; 3];
+        for (index, corner) in indices.iter_mut().zip(triangle) {
+            let key = Corner {
+                position: corner.position,
+                uv: if has_uvs { corner.uv } else { None },
+                normal: if has_normals { corner.normal } else { None },
+            };
+            *index = *vertices.entry(key).or_insert_with(|| {
+                mesh.positions.push(positions[key.position]);
+                if let Some(uv) = key.uv {
+                    mesh.uvs.push(uvs[uv]);
+                }
+                if let Some(normal) = key.normal {
+                    mesh.normals.push(normals[normal]);
+                }
+                (mesh.positions.len() - 1) as u32
+            });
+        }
+        mesh.indices.push(indices);
+    }
+
+    mesh
+}
+
+// the subset of an MTL material the crate can render
+struct MtlDesc {
+    name: String,
+    diffuse: [f32; 3],
+    specular: [f32; 3],
+    // Phong exponent
+    shininess: Option<f32>,
+    // glass without one is given that of common glass
+    ref_idx: Option<f32>,
+    emission: [f32; 3],
+    dissolve: f32,
+    illum: Option<u32>,
+    diffuse_map: Option<PathBuf>,
+    // the PBR extension of MTL, any of which makes a principled material
+    roughness: Option<f32>,
+    metallic: Option<f32>,
+    sheen: Option<f32>,
+    clearcoat: Option<f32>,
+    clearcoat_roughness: Option<f32>,
+    anisotropy: Option<f32>,
+    // read from their red channel
+    roughness_map: Option<PathBuf>,
+    metallic_map: Option<PathBuf>,
+}
+
+impl MtlDesc {
+    fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            diffuse: [0.8, 0.8, 0.8],
+            specular: [0., 0., 0.],
+            shininess: None,
+            ref_idx: None,
+            emission: [0., 0., 0.],
+            dissolve: 1.,
+            illum: None,
+            diffuse_map: None,
+            roughness: None,
+            metallic: None,
+            sheen: None,
+            clearcoat: None,
+            clearcoat_roughness: None,
+            anisotropy: None,
+            roughness_map: None,
+            metallic_map: None,
+        }
+    }
+
+    fn is_pbr(&self) -> bool {
+        [self.roughness, self.metallic, self.sheen, self.clearcoat, self.clearcoat_roughness, self.anisotropy]
+            .iter()
+            .any(Option::is_some)
+            || self.roughness_map.is_some()
+            || self.metallic_map.is_some()
+    }
+
+    // emissive materials become lights, transparent ones or the refraction illumination models glass,
+    // the mirror illumination model or a purely specular color metal, and everything else Lambertian;
+    // with PBR parameters they all are a principled material instead
+    fn build(&self) -> Result<Arc<dyn Material>, ObjError> {
+        let color = |c: [f32; 3]| Color(Vec3::new(c[0], c[1], c[2]));
+        let max = |c: [f32; 3]| c[0].max(c[1]).max(c[2]);
+
+        if self.is_pbr() {
+            return self.build_principled();
+        }
+        if max(self.emission) > 0. {
+            return Ok(Arc::new(DiffuseLight::new(color(self.emission))));
+        }
+        if self.dissolve < 1. || matches!(self.illum, Some(4) | Some(6) | Some(7) | Some(9)) {
+            return Ok(Arc::new(Dielectric::new(self.ref_idx.unwrap_or(1.5))));
+        }
+        if self.illum == Some(3) || (max(self.diffuse) == 0. && max(self.specular) > 0.) {
+            // the usual mapping of a Phong exponent onto a roughness
+            let fuzz = self.shininess.map_or(0., |ns| (2. / (ns.max(0.) + 2.)).sqrt());
+            return Ok(Arc::new(Metal::new(color(self.specular), fuzz)));
+        }
+        Ok(Arc::new(Lambertian::new_from_texture(texture(&self.diffuse_map, self.diffuse)?)))
+    }
+
+    fn build_principled(&self) -> Result<Arc<dyn Material>, ObjError> {
+        let mut principled = Principled::new(texture(&self.diffuse_map, self.diffuse)?);
+        let parameter = |map: &Option<PathBuf>, value: Option<f32>| -> Result<Option<Parameter>, ObjError> {
+            Ok(match (map, value) {
+                (None, None) => None,
+                (map, value) => Some(Parameter::from_texture(texture(map, [value.unwrap_or(0.); 3])?, 0)),
+            })
+        };
+        if let Some(roughness) = parameter(&self.roughness_map, self.roughness)? {
+            principled.roughness = roughness;
+        }
+        if let Some(metallic) = parameter(&self.metallic_map, self.metallic)? {
+            principled.metallic = metallic;
+        }
+        let constant = |value: Option<f32>, parameter: &mut Parameter| {
+            if let Some(value) = value {
+                *parameter = Parameter::constant(value);
+            }
+        };
+        constant(self.sheen, &mut principled.sheen);
+        constant(self.clearcoat, &mut principled.clearcoat);
+        constant(self.clearcoat_roughness.map(|r| 1. - r), &mut principled.clearcoat_gloss);
+        constant(self.anisotropy, &mut principled.anisotropic);
+
+        if let Some(ref_idx) = self.ref_idx.filter(|&n| n > 1.) {
+            principled.ior = ref_idx;
+        }
+        principled.transmission = Parameter::constant(1. - self.dissolve);
+        principled.emission = texture(&None, self.emission)?;
+        Ok(Arc::new(principled))
+    }
+}
+
+// the image at `path`, or a constant color without one
+fn texture(path: &Option<PathBuf>, c: [f32; 3]) -> Result<Box<dyn Texture>, ObjError> {
+    Ok(match path {
+        Some(path) => Box::new(ImageTexture::load(path).map_err(|e| ObjError::Image(path.clone(), e))?),
+        None => Box::new(SolidColor::new_from_color(Color(Vec3::new(c[0], c[1], c[2])))),
+    })
+}
+
+fn load_mtl(path: &Path, materials: &mut HashMap<String, Arc<dyn Material>>) -> Result<(), ObjError> {
+    let text = fs::read_to_string(path).map_err(|e| ObjError::Io(path.to_path_buf(), e))?;
+    let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+
+    let mut current: Option<MtlDesc> = None;
+    for (i, line) in text.lines().enumerate() {
+        let at = Location { path, line: i + 1 };
+        let line = line.split('#').next().unwrap_or("");
+        let mut tokens = line.split_whitespace();
+
+        let keyword = match tokens.next() {
+            Some(keyword) => keyword,
+            None => continue,
+        };
+        if keyword == "newmtl" {
+            let name = tokens.next().ok_or_else(|| at.error("missing material name".to_string()))?;
+            if let Some(desc) = current.replace(MtlDesc::new(name)) {
+                materials.insert(desc.name.clone(), desc.build()?);
+            }
+            continue;
+        }
+
+        let desc = current.as_mut().ok_or_else(|| at.error(format!("`{}` before any `newmtl`", keyword)))?;
+        let rgb = |tokens: &mut SplitWhitespace| -> Result<[f32; 3], ObjError> {
+            let r = at.parse(tokens.next(), "red")?;
+            // a single value is a gray
+            match tokens.next() {
+                None => Ok([r, r, r]),
+                g => Ok([r, at.parse(g, "green")?, at.parse(tokens.next(), "blue")?]),
+            }
+        };
+        match keyword {
+            "Kd" => desc.diffuse = rgb(&mut tokens)?,
+            "Ks" => desc.specular = rgb(&mut tokens)?,
+            "Ke" => desc.emission = rgb(&mut tokens)?,
+            "Ns" => desc.shininess = Some(at.parse(tokens.next(), "shininess")?),
+            "Ni" => {
+                let ref_idx: f32 = at.parse(tokens.next(), "index of refraction")?;
+                if !(ref_idx > 0. && ref_idx.is_finite()) {
+                    return Err(at.error(format!("index of refraction {} is not positive", ref_idx)));
+                }
+                desc.ref_idx = Some(ref_idx);
+            },
+            "d" => desc.dissolve = at.parse(tokens.next(), "dissolve")?,
+            "Tr" => desc.dissolve = 1. - at.parse::<f32>(tokens.next(), "transparency")?,
+            "illum" => desc.illum = Some(at.parse(tokens.next(), "illumination model")?),
+            // options such as `-s 1 1 1` come before the file name
+            "map_Kd" => {
+                let file = tokens.last().ok_or_else(|| at.error("missing texture file".to_string()))?;
+                desc.diffuse_map = Some(base_dir.join(file));
+            },
+            "Pr" => desc.roughness = Some(at.parse(tokens.next(), "roughness")?),
+            "Pm" => desc.metallic = Some(at.parse(tokens.next(), "metallic")?),
+            // a sheen color, of which only the strength is kept
+            "Ps" => desc.sheen = Some(rgb(&mut tokens)?.iter().sum::<f32>() / 3.),
+            "Pc" => desc.clearcoat = Some(at.parse(tokens.next(), "clearcoat thickness")?),
+            "Pcr" => desc.clearcoat_roughness = Some(at.parse(tokens.next(), "clearcoat roughness")?),
+            "aniso" => desc.anisotropy = Some(at.parse(tokens.next(), "anisotropy")?),
+            "map_Pr" => {
+                let file = tokens.last().ok_or_else(|| at.error("missing texture file".to_string()))?;
+                desc.roughness_map = Some(base_dir.join(file));
+            },
+            "map_Pm" => {
+                let file = tokens.last().ok_or_else(|| at.error("missing texture file".to_string()))?;
+                desc.metallic_map = Some(base_dir.join(file));
+            },
+            // ambient colors, other texture maps and filters are not rendered
+            _ => {},
+        }
+    }
+    if let Some(desc) = current {
+        materials.insert(desc.name.clone(), desc.build()?);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::hittable::Hittable;
+    use crate::ray::Ray;
+    use crate::sampler::Sampler;
+    use rand::SeedableRng;
+
+    // writes the files of a test model into a fresh directory
+    fn write_model(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("obj-{}-{}", std::process::id(), name));
+        fs::create_dir_all(&dir).unwrap();
+        for (file, text) in files {
+            fs::write(dir.join(file), text).unwrap();
+        }
+        dir
+    }
+
+    const QUAD: &str = "
+        mtllib quad.mtl
+        v 0 0 0
+        v 1 0 0
+        v 1 1 0
+        v 0 1 0
+        vt 0 0
+        vt 1 0
+        vt 1 1
+        vt 0 1
+        vn 0 0 1
+        usemtl light
+        f 1/1/1 2/2/1 3/3/1 4/4/1
+        v 0 0 -1
+        v 1 0 -1
+        v 0 1 -1
+        usemtl glass
+        f -3//1 -2//1 -1//1 # a comment
+    ";
+
+    const QUAD_MTL: &str = "
+        newmtl light
+        Ke 4 4 4
+        newmtl glass
+        Ni 1.5
+        d 0.1
+    ";
+
+    #[test]
+    fn test_load_groups_by_material() {
+        let dir = write_model("groups", &[("quad.obj", QUAD), ("quad.mtl", QUAD_MTL)]);
+        let meshes = load(dir.join("quad.obj"), None).unwrap();
+        assert_eq!(meshes.objects.len(), 2);
+
+        // the quad is in front of the triangle, and emits
+        let r = Ray::new_without_move(Point3::new(0.25, 0.5, 1.), Vec3::new(0., 0., -1.));
+        let rec = meshes.hit(&r, 0.001, f32::MAX).unwrap();
+        assert_eq!(rec.t, 1.);
+        assert_eq!((rec.u, rec.v), (0.25, 0.5));
+        assert_eq!(rec.material.emitted(rec.u, rec.v, &rec.p).0, Vec3::new(4., 4., 4.));
+
+        let r = Ray::new_without_move(Point3::new(0.25, 0.5, -2.), Vec3::new(0., 0., 1.));
+        let rec = meshes.hit(&r, 0.001, f32::MAX).unwrap();
+        assert_eq!(rec.t, 1.);
+        assert_eq!(rec.material.emitted(rec.u, rec.v, &rec.p).0, Vec3::new(0., 0., 0.));
+    }
+
+    #[test]
+    fn test_pbr_materials_are_principled() {
+        let dir = write_model("pbr", &[
+            ("quad.obj", QUAD),
+            ("quad.mtl", "newmtl light\nKe 4 4 4\nPm 1\nPr 0.2\nnewmtl glass\nKd 1 1 1\nNi 1.5\nd 0\nPr 0.3\n"),
+        ]);
+        let meshes = load(dir.join("quad.obj"), None).unwrap();
+
+        // a polished metal emitting like the light it would have been, which a Lambertian reflects
+        // far less of along the mirror direction
+        let r = Ray::new_without_move(Point3::new(0.25, 0.5, 1.), Vec3::new(0., 0., -1.));
+        let rec = meshes.hit(&r, 0.001, f32::MAX).unwrap();
+        assert_eq!(rec.material.emitted(rec.u, rec.v, &rec.p).0, Vec3::new(4., 4., 4.));
+        let wo = Vec3::new(0.6, 0., 0.8);
+        assert!(rec.material.pdf(&rec, Vec3::new(-0.6, 0., 0.8), wo) > 1.);
+
+        // a rough glass letting light through
+        let r = Ray::new_without_move(Point3::new(0.25, 0.5, -2.), Vec3::new(0., 0., 1.));
+        let rec = meshes.hit(&r, 0.001, f32::MAX).unwrap();
+        let wo = Vec3::new(0., 0., -1.);
+        assert!(rec.material.eval(&rec, Vec3::new(0., 0., 1.), wo).0.x > 0.);
+    }
+
+    #[test]
+    fn test_errors_have_file_and_line() {
+        let cases = [
+            ("v 0 0 0\nv 1 0 zero\n", 2, "invalid z `zero`"),
+            ("v 0 0 0\nv 1 0 0\nv 0 1 0\n\nf 1 2 4\n", 5, "position index 4 out of 3 positions"),
+            ("v 0 0 0\nf 1 1\n", 2, "face with 2 vertices"),
+            ("usemtl missing\n", 1, "unknown material `missing`"),
+            ("v 0 0 0\nvn 0 0 1\nvn 0 0 0\n", 3, "normal of length 0"),
+        ];
+
+        let dir = write_model("errors", &[]);
+        for (text, line, message) in &cases {
+            let path = dir.join("bad.obj");
+            fs::write(&path, text).unwrap();
+            match load(&path, None) {
+                Err(ObjError::Parse { path: p, line: l, message: m }) => {
+                    assert_eq!((p, l, m.as_str()), (path.clone(), *line, *message));
+                },
+                Err(e) => panic!("unexpected error: {}", e),
+                Ok(_) => panic!("expected an error for {:?}", text),
+            }
+        }
+    }
+
+    #[test]
+    fn test_skips_unknown_statements_and_defaults_glass_index() {
+        let obj = format!("vp 0.5\ncstype bezier\ncurv 0 1 1 2\nbevel on\n{}", QUAD);
+        let dir = write_model("lenient", &[("quad.obj", &obj), ("quad.mtl", "newmtl light\nKe 4 4 4\nnewmtl glass\nd 0.1\n")]);
+        let meshes = load(dir.join("quad.obj"), None).unwrap();
+        assert_eq!(meshes.objects.len(), 2);
+
+        // without `Ni` the glass still bends the light it lets through
+        let r = Ray::new_without_move(Point3::new(0.1, 0.3, -2.), Vec3::new(0.1, 0., 1.));
+        let rec = meshes.hit(&r, 0.001, f32::MAX).unwrap();
+        let wo = -r.direction.normalize();
+        let mut rng = Sampler::seed_from_u64(0);
+        let wi = (0..100)
+            .filter_map(|_| rec.material.sample(&rec, wo, &mut rng))
+            .map(|(wi, _, _)| wi)
+            .find(|wi| wi.dot(rec.normal) < 0.)
+            .unwrap();
+        assert!((wi.normalize() + wo).length() > 0.01);
+    }
+
+    #[test]
+    fn test_model_without_faces() {
+        let dir = write_model("empty", &[("points.obj", "v 0 0 0\nv 1 0 0\nv 0 1 0\np 1 2 3\n")]);
+        let err = load(dir.join("points.obj"), None).err().unwrap();
+        assert_eq!(err.to_string(), format!("{}: no faces", dir.join("points.obj").display()));
+    }
+
+    #[test]
+    fn test_mtl_errors_have_file_and_line() {
+        let dir = write_model("mtl-errors", &[
+            ("a.obj", "mtllib a.mtl\n"),
+            ("a.mtl", "newmtl red\nKd 1 0\n"),
+        ]);
+
+        let err = load(dir.join("a.obj"), None).err().unwrap();
+        assert_eq!(err.to_string(), format!("{}:2: missing blue", dir.join("a.mtl").display()));
+
+        for ref_idx in &["0", "-1.5"] {
+            fs::write(dir.join("a.mtl"), format!("newmtl glass\nd 0.1\nNi {}\n", ref_idx)).unwrap();
+            let err = load(dir.join("a.obj"), None).err().unwrap();
+            assert_eq!(err.to_string(), format!("{}:3: index of refraction {} is not positive", dir.join("a.mtl").display(), ref_idx));
+        }
+    }
+}
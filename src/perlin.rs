@@ -1,5 +1,7 @@
 use crate::vec3::{Vec3, Point3};
-use rand::{self, Rng};
+use rand::Rng;
+
+use crate::sampler::Sampler;
 
 const POINT_COUNT: i32 = 256;
 
@@ -11,9 +13,7 @@ pub struct Perlin {
 }
 
 impl Perlin {
-    fn permute(p: &mut Vec<i32>) {
-        let mut rng = rand::thread_rng();
-
+    fn permute(p: &mut [i32], rng: &mut Sampler) {
         // TODO Refactor later to use more ideal way, e.g. reverse & iterator
         let mut i = POINT_COUNT - 1;
 
@@ -24,16 +24,15 @@ impl Perlin {
         }
     }
 
-    pub fn perlin_generate_perm() -> Vec<i32> {
+    pub fn perlin_generate_perm(rng: &mut Sampler) -> Vec<i32> {
         let mut p: Vec<i32> = (0..POINT_COUNT).collect();
 
-        Self::permute(&mut p);
+        Self::permute(&mut p, rng);
 
         p
     }
 
-    pub fn new() -> Self {
-        let mut rng = rand::thread_rng();
+    pub fn new(rng: &mut Sampler) -> Self {
         let ran_vec = (0..POINT_COUNT)
             .map(|_| Vec3{ x: rng.gen_range(-1., 1.),
                            y: rng.gen_range(-1., 1.),
@@ -42,17 +41,17 @@ impl Perlin {
             .collect();
 
         Self {
-            ran_vec: ran_vec,
-            perm_x: Self::perlin_generate_perm(),
-            perm_y: Self::perlin_generate_perm(),
-            perm_z: Self::perlin_generate_perm(),
+            ran_vec,
+            perm_x: Self::perlin_generate_perm(rng),
+            perm_y: Self::perlin_generate_perm(rng),
+            perm_z: Self::perlin_generate_perm(rng),
         }
     }
 
     pub fn noise(&self, p: &Point3) -> f32 {
-        let mut u = p.x - p.x.floor();
-        let mut v = p.y - p.y.floor();
-        let mut w = p.z - p.z.floor();
+        let u = p.x - p.x.floor();
+        let v = p.y - p.y.floor();
+        let w = p.z - p.z.floor();
 
         let i = p.x.floor() as i32;
         let j = p.y.floor() as i32;
@@ -89,22 +88,7 @@ impl Perlin {
     }
 }
 
-fn trilinear_interp(c: [[[f32; 2]; 2]; 2], u: f32, v: f32, w: f32) -> f32 {
-    let mut accum: f32 = 0.;
-
-    for i in 0..2 {
-        for j in 0..2 {
-            for k in 0..2 {
-                accum += (i as f32 * u + ((1 - i) as f32) * (1. - u)) *
-                    (j as f32 * v + ((1 - j) as f32) * (1. - v)) *
-                    (k as f32 * w + ((1 - k) as f32) * (1. - w)) * c[i][j][k];
-            }
-        }
-    }
-
-    accum
-}
-
+#[allow(clippy::needless_range_loop)]
 fn perlin_interp(c: [[[Vec3; 2]; 2]; 2], u: f32, v: f32, w: f32) -> f32 {
     let uu = u * u * (3. - 2. * u);
     let vv = v * v * (3. - 2. * v);
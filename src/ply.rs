@@ -0,0 +1,419 @@
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::SplitAsciiWhitespace;
+use std::sync::Arc;
+
+use crate::color::Color;
+use crate::material::Material;
+use crate::triangle::{Mesh, TriangleMesh};
+use crate::vec3::{Point3, Vec3};
+
+#[derive(Debug)]
+pub enum PlyError {
+    Io(PathBuf, io::Error),
+    Parse(PathBuf, String),
+}
+
+impl Display for PlyError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            PlyError::Io(path, e) => write!(f, "{}: {}", path.display(), e),
+            PlyError::Parse(path, message) => write!(f, "{}: {}", path.display(), message),
+        }
+    }
+}
+
+impl Error for PlyError {}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Scalar {
+    Char,
+    UChar,
+    Short,
+    UShort,
+    Int,
+    UInt,
+    Float,
+    Double,
+}
+
+impl Scalar {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "char" | "int8" => Some(Scalar::Char),
+            "uchar" | "uint8" => Some(Scalar::UChar),
+            "short" | "int16" => Some(Scalar::Short),
+            "ushort" | "uint16" => Some(Scalar::UShort),
+            "int" | "int32" => Some(Scalar::Int),
+            "uint" | "uint32" => Some(Scalar::UInt),
+            "float" | "float32" => Some(Scalar::Float),
+            "double" | "float64" => Some(Scalar::Double),
+            _ => None,
+        }
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Scalar::Char | Scalar::UChar => 1,
+            Scalar::Short | Scalar::UShort => 2,
+            Scalar::Int | Scalar::UInt | Scalar::Float => 4,
+            Scalar::Double => 8,
+        }
+    }
+
+    // integer colors span their whole range, float ones [0, 1]
+    fn color_scale(self) -> f64 {
+        match self {
+            Scalar::UChar => 1. / 255.,
+            Scalar::UShort => 1. / 65535.,
+            _ => 1.,
+        }
+    }
+}
+
+enum Property {
+    Scalar { name: String, scalar: Scalar },
+    List { name: String, count: Scalar, item: Scalar },
+}
+
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+// reads the scalars of the body one after the other whatever the format
+enum Body<'a> {
+    Ascii(SplitAsciiWhitespace<'a>),
+    Binary { bytes: &'a [u8], offset: usize, big_endian: bool },
+}
+
+impl Body<'_> {
+    fn read(&mut self, scalar: Scalar) -> Result<f64, String> {
+        match self {
+            Body::Ascii(tokens) => {
+                let token = tokens.next().ok_or_else(|| "unexpected end of file".to_string())?;
+                token.parse().map_err(|_| format!("invalid {:?} `{}`", scalar, token))
+            },
+            Body::Binary { bytes, offset, big_endian } => {
+                let size = scalar.size();
+                let data = bytes.get(*offset..*offset + size).ok_or_else(|| "unexpected end of file".to_string())?;
+                *offset += size;
+
+                let mut raw = [0u8; 8];
+                raw[..size].copy_from_slice(data);
+                if *big_endian {
+                    raw[..size].reverse();
+                }
+                Ok(match scalar {
+                    Scalar::Char => raw[0] as i8 as f64,
+                    Scalar::UChar => raw[0] as f64,
+                    Scalar::Short => i16::from_le_bytes([raw[0], raw[1]]) as f64,
+                    Scalar::UShort => u16::from_le_bytes([raw[0], raw[1]]) as f64,
+                    Scalar::Int => i32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]) as f64,
+                    Scalar::UInt => u32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]) as f64,
+                    Scalar::Float => f32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]) as f64,
+                    Scalar::Double => f64::from_le_bytes(raw),
+                })
+            },
+        }
+    }
+}
+
+fn parse_header(text: &str) -> Result<(Format, Vec<Element>), String> {
+    // the first line is the `ply` magic number
+    let lines = text.lines().enumerate().skip(1).map(|(i, line)| (i + 1, line.trim()));
+
+    let mut format = None;
+    let mut elements: Vec<Element> = Vec::new();
+    for (line_number, line) in lines {
+        let at = |message: String| format!("line {}: {}", line_number, message);
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+
+        match tokens.as_slice() {
+            ["format", name, "1.0"] => {
+                format = Some(match *name {
+                    "ascii" => Format::Ascii,
+                    "binary_little_endian" => Format::BinaryLittleEndian,
+                    "binary_big_endian" => Format::BinaryBigEndian,
+                    _ => return Err(at(format!("unknown format `{}`", name))),
+                });
+            },
+            ["element", name, count] => {
+                let count = count.parse().map_err(|_| at(format!("invalid element count `{}`", count)))?;
+                elements.push(Element { name: name.to_string(), count, properties: Vec::new() });
+            },
+            ["property", "list", count, item, name] => {
+                let element = elements.last_mut().ok_or_else(|| at("property before any element".to_string()))?;
+                let count = Scalar::parse(count).ok_or_else(|| at(format!("unknown type `{}`", count)))?;
+                let item = Scalar::parse(item).ok_or_else(|| at(format!("unknown type `{}`", item)))?;
+                element.properties.push(Property::List { name: name.to_string(), count, item });
+            },
+            ["property", scalar, name] => {
+                let element = elements.last_mut().ok_or_else(|| at("property before any element".to_string()))?;
+                let scalar = Scalar::parse(scalar).ok_or_else(|| at(format!("unknown type `{}`", scalar)))?;
+                element.properties.push(Property::Scalar { name: name.to_string(), scalar });
+            },
+            ["comment", ..] | ["obj_info", ..] | [] => {},
+            ["end_header"] => return format.map(|f| (f, elements)).ok_or_else(|| "missing format".to_string()),
+            _ => return Err(at(format!("invalid header line `{}`", line))),
+        }
+    }
+
+    Err("missing end_header".to_string())
+}
+
+// byte offset of the body, right after the `end_header` line
+fn body_offset(bytes: &[u8]) -> Option<usize> {
+    let marker = b"end_header";
+    let start = bytes.windows(marker.len()).position(|w| w == marker)? + marker.len();
+    let newline = bytes[start..].iter().position(|&b| b == b'\n')?;
+    Some(start + newline + 1)
+}
+
+// loads the `vertex` and `face` elements of a PLY file into one mesh, the optional
+// normals (nx, ny, nz), texture coordinates (u, v or s, t) and colors (red, green, blue)
+// are kept when present, polygons are split into fans
+pub fn load<P: AsRef<Path>>(path: P, material: Arc<dyn Material>) -> Result<TriangleMesh, PlyError> {
+    let path = path.as_ref();
+    let bytes = fs::read(path).map_err(|e| PlyError::Io(path.to_path_buf(), e))?;
+    let mesh = parse(&bytes, material).map_err(|message| PlyError::Parse(path.to_path_buf(), message))?;
+
+    Ok(TriangleMesh::new(mesh))
+}
+
+fn parse(bytes: &[u8], material: Arc<dyn Material>) -> Result<Mesh, String> {
+    if !bytes.starts_with(b"ply\n") && !bytes.starts_with(b"ply\r\n") {
+        return Err("not a PLY file".to_string());
+    }
+    let offset = body_offset(bytes).ok_or_else(|| "missing end_header".to_string())?;
+    let header = std::str::from_utf8(&bytes[..offset]).map_err(|_| "invalid header".to_string())?;
+    let (format, elements) = parse_header(header)?;
+
+    let mut body = match format {
+        Format::Ascii => {
+            let text = std::str::from_utf8(&bytes[offset..]).map_err(|_| "invalid ASCII body".to_string())?;
+            Body::Ascii(text.split_ascii_whitespace())
+        },
+        Format::BinaryLittleEndian => Body::Binary { bytes, offset, big_endian: false },
+        Format::BinaryBigEndian => Body::Binary { bytes, offset, big_endian: true },
+    };
+
+    let mut mesh = Mesh {
+        positions: Vec::new(),
+        normals: Vec::new(),
+        uvs: Vec::new(),
+        colors: Vec::new(),
+        indices: Vec::new(),
+        material,
+    };
+
+    for element in &elements {
+        let names: Vec<&str> = element.properties.iter()
+            .map(|p| match p {
+                Property::Scalar { name, .. } | Property::List { name, .. } => name.as_str(),
+            })
+            .collect();
+        let has = |wanted: &[&str]| wanted.iter().all(|w| names.contains(w));
+        let uv_names = if has(&["u", "v"]) {
+            Some(["u", "v"])
+        } else if has(&["s", "t"]) {
+            Some(["s", "t"])
+        } else if has(&["texture_u", "texture_v"]) {
+            Some(["texture_u", "texture_v"])
+        } else {
+            None
+        };
+
+        for index in 0..element.count {
+            let at = |message: String| format!("{} {}: {}", element.name, index, message);
+            let mut scalars: Vec<(&str, f64, Scalar)> = Vec::with_capacity(element.properties.len());
+            let mut lists: Vec<(&str, Vec<f64>)> = Vec::new();
+
+            for property in &element.properties {
+                match property {
+                    Property::Scalar { name, scalar } => {
+                        scalars.push((name, body.read(*scalar).map_err(at)?, *scalar));
+                    },
+                    Property::List { name, count, item } => {
+                        let n = body.read(*count).map_err(at)? as usize;
+                        let items = (0..n).map(|_| body.read(*item)).collect::<Result<Vec<_>, _>>().map_err(at)?;
+                        lists.push((name, items));
+                    },
+                }
+            }
+            let get = |name: &str| scalars.iter().find(|s| s.0 == name).map(|s| (s.1, s.2));
+            let value = |name: &str| get(name).map_or(0., |s| s.0) as f32;
+
+            match element.name.as_str() {
+                "vertex" => {
+                    mesh.positions.push(Point3::new(value("x"), value("y"), value("z")));
+                    if has(&["nx", "ny", "nz"]) {
+                        mesh.normals.push(Vec3::new(value("nx"), value("ny"), value("nz")).normalize());
+                    }
+                    if let Some([u, v]) = uv_names {
+                        mesh.uvs.push([value(u), value(v)]);
+                    }
+                    if has(&["red", "green", "blue"]) {
+                        let channel = |name: &str| get(name).map_or(0., |(x, scalar)| x * scalar.color_scale()) as f32;
+                        mesh.colors.push(Color(Vec3::new(channel("red"), channel("green"), channel("blue"))));
+                    }
+                },
+                "face" => {
+                    let corners = lists.iter()
+                        .find(|(name, _)| *name == "vertex_indices" || *name == "vertex_index")
+                        .map(|(_, items)| items)
+                        .ok_or_else(|| at("missing vertex_indices".to_string()))?;
+                    if corners.len() < 3 {
+                        return Err(at(format!("face with {} vertices", corners.len())));
+                    }
+                    // float or signed lists would otherwise wrap or truncate into some other vertex
+                    let corners = corners.iter()
+                        .map(|&i| if i >= 0. && i.fract() == 0. && i <= u32::MAX as f64 {
+                            Ok(i as u32)
+                        } else {
+                            Err(at(format!("invalid vertex index {}", i)))
+                        })
+                        .collect::<Result<Vec<u32>, _>>()?;
+                    for k in 1..corners.len() - 1 {
+                        mesh.indices.push([corners[0], corners[k], corners[k + 1]]);
+                    }
+                },
+                // other elements, like edges, are read and skipped
+                _ => {},
+            }
+        }
+    }
+
+    mesh.validate()?;
+    Ok(mesh)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::hittable::Hittable;
+    use crate::material::Lambertian;
+    use crate::ray::Ray;
+    use crate::texture::{Texture, VertexColorTexture};
+
+    fn material() -> Arc<dyn Material> {
+        Arc::new(Lambertian::new_from_texture(Box::new(VertexColorTexture { fallback: Color(Vec3::new(1., 0., 1.)) })))
+    }
+
+    const HEADER: &str = "element vertex 4
+property float x
+property float y
+property float z
+property float nx
+property float ny
+property float nz
+property float s
+property float t
+property uchar red
+property uchar green
+property uchar blue
+element face 1
+property list uchar int vertex_indices
+end_header
+";
+
+    // a unit quad facing +z with a red, green, blue and white corner
+    const VERTICES: [[f32; 8]; 4] = [
+        [0., 0., 0., 0., 0., 1., 0., 0.],
+        [1., 0., 0., 0., 0., 1., 1., 0.],
+        [1., 1., 0., 0., 0., 1., 1., 1.],
+        [0., 1., 0., 0., 0., 1., 0., 1.],
+    ];
+    const COLORS: [[u8; 3]; 4] = [[255, 0, 0], [0, 255, 0], [0, 0, 255], [255, 255, 255]];
+
+    fn ascii() -> Vec<u8> {
+        let mut text = format!("ply\nformat ascii 1.0\ncomment a quad\n{}", HEADER);
+        for (v, c) in VERTICES.iter().zip(&COLORS) {
+            let v: Vec<String> = v.iter().map(|x| x.to_string()).collect();
+            text += &format!("{} {} {} {}\n", v.join(" "), c[0], c[1], c[2]);
+        }
+        text += "4 0 1 2 3\n";
+        text.into_bytes()
+    }
+
+    fn binary(big_endian: bool) -> Vec<u8> {
+        let format = if big_endian { "binary_big_endian" } else { "binary_little_endian" };
+        let mut bytes = format!("ply\nformat {} 1.0\n{}", format, HEADER).into_bytes();
+        for (v, c) in VERTICES.iter().zip(&COLORS) {
+            for x in v {
+                bytes.extend_from_slice(&if big_endian { x.to_be_bytes() } else { x.to_le_bytes() });
+            }
+            bytes.extend_from_slice(c);
+        }
+        bytes.push(4);
+        for i in 0..4i32 {
+            bytes.extend_from_slice(&if big_endian { i.to_be_bytes() } else { i.to_le_bytes() });
+        }
+        bytes
+    }
+
+    #[test]
+    fn test_formats_read_the_same_mesh() {
+        for (name, bytes) in [("ascii", ascii()), ("little endian", binary(false)), ("big endian", binary(true))] {
+            let mesh = parse(&bytes, material()).unwrap_or_else(|e| panic!("{}: {}", name, e));
+
+            assert_eq!(mesh.positions.len(), 4, "{}", name);
+            assert_eq!(mesh.positions[2], Point3::new(1., 1., 0.), "{}", name);
+            assert_eq!(mesh.normals[3], Vec3::new(0., 0., 1.), "{}", name);
+            assert_eq!(mesh.uvs[1], [1., 0.], "{}", name);
+            assert_eq!(mesh.colors[1].0, Vec3::new(0., 1., 0.), "{}", name);
+            assert_eq!(mesh.indices, vec![[0, 1, 2], [0, 2, 3]], "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_vertex_color_texture() {
+        let mesh = TriangleMesh::new(parse(&ascii(), material()).unwrap());
+
+        // next to the red corner
+        let r = Ray::new_without_move(Point3::new(0.01, 0.005, 1.), Vec3::new(0., 0., -1.));
+        let rec = mesh.hit(&r, 0.001, f32::MAX).unwrap();
+        let color = rec.color.unwrap().0;
+        assert!(color.x > 0.95 && color.y < 0.05 && color.z < 0.05);
+        assert_eq!((rec.u, rec.v), (0.01, 0.005));
+
+        let texture = VertexColorTexture { fallback: Color(Vec3::new(1., 0., 1.)) };
+        assert_eq!(texture.value_at(&rec).0, color);
+    }
+
+    #[test]
+    fn test_errors() {
+        let truncated = &binary(false)[..binary(false).len() - 2];
+        let cases: [(&[u8], &str); 7] = [
+            (b"PLY\n", "not a PLY file"),
+            (b"ply\nformat ascii 1.0\nelement vertex 1\nproperty half x\nend_header\n", "line 4: unknown type `half`"),
+            (b"ply\nformat ascii 1.0\nelement vertex 1\nproperty float x\nend_header\nnan0\n", "vertex 0: invalid Float `nan0`"),
+            (truncated, "face 0: unexpected end of file"),
+            (b"ply\nformat ascii 1.0\nelement vertex 0\nproperty float x\nelement face 0\nproperty list uchar int vertex_indices\nend_header\n",
+             "no triangles"),
+            (b"ply\nformat ascii 1.0\nelement vertex 3\nproperty float x\nelement face 1\nproperty list uchar int vertex_indices\nend_header\n0\n1\n2\n3 0 1 -1\n",
+             "face 0: invalid vertex index -1"),
+            (b"ply\nformat ascii 1.0\nelement vertex 3\nproperty float x\nelement face 1\nproperty list uchar float vertex_indices\nend_header\n0\n1\n2\n3 0 1.5 2\n",
+             "face 0: invalid vertex index 1.5"),
+        ];
+
+        for (bytes, message) in &cases {
+            match parse(bytes, material()) {
+                Err(e) => assert_eq!(&e, message),
+                Ok(_) => panic!("expected `{}`", message),
+            }
+        }
+    }
+}
@@ -0,0 +1,383 @@
+use std::f32::consts::PI;
+
+use rand::Rng;
+
+use crate::color::Color;
+use crate::hittable::HitRecord;
+use crate::material::{Material, reflect};
+use crate::microfacet::{Frame, Ggx, fresnel_dielectric};
+use crate::sampler::Sampler;
+use crate::sphere::random_unit_vector;
+use crate::texture::{SolidColor, Texture};
+use crate::vec3::{Point3, Vec3};
+
+// a number of a material, constant or read from one channel of a texture
+pub struct Parameter {
+    pub texture: Box<dyn Texture>,
+    // 0, 1 or 2 for red, green or blue
+    pub channel: usize,
+}
+
+impl Parameter {
+    pub fn constant(value: f32) -> Self {
+        Self::from_texture(Box::new(SolidColor::new_from_color(Color(Vec3::new(value, value, value)))), 0)
+    }
+
+    pub fn from_texture(texture: Box<dyn Texture>, channel: usize) -> Self {
+        Parameter { texture, channel }
+    }
+
+    fn at(&self, rec: &HitRecord) -> f32 {
+        self.texture.value_at(rec).0[self.channel].clamp(0., 1.)
+    }
+}
+
+// the Disney BSDF (Burley 2012 and 2015): a diffuse base with retro-reflection, sheen and a
+// subsurface look, under a GGX specular reflection and a clear coat, turning into a metal with
+// `metallic` and into rough glass with `transmission`; every parameter but the index of refraction
+// lies in [0, 1]
+pub struct Principled {
+    pub base_color: Box<dyn Texture>,
+    pub metallic: Parameter,
+    pub roughness: Parameter,
+    // the reflectance of dielectrics at normal incidence, 0.5 being the 4% of an index of 1.5
+    pub specular: Parameter,
+    // how much the dielectric reflection takes the hue of the base color
+    pub specular_tint: Parameter,
+    // stretches the highlights along the u of textured surfaces
+    pub anisotropic: Parameter,
+    // a grazing reflection for cloth
+    pub sheen: Parameter,
+    pub sheen_tint: Parameter,
+    // a second, colorless and sharper specular layer
+    pub clearcoat: Parameter,
+    pub clearcoat_gloss: Parameter,
+    // the part of the dielectric base refracting light through it, tinted by the base color
+    pub transmission: Parameter,
+    pub ior: f32,
+    // flattens the diffuse base like light scattering under the surface would
+    pub subsurface: Parameter,
+    pub emission: Box<dyn Texture>,
+    // tangent space normals encoded in [0, 1], x and y scaled by `normal_scale`
+    pub normal_map: Option<Box<dyn Texture>>,
+    pub normal_scale: f32,
+}
+
+impl Principled {
+    // a rough plastic of that color, with the default values of the other parameters
+    pub fn new(base_color: Box<dyn Texture>) -> Self {
+        Principled {
+            base_color,
+            metallic: Parameter::constant(0.),
+            roughness: Parameter::constant(0.5),
+            specular: Parameter::constant(0.5),
+            specular_tint: Parameter::constant(0.),
+            anisotropic: Parameter::constant(0.),
+            sheen: Parameter::constant(0.),
+            sheen_tint: Parameter::constant(0.5),
+            clearcoat: Parameter::constant(0.),
+            clearcoat_gloss: Parameter::constant(1.),
+            transmission: Parameter::constant(0.),
+            ior: 1.5,
+            subsurface: Parameter::constant(0.),
+            emission: Box::new(SolidColor::new_from_color(Color(Vec3::new(0., 0., 0.)))),
+            normal_map: None,
+            normal_scale: 1.,
+        }
+    }
+
+    fn shading_normal(&self, rec: &HitRecord, wo: Vec3) -> Vec3 {
+        let (map, (dpdu, dpdv)) = match (&self.normal_map, rec.tangents) {
+            (Some(map), Some(tangents)) => (map, tangents),
+            _ => return rec.normal,
+        };
+
+        // Gram-Schmidt the tangent against the normal, keeping the handedness of the uv mapping
+        let n = rec.normal;
+        let t = (dpdu - dpdu.dot(n) * n).normalize();
+        let b = if n.cross(t).dot(dpdv) < 0. { -n.cross(t) } else { n.cross(t) };
+
+        let c = map.value_at(rec).0;
+        let x = (2. * c.x - 1.) * self.normal_scale;
+        let y = (2. * c.y - 1.) * self.normal_scale;
+        let z = 2. * c.z - 1.;
+        let mapped = (x * t + y * b + z * n).normalize();
+        // a normal turned away from the viewer would show the back of the surface
+        if mapped.x.is_finite() && mapped.dot(wo) > 0. { mapped } else { n }
+    }
+
+    fn lobes(&self, rec: &HitRecord, wo: Vec3) -> Lobes {
+        let base_color = self.base_color.value_at(rec).0;
+        let metallic = self.metallic.at(rec);
+        let roughness = self.roughness.at(rec);
+        let transmission = (1. - metallic) * self.transmission.at(rec);
+
+        let lum = luminance(base_color);
+        let tint = if lum > 0. { base_color / lum } else { Vec3::new(1., 1., 1.) };
+        let white = Vec3::new(1., 1., 1.);
+        let dielectric0 = 0.08 * self.specular.at(rec) * lerp(white, tint, self.specular_tint.at(rec));
+
+        let aspect = (1. - 0.9 * self.anisotropic.at(rec)).sqrt();
+        let alpha = roughness * roughness;
+        let specular = Ggx { alpha_x: (alpha / aspect).max(1e-3), alpha_y: (alpha * aspect).max(1e-3) };
+
+        let normal = self.shading_normal(rec, wo);
+        let frame = Frame::new(normal, rec.tangents.map(|(dpdu, _)| dpdu));
+        let specular0 = lerp(dielectric0, base_color, metallic);
+        let clearcoat = self.clearcoat.at(rec);
+
+        // the diffuse and the specular reflection are sampled in proportion to the light they scatter
+        // straight back, keeping a few specular samples for the reflections of darker dielectrics
+        let diffuse = (1. - metallic) * (1. - transmission);
+        let reflectance = luminance(schlick(specular0, frame.to_local(wo).z)).max(0.1);
+        let weights = [diffuse * lum, (1. - transmission) * reflectance, 0.25 * clearcoat, transmission];
+
+        Lobes {
+            frame,
+            base_color,
+            roughness,
+            diffuse,
+            subsurface: self.subsurface.at(rec),
+            sheen: self.sheen.at(rec) * lerp(white, tint, self.sheen_tint.at(rec)),
+            specular,
+            specular0,
+            specular_weight: 1. - transmission,
+            clearcoat,
+            clearcoat_alpha: 0.1 + (0.001 - 0.1) * self.clearcoat_gloss.at(rec),
+            transmission,
+            eta: if rec.is_front_face { self.ior } else { 1. / self.ior },
+            weights,
+        }
+    }
+}
+
+// the parameters at a hit, in the frame of its shading normal
+struct Lobes {
+    frame: Frame,
+    base_color: Vec3,
+    roughness: f32,
+    diffuse: f32,
+    subsurface: f32,
+    sheen: Vec3,
+    specular: Ggx,
+    // the color of the specular reflection at normal incidence
+    specular0: Vec3,
+    specular_weight: f32,
+    clearcoat: f32,
+    clearcoat_alpha: f32,
+    transmission: f32,
+    // the index of refraction of the far side over the one of the side lit
+    eta: f32,
+    // the odds of sampling the diffuse, specular, clear coat and transmission lobes
+    weights: [f32; 4],
+}
+
+fn luminance(c: Vec3) -> f32 {
+    0.2126 * c.x + 0.7152 * c.y + 0.0722 * c.z
+}
+
+fn lerp(a: Vec3, b: Vec3, t: f32) -> Vec3 {
+    (1. - t) * a + t * b
+}
+
+fn schlick_weight(cosine: f32) -> f32 {
+    (1. - cosine.clamp(0., 1.)).powi(5)
+}
+
+fn schlick(specular0: Vec3, cosine: f32) -> Vec3 {
+    lerp(specular0, Vec3::new(1., 1., 1.), schlick_weight(cosine))
+}
+
+// the distribution of the clear coat, with a longer tail than GGX
+fn gtr1(cos_theta: f32, alpha: f32) -> f32 {
+    let a2 = alpha * alpha;
+    (a2 - 1.) / (PI * a2.ln() * (1. + (a2 - 1.) * cos_theta * cos_theta))
+}
+
+// the shadowing of the clear coat, fixed to the one of GGX of width 0.25
+const CLEARCOAT_SHADOWING: Ggx = Ggx { alpha_x: 0.25, alpha_y: 0.25 };
+
+impl Lobes {
+    fn eval(&self, wi: Vec3, wo: Vec3) -> Vec3 {
+        let mut f = Vec3::new(0., 0., 0.);
+        if wi.z > 0. {
+            let wh = (wi + wo).normalize();
+            let cos_d = wi.dot(wh);
+
+            if self.diffuse > 0. {
+                let (fl, fv) = (schlick_weight(wi.z), schlick_weight(wo.z));
+                let fd90 = 0.5 + 2. * cos_d * cos_d * self.roughness;
+                let retro = (1. + (fd90 - 1.) * fl) * (1. + (fd90 - 1.) * fv);
+                let fss90 = cos_d * cos_d * self.roughness;
+                let fss = (1. + (fss90 - 1.) * fl) * (1. + (fss90 - 1.) * fv);
+                let subsurface = 1.25 * (fss * (1. / (wi.z + wo.z) - 0.5) + 0.5);
+
+                let diffuse = (retro + self.subsurface * (subsurface - retro)) / PI * self.base_color;
+                f += self.diffuse * wi.z * (diffuse + schlick_weight(cos_d) * self.sheen);
+            }
+
+            let ggx = &self.specular;
+            f += self.specular_weight * ggx.d(wh) * ggx.g(wo, wi) / (4. * wo.z) * schlick(self.specular0, cos_d);
+
+            if self.clearcoat > 0. {
+                let fresnel = 0.04 + 0.96 * schlick_weight(cos_d);
+                let value = gtr1(wh.z, self.clearcoat_alpha) * CLEARCOAT_SHADOWING.g(wo, wi) * fresnel / (4. * wo.z);
+                f += 0.25 * self.clearcoat * value * Vec3::new(1., 1., 1.);
+            }
+        }
+
+        if self.transmission > 0. {
+            let glass = self.transmission * self.specular.dielectric_eval(wi, wo, self.eta, |cos| fresnel_dielectric(cos, self.eta));
+            f += if wi.z < 0. { glass * self.base_color } else { glass * Vec3::new(1., 1., 1.) };
+        }
+        f
+    }
+
+    fn pdf(&self, wi: Vec3, wo: Vec3) -> f32 {
+        let total: f32 = self.weights.iter().sum();
+        let [diffuse, specular, clearcoat, transmission] = self.weights;
+
+        let mut pdf = 0.;
+        if wi.z > 0. {
+            let wh = (wi + wo).normalize();
+            pdf += diffuse * wi.z / PI;
+            pdf += specular * self.specular.d_visible(wo, wh) / (4. * wo.dot(wh));
+            pdf += clearcoat * gtr1(wh.z, self.clearcoat_alpha) * wh.z / (4. * wo.dot(wh));
+        }
+        if transmission > 0. {
+            pdf += transmission * self.specular.dielectric_pdf(wi, wo, self.eta, |cos| fresnel_dielectric(cos, self.eta));
+        }
+        pdf / total
+    }
+
+    fn sample(&self, wo: Vec3, rng: &mut Sampler) -> Option<Vec3> {
+        let total: f32 = self.weights.iter().sum();
+        let mut pick = rng.gen::<f32>() * total;
+        let lobe = self.weights.iter().position(|&w| { pick -= w; pick < 0. }).unwrap_or(3);
+
+        let wi = match lobe {
+            0 => {
+                let direction = Vec3::new(0., 0., 1.) + random_unit_vector(rng);
+                if direction.length_squared() > 1e-8 { direction.normalize() } else { Vec3::new(0., 0., 1.) }
+            },
+            1 => reflect(&-wo, &self.specular.sample_visible(wo, rng.gen(), rng.gen())),
+            2 => {
+                let a2 = self.clearcoat_alpha * self.clearcoat_alpha;
+                let cos_theta = ((1. - a2.powf(1. - rng.gen::<f32>())) / (1. - a2)).max(0.).sqrt();
+                let sin_theta = (1. - cos_theta * cos_theta).max(0.).sqrt();
+                let phi = rng.gen_range(0., 2. * PI);
+                let wh = Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta);
+                reflect(&-wo, &if wh.dot(wo) < 0. { -wh } else { wh })
+            },
+            _ => self.specular.dielectric_sample(wo, self.eta, |cos| fresnel_dielectric(cos, self.eta), [rng.gen(), rng.gen(), rng.gen()])?,
+        };
+        Some(wi)
+    }
+}
+
+impl Material for Principled {
+    fn emitted(&self, u: f32, v: f32, p: &Point3) -> Color {
+        self.emission.value(u, v, p)
+    }
+
+    fn emits(&self) -> bool {
+        !self.emission.is_black()
+    }
+
+    fn eval(&self, rec: &HitRecord, wi: Vec3, wo: Vec3) -> Color {
+        let lobes = self.lobes(rec, wo);
+        let (wi_local, wo_local) = (lobes.frame.to_local(wi), lobes.frame.to_local(wo));
+        // the shading normal must not let light through the side of the surface it comes from
+        if wo_local.z <= 0. || (wi_local.z > 0.) != (wi.dot(rec.normal) > 0.) {
+            return Color(Vec3::new(0., 0., 0.));
+        }
+        Color(lobes.eval(wi_local, wo_local))
+    }
+
+    // picks a lobe by its weight, then weighs the direction against every lobe that could have picked it
+    fn sample(&self, rec: &HitRecord, wo: Vec3, rng: &mut Sampler) -> Option<(Vec3, Color, f32)> {
+        let lobes = self.lobes(rec, wo);
+        let wo_local = lobes.frame.to_local(wo);
+        if wo_local.z <= 0. || lobes.weights.iter().sum::<f32>() <= 0. {
+            return None;
+        }
+
+        let wi = lobes.frame.to_world(lobes.sample(wo_local, rng)?);
+        let pdf = self.pdf(rec, wi, wo);
+        if pdf <= 0. {
+            return None;
+        }
+        Some((wi, Color(self.eval(rec, wi, wo).0 / pdf), pdf))
+    }
+
+    fn pdf(&self, rec: &HitRecord, wi: Vec3, wo: Vec3) -> f32 {
+        let lobes = self.lobes(rec, wo);
+        let (wi_local, wo_local) = (lobes.frame.to_local(wi), lobes.frame.to_local(wo));
+        if wo_local.z <= 0. || (wi_local.z > 0.) != (wi.dot(rec.normal) > 0.) || lobes.weights.iter().sum::<f32>() <= 0. {
+            return 0.;
+        }
+        lobes.pdf(wi_local, wo_local)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use rand::SeedableRng;
+    use std::sync::Arc;
+
+    fn material(configure: impl FnOnce(&mut Principled)) -> Principled {
+        let mut principled = Principled::new(Box::new(SolidColor::new_from_color(Color(Vec3::new(0.8, 0.5, 0.3)))));
+        configure(&mut principled);
+        principled
+    }
+
+    // the sampled directions are weighted by eval over pdf, the density integrates to one over every
+    // direction but the ones lost below the surface, and no more light leaves than arrives
+    #[test]
+    fn test_lobes_sample_eval_and_pdf_agree() {
+        let mut rng = Sampler::seed_from_u64(0);
+        let lambertian: Arc<dyn Material> = Arc::new(crate::material::Lambertian::new_from_color(Color(Vec3::new(0.5, 0.5, 0.5))));
+        let rec = HitRecord::new(1., Point3::new(0., 0., 0.), 0., 0., true, Vec3::new(0., 1., 0.), &lambertian);
+        let wo = Vec3::new(1., 2., -0.5).normalize();
+
+        let materials = vec![
+            ("plastic", material(|_| {})),
+            ("metal", material(|m| { m.metallic = Parameter::constant(1.); m.roughness = Parameter::constant(0.3); })),
+            ("brushed", material(|m| { m.metallic = Parameter::constant(0.8); m.anisotropic = Parameter::constant(0.9); })),
+            ("cloth", material(|m| { m.sheen = Parameter::constant(1.); m.subsurface = Parameter::constant(0.7); m.roughness = Parameter::constant(0.9); })),
+            ("lacquer", material(|m| { m.clearcoat = Parameter::constant(1.); m.clearcoat_gloss = Parameter::constant(0.7); })),
+            ("glass", material(|m| { m.transmission = Parameter::constant(1.); m.roughness = Parameter::constant(0.5); })),
+        ];
+        for (name, principled) in &materials {
+            let n = 100000;
+            let mut energy = 0f64;
+            for _ in 0..n {
+                if let Some((wi, weight, pdf)) = principled.sample(&rec, wo, &mut rng) {
+                    assert!((pdf - principled.pdf(&rec, wi, wo)).abs() < 1e-3 * pdf, "{}", name);
+                    assert!((weight.0 * pdf - principled.eval(&rec, wi, wo).0).length() < 1e-3 * pdf, "{}", name);
+                    // undo the scaling of radiance by the change of medium
+                    energy += (luminance(weight.0) * if wi.y < 0. { 1.5 * 1.5 } else { 1. }) as f64;
+                }
+            }
+            let energy = energy / n as f64;
+            assert!(energy > 0.1 && energy < 1.01, "{}: energy {}", name, energy);
+
+            let total: f64 = (0..10 * n).map(|_| principled.pdf(&rec, random_unit_vector(&mut rng), wo) as f64).sum();
+            let integral = 4. * std::f64::consts::PI * total / (10 * n) as f64;
+            assert!(integral > 0.85 && integral < 1.02, "{}: pdf integrates to {}", name, integral);
+        }
+    }
+
+    #[test]
+    fn test_textured_parameter() {
+        let lambertian: Arc<dyn Material> = Arc::new(crate::material::Lambertian::new_from_color(Color(Vec3::new(0.5, 0.5, 0.5))));
+        let rec = HitRecord::new(1., Point3::new(0., 0., 0.), 0., 0., true, Vec3::new(0., 1., 0.), &lambertian);
+        let packed = Box::new(SolidColor::new_from_color(Color(Vec3::new(0.1, 0.4, 2.))));
+
+        assert_eq!(Parameter::from_texture(packed, 1).at(&rec), 0.4);
+        assert_eq!(Parameter::from_texture(Box::new(SolidColor::new_from_color(Color(Vec3::new(0., 0., 2.)))), 2).at(&rec), 1.);
+        assert_eq!(Parameter::constant(0.3).at(&rec), 0.3);
+    }
+}
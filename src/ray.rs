@@ -9,16 +9,17 @@ pub struct Ray {
 impl Ray {
     pub fn new(origin: Point3, direction: Vec3, time: f32) -> Self {
         Ray {
-            origin: origin,
-            direction: direction,
-            time: time,
+            origin,
+            direction,
+            time,
         }
     }
 
+    #[cfg(test)]
     pub fn new_without_move(origin: Point3, direction: Vec3) -> Self {
         Ray {
-            origin: origin,
-            direction: direction,
+            origin,
+            direction,
             time: 0.,
         }
     }
@@ -0,0 +1,314 @@
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use rand::Rng;
+use rayon::prelude::*;
+
+use crate::camera::Camera;
+use crate::color::Color;
+use crate::film::{Film, Surface};
+use crate::hittable::{HitRecord, Hittable};
+use crate::hittable_list::HittableList;
+use crate::ray::Ray;
+use crate::sampler::{Sampler, pixel_sampler};
+use crate::vec3::Vec3;
+
+pub struct RenderSettings {
+    pub image_width: usize,
+    pub image_height: usize,
+    pub samples_per_pixel: u32,
+    pub max_depth: u32,
+    pub background: Color,
+    // number of worker threads, 0 uses every core
+    pub threads: usize,
+    // the same seed renders the same image whatever the number of threads
+    pub seed: u64,
+    // also record the surfaces the camera rays first hit, at the cost of intersecting them twice
+    pub surfaces: bool,
+}
+
+// how far from t = 1 a sampled light point is looked for again, relative to the distance to it;
+// shadow rays stop as far before it
+const LIGHT_EPSILON: f32 = 1e-3;
+
+// radiance along r, sampling the `lights` at every diffuse bounce besides following the scattered rays
+pub fn ray_color(r: &Ray, background: Color, w: &dyn Hittable, lights: &HittableList, depth: u32, rng: &mut Sampler) -> Color {
+    radiance(r, background, w, lights, depth, None, rng)
+}
+
+// `bsdf_pdf` is the density with which the previous bounce scattered along r when it sampled the lights
+// too, the light found here by chance is then weighted against the one found by light sampling
+fn radiance(r: &Ray, background: Color, w: &dyn Hittable, lights: &HittableList, depth: u32, bsdf_pdf: Option<f32>,
+            rng: &mut Sampler) -> Color {
+    if depth == 0 {
+        return Color(Vec3::new(0., 0., 0.));
+    }
+
+    let rec = match w.hit(r, 0.001, f32::MAX) {
+        Some(rec) => rec,
+        None => return background,
+    };
+
+    // whatever reaches rec from its inside crossed the material on the way back along r
+    let absorbed = rec.material.interior_transmittance(&rec, rec.t * r.direction.length()).0;
+
+    let mut emitted = rec.material.emitted(rec.u, rec.v, &rec.p);
+    if let (Some(bsdf_pdf), true) = (bsdf_pdf, emitted.0 != Vec3::new(0., 0., 0.)) {
+        let light_pdf = lights.pdf_value(r, rec.t * (1. - LIGHT_EPSILON), rec.t * (1. + LIGHT_EPSILON));
+        emitted = Color(emitted.0 * power_heuristic(bsdf_pdf, light_pdf));
+    }
+
+    let wo = -r.direction.normalize();
+    let (wi, weight, pdf) = match rec.material.sample(&rec, wo, rng) {
+        Some(sample) => sample,
+        None => return Color(absorbed * emitted.0),
+    };
+    let scattered = Ray::new(rec.p, wi, r.time);
+
+    // the last bounce would sample lights that the scattered ray cannot reach anymore
+    if rec.material.is_delta() || pdf <= 0. || depth == 1 || lights.objects.is_empty() {
+        let indirect = radiance(&scattered, background, w, lights, depth - 1, None, rng);
+        return Color(absorbed * (emitted.0 + weight.0 * indirect.0));
+    }
+
+    let direct = sample_light(r, &rec, wo, w, lights, rng);
+    let indirect = radiance(&scattered, background, w, lights, depth - 1, Some(pdf), rng);
+    Color(absorbed * (emitted.0 + direct.0 + weight.0 * indirect.0))
+}
+
+// the light reaching rec from a random point of the lights through whatever lies in between,
+// weighted against finding the same point by scattering
+fn sample_light(r_in: &Ray, rec: &HitRecord, wo: Vec3, w: &dyn Hittable, lights: &HittableList,
+                rng: &mut Sampler) -> Color {
+    let black = Color(Vec3::new(0., 0., 0.));
+    let to_light = lights.random(&rec.p, rng);
+    let shadow = Ray::new(rec.p, to_light, r_in.time);
+
+    let light = match lights.hit(&shadow, 1. - LIGHT_EPSILON, 1. + LIGHT_EPSILON) {
+        Some(light) => light,
+        None => return black,
+    };
+    let light_pdf = lights.pdf_value(&shadow, 1. - LIGHT_EPSILON, 1. + LIGHT_EPSILON);
+    let wi = to_light.normalize();
+    let bsdf = rec.material.eval(rec, wi, wo);
+    if light_pdf <= 0. || bsdf.0 == Vec3::new(0., 0., 0.) {
+        return black;
+    }
+
+    // the same offset from the surface as scattered rays, whose directions are about unit long
+    let transmittance = w.transmittance(&shadow, 0.001 / to_light.length(), 1. - LIGHT_EPSILON);
+    if transmittance <= 0. {
+        return black;
+    }
+
+    let emitted = light.material.emitted(light.u, light.v, &light.p);
+    let weight = power_heuristic(light_pdf, rec.material.pdf(rec, wi, wo)) * transmittance / light_pdf;
+    Color(bsdf.0 * emitted.0 * weight)
+}
+
+// the weight of a sample drawn with density `pdf` when `other_pdf` could have drawn it too
+fn power_heuristic(pdf: f32, other_pdf: f32) -> f32 {
+    pdf * pdf / (pdf * pdf + other_pdf * other_pdf)
+}
+
+fn render_scanline(world: &dyn Hittable, lights: &HittableList, cam: &Camera, settings: &RenderSettings,
+                   j: usize) -> Vec<(Color, Surface)> {
+    (0..settings.image_width)
+        .map(|i| {
+            let mut rng = pixel_sampler(settings.seed, i, j, settings.image_width);
+            let mut v_pixel_color = Vec3::new(0., 0., 0.);
+            let (mut hits, mut depth, mut normal) = (0, 0., Vec3::new(0., 0., 0.));
+            for _s in 0..settings.samples_per_pixel {
+                let u = ((i as f32) + rng.gen::<f32>()) / ((settings.image_width - 1) as f32);
+                let v = ((j as f32) + rng.gen::<f32>()) / (settings.image_height - 1) as f32;
+                let r = cam.get_ray(u, v, &mut rng);
+                if settings.surfaces {
+                    if let Some(rec) = world.hit(&r, 0.001, f32::MAX) {
+                        hits += 1;
+                        depth += rec.t * r.direction.length();
+                        normal += rec.normal;
+                    }
+                }
+                v_pixel_color += ray_color(&r, settings.background, world, lights, settings.max_depth, &mut rng).0;
+            }
+            let surface = Surface {
+                alpha: hits as f32 / settings.samples_per_pixel as f32,
+                depth: if hits > 0 { depth / hits as f32 } else { f32::INFINITY },
+                normal: if normal.length() > 0. { normal.normalize() } else { normal },
+            };
+            (Color(v_pixel_color / settings.samples_per_pixel as f32), surface)
+        })
+        .collect()
+}
+
+// renders scanlines in parallel into a film of the average of the samples of every pixel, the `lights`
+// being emitting objects of the world to sample
+pub fn render(world: &dyn Hittable, lights: &HittableList, cam: &Camera, settings: &RenderSettings) -> Film {
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(settings.threads)
+        .build()
+        .unwrap();
+    let remaining = AtomicUsize::new(settings.image_height);
+
+    let scanlines: Vec<Vec<(Color, Surface)>> = pool.install(|| {
+        (0..settings.image_height)
+            .into_par_iter()
+            .rev()
+            .map(|j| {
+                let scanline = render_scanline(world, lights, cam, settings, j);
+
+                let left = remaining.fetch_sub(1, Ordering::Relaxed) - 1;
+                let stderr = io::stderr();
+                let mut err_handle = stderr.lock();
+                write!(err_handle, "\rScanlines remaining: {} ", left).unwrap();
+                err_handle.flush().unwrap();
+
+                scanline
+            })
+            .collect()
+    });
+
+    let (pixels, surfaces): (Vec<Color>, Vec<Surface>) = scanlines.into_iter().flatten().unzip();
+    let film = Film::from_pixels(settings.image_width, settings.image_height, pixels);
+    if settings.surfaces {
+        film.with_surfaces(surfaces)
+    } else {
+        film
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::aarect::{XYRect, XZRect};
+    use crate::bvh::BvhBuilder;
+    use crate::material::{Dielectric, DiffuseLight, Lambertian, Material};
+    use crate::sphere::Sphere;
+    use rand::SeedableRng;
+    use std::sync::Arc;
+
+    fn render_random_scene(seed: u64, threads: usize) -> Vec<Vec3> {
+        let world = crate::random_scene(&mut Sampler::seed_from_u64(seed));
+        let (world, _) = BvhBuilder::Sah.build(&world, 0., 1.);
+        let cam = Camera::new(Vec3::new(13., 2., 3.), Vec3::new(0., 0., 0.), Vec3::new(0., 1., 0.),
+                              20., 16. / 9., 0.1, 10., 0., 1.);
+        let settings = RenderSettings {
+            image_width: 32,
+            image_height: 18,
+            samples_per_pixel: 4,
+            max_depth: 10,
+            background: Color(Vec3::new(0.7, 0.8, 1.)),
+            threads,
+            seed,
+            surfaces: false,
+        };
+
+        let film = render(world.as_ref(), &HittableList { objects: Vec::new() }, &cam, &settings);
+        (0..film.height).flat_map(|y| (0..film.width).map(move |x| (x, y))).map(|(x, y)| film.pixel(x, y).0).collect()
+    }
+
+    #[test]
+    fn test_surfaces() {
+        let material: Arc<dyn Material> = Arc::new(Lambertian::new_from_color(Color(Vec3::new(0.5, 0.5, 0.5))));
+        let world = HittableList { objects: vec![Arc::new(Sphere { center: Vec3::new(0., 0., 0.), radius: 1., material })] };
+        let cam = Camera::new(Vec3::new(0., 0., -5.), Vec3::new(0., 0., 0.), Vec3::new(0., 1., 0.), 30., 1., 0., 5., 0., 1.);
+        let settings = RenderSettings {
+            image_width: 9,
+            image_height: 9,
+            samples_per_pixel: 4,
+            max_depth: 2,
+            background: Color(Vec3::new(0., 0., 0.)),
+            threads: 1,
+            seed: 0,
+            surfaces: true,
+        };
+
+        let film = render(&world, &HittableList { objects: Vec::new() }, &cam, &settings);
+        let center = film.surface(4, 4).unwrap();
+        assert_eq!(1., center.alpha);
+        assert!((center.depth - 4.).abs() < 0.05, "{}", center.depth);
+        assert!(center.normal.z < -0.95 && (center.normal.length() - 1.).abs() < 1e-5, "{:?}", center.normal);
+        let corner = film.surface(0, 0).unwrap();
+        assert_eq!((0., f32::INFINITY, Vec3::new(0., 0., 0.)), (corner.alpha, corner.depth, corner.normal));
+    }
+
+    #[test]
+    fn test_same_seed_same_image() {
+        assert_eq!(render_random_scene(7, 1), render_random_scene(7, 1));
+    }
+
+    #[test]
+    fn test_threads_do_not_change_image() {
+        assert_eq!(render_random_scene(7, 1), render_random_scene(7, 4));
+    }
+
+    #[test]
+    fn test_seed_changes_image() {
+        assert_ne!(render_random_scene(7, 1), render_random_scene(8, 1));
+    }
+
+    // mean and variance of the direct light on a point of a floor under a small lamp
+    fn direct_light(sample_lights: bool) -> (f32, f32) {
+        let floor: Arc<dyn Material> = Arc::new(Lambertian::new_from_color(Color(Vec3::new(0.5, 0.5, 0.5))));
+        let glow: Arc<dyn Material> = Arc::new(DiffuseLight::new(Color(Vec3::new(20., 20., 20.))));
+        let lamp: Arc<dyn Hittable> = Arc::new(XZRect { x0: 0.5, x1: 1., z0: -0.25, z1: 0.25, k: 1., material: glow });
+        let world = HittableList {
+            objects: vec![Arc::new(XZRect { x0: -10., x1: 10., z0: -10., z1: 10., k: 0., material: floor }), lamp.clone()],
+        };
+        let lights = HittableList { objects: if sample_lights { vec![lamp] } else { Vec::new() } };
+
+        let mut rng = Sampler::seed_from_u64(5);
+        let r = Ray::new_without_move(Vec3::new(0., 1., -2.), Vec3::new(0., -1., 2.));
+        let n = 100000;
+        let samples: Vec<f32> = (0..n)
+            .map(|_| ray_color(&r, Color(Vec3::new(0., 0., 0.)), &world, &lights, 2, &mut rng).0.x)
+            .collect();
+        let mean = samples.iter().sum::<f32>() / n as f32;
+        let variance = samples.iter().map(|s| (s - mean) * (s - mean)).sum::<f32>() / n as f32;
+        (mean, variance)
+    }
+
+    #[test]
+    fn test_light_sampling_converges_to_the_same_light() {
+        let (scattered, scattered_variance) = direct_light(false);
+        let (sampled, sampled_variance) = direct_light(true);
+
+        assert!((sampled - scattered).abs() < 0.03 * scattered, "{} != {}", sampled, scattered);
+        assert!(sampled_variance < 0.1 * scattered_variance, "{} !< {}", sampled_variance, scattered_variance);
+    }
+
+    #[test]
+    fn test_glass_absorbs_along_the_path_inside() {
+        let mut glass = Dielectric::new(1.5);
+        glass.absorption = Color(Vec3::new(0., 0.5, 2.));
+        let glow: Arc<dyn Material> = Arc::new(DiffuseLight::new(Color(Vec3::new(1., 1., 1.))));
+        // a ball of glass 1 wide in front of a wide lamp
+        let world = HittableList {
+            objects: vec![
+                Arc::new(Sphere { center: Vec3::new(0., 0., 0.5), radius: 0.5, material: Arc::new(glass) }),
+                Arc::new(XYRect { x0: -5., x1: 5., y0: -5., y1: 5., k: 3., material: glow }),
+            ],
+        };
+
+        let mut rng = Sampler::seed_from_u64(6);
+        let r = Ray::new_without_move(Vec3::new(0., 0., -1.), Vec3::new(0., 0., 1.));
+        let n = 20000;
+        let mut total = [0f64; 3];
+        for _ in 0..n {
+            let color = ray_color(&r, Color(Vec3::new(0., 0., 0.)), &world, &HittableList { objects: Vec::new() }, 50, &mut rng);
+            for (channel, total) in total.iter_mut().enumerate() {
+                *total += color.0[channel] as f64;
+            }
+        }
+
+        // through both sides along the diameter, after any number of round trips inside
+        let reflectance = 0.04f64;
+        for (channel, &absorption) in [0f64, 0.5, 2.].iter().enumerate() {
+            let transmittance = (-absorption).exp();
+            let expected = (1. - reflectance).powi(2) * transmittance / (1. - (reflectance * transmittance).powi(2));
+            let mean = total[channel] / n as f64;
+            assert!((mean - expected).abs() < 0.01 * expected, "channel {}: {} != {}", channel, mean, expected);
+        }
+    }
+}
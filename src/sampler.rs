@@ -0,0 +1,23 @@
+use rand::SeedableRng;
+use rand_pcg::Pcg32;
+
+use crate::ray::Ray;
+
+// the one generator every random decision of a render is drawn from
+pub type Sampler = Pcg32;
+
+// each pixel gets its own stream, so the image does not depend on which thread renders it
+pub fn pixel_sampler(seed: u64, i: usize, j: usize, image_width: usize) -> Sampler {
+    let index = (j * image_width + i) as u64;
+    Sampler::seed_from_u64(seed ^ index.wrapping_mul(0x9e37_79b9_7f4a_7c15))
+}
+
+// hittables get no sampler, so the ones that need random numbers, like volumes, seed one from the ray
+// they are asked about; it is as reproducible as the ray itself
+pub fn ray_sampler(r: &Ray) -> Sampler {
+    let bits = [r.origin.x, r.origin.y, r.origin.z, r.direction.x, r.direction.y, r.direction.z, r.time];
+    let hash = bits.iter().fold(0xcbf2_9ce4_8422_2325u64, |hash, x| {
+        (hash ^ x.to_bits() as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    });
+    Sampler::seed_from_u64(hash)
+}
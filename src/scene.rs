@@ -0,0 +1,1113 @@
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use serde::Deserialize;
+
+use crate::aabb::AABB;
+use crate::aarect::{XYRect, XZRect, YZRect};
+use crate::bvh::{BvhBuilder, BvhStats};
+use crate::camera::Camera;
+use crate::constant_medium::ConstantMedium;
+use crate::color::Color;
+use crate::hittable::{self, Hittable, Transformed};
+use crate::gltf_import::{self, GltfError};
+use crate::heterogeneous_medium::HeterogeneousMedium;
+use crate::hittable_list::HittableList;
+use crate::obj::{self, ObjError};
+use crate::ply::{self, PlyError};
+use crate::matrix::{Matrix4, Transform};
+use crate::material::{Dielectric, DiffuseLight, Dispersion, Isotropic, Lambertian, Material, Metal, ThinFilm, conductor_ior, glass_dispersion};
+use crate::microfacet::Ggx;
+use crate::perlin::Perlin;
+use crate::principled::{Parameter, Principled};
+use crate::r#box;
+use crate::sampler::Sampler;
+use crate::sphere::{MovingSphere, Sphere};
+use crate::triangle::{Mesh, Triangle, TriangleMesh};
+use crate::texture::{CheckerTexture, ImageTexture, NoiseTexture, SolidColor, Texture, VertexColorTexture};
+use crate::vec3::Vec3;
+use crate::volume::{DensityField, GridDensity, NoiseDensity};
+use crate::volume_file::{self, VolumeError};
+
+// everything needed to render an image, either built in or loaded from a scene file
+pub struct Scene {
+    // the objects of the scene under the bounding volume hierarchy they were loaded with
+    pub world: Arc<dyn Hittable>,
+    pub bvh_stats: BvhStats,
+    // the emitting objects of the world that are sampled directly
+    pub lights: HittableList,
+    pub camera: Camera,
+    pub background: Color,
+    pub image_width: usize,
+    pub image_height: usize,
+    pub samples_per_pixel: u32,
+}
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io(PathBuf, io::Error),
+    Parse(PathBuf, toml::de::Error),
+    Image(PathBuf, image::ImageError),
+    UnknownMaterial(String),
+    UnknownGeometry(String),
+    // "object <index>" or "geometry `<name>`"
+    MissingMaterial(String),
+    // an image too small for the camera to spread its rays over, or without samples
+    InvalidImage(String),
+    InvalidMaterial(String),
+    InvalidMesh(String),
+    InvalidVolume(String),
+    // the object whose transforms flatten it, named like above
+    InvalidTransform(String),
+    // the emitting object, named like above, whose shape has no directions to sample it by
+    InvalidLight(String),
+    Obj(ObjError),
+    Ply(PlyError),
+    Gltf(GltfError),
+    Volume(VolumeError),
+}
+
+impl Display for SceneError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneError::Io(path, e) => write!(f, "{}: {}", path.display(), e),
+            SceneError::Parse(path, e) => write!(f, "{}: {}", path.display(), e),
+            SceneError::Image(path, e) => write!(f, "{}: {}", path.display(), e),
+            SceneError::UnknownMaterial(name) => write!(f, "unknown material `{}`", name),
+            SceneError::UnknownGeometry(name) => write!(f, "unknown geometry `{}`", name),
+            SceneError::MissingMaterial(name) => write!(f, "{} has no material", name),
+            SceneError::InvalidImage(message) => write!(f, "invalid image: {}", message),
+            SceneError::InvalidMaterial(message) => write!(f, "invalid material: {}", message),
+            SceneError::InvalidMesh(message) => write!(f, "invalid mesh: {}", message),
+            SceneError::InvalidVolume(message) => write!(f, "invalid volume: {}", message),
+            SceneError::InvalidTransform(name) => write!(f, "the transforms of {} are not invertible", name),
+            SceneError::InvalidLight(name) => write!(f, "{} emits light, but its shape cannot be sampled as a light", name),
+            SceneError::Obj(e) => e.fmt(f),
+            SceneError::Ply(e) => e.fmt(f),
+            SceneError::Gltf(e) => e.fmt(f),
+            SceneError::Volume(e) => e.fmt(f),
+        }
+    }
+}
+
+impl Error for SceneError {}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SceneDesc {
+    #[serde(default)]
+    background: [f32; 3],
+    #[serde(default)]
+    image: ImageDesc,
+    camera: CameraDesc,
+    // sorted, so noise textures draw from the sampler in the same order on every load
+    #[serde(default)]
+    materials: BTreeMap<String, MaterialDesc>,
+    // built once and shared by every `instance` object naming them
+    #[serde(default)]
+    geometries: BTreeMap<String, ObjectDesc>,
+    #[serde(default)]
+    objects: Vec<ObjectDesc>,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ImageDesc {
+    width: usize,
+    samples_per_pixel: u32,
+}
+
+impl Default for ImageDesc {
+    fn default() -> Self {
+        ImageDesc {
+            width: 400,
+            samples_per_pixel: 100,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CameraDesc {
+    look_from: [f32; 3],
+    look_at: [f32; 3],
+    #[serde(default = "default_vup")]
+    vup: [f32; 3],
+    // vertical field of view in degrees
+    vfov: f32,
+    #[serde(default = "default_aspect_ratio")]
+    aspect_ratio: f32,
+    #[serde(default)]
+    aperture: f32,
+    #[serde(default = "default_focus_dist")]
+    focus_dist: f32,
+    #[serde(default)]
+    time0: f32,
+    #[serde(default = "default_time1")]
+    time1: f32,
+}
+
+fn default_vup() -> [f32; 3] {
+    [0., 1., 0.]
+}
+
+fn default_aspect_ratio() -> f32 {
+    16. / 9.
+}
+
+fn default_focus_dist() -> f32 {
+    10.
+}
+
+fn default_time1() -> f32 {
+    1.
+}
+
+// either a plain color or a table with a `type`
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TextureDesc {
+    Color([f32; 3]),
+    Pattern(PatternDesc),
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum PatternDesc {
+    Checker { odd: [f32; 3], even: [f32; 3] },
+    Noise { scale: f32 },
+    // relative to the scene file
+    Image { path: PathBuf },
+    // the colors of the vertices of a mesh, `fallback` on other shapes
+    VertexColor { #[serde(default)] fallback: [f32; 3] },
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PrincipledDesc {
+    base_color: TextureDesc,
+    metallic: Option<ParameterDesc>,
+    roughness: Option<ParameterDesc>,
+    specular: Option<ParameterDesc>,
+    specular_tint: Option<ParameterDesc>,
+    anisotropic: Option<ParameterDesc>,
+    sheen: Option<ParameterDesc>,
+    sheen_tint: Option<ParameterDesc>,
+    clearcoat: Option<ParameterDesc>,
+    clearcoat_gloss: Option<ParameterDesc>,
+    transmission: Option<ParameterDesc>,
+    subsurface: Option<ParameterDesc>,
+    ior: Option<f32>,
+    emission: Option<TextureDesc>,
+    normal_map: Option<TextureDesc>,
+    normal_scale: Option<f32>,
+}
+
+// a thin film coating a conductor or a dielectric, `thickness` in nanometers scaled by the red channel
+// of the `thickness_map`
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FilmDesc {
+    ior: f32,
+    thickness: f32,
+    thickness_map: Option<TextureDesc>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SellmeierDesc {
+    b: [f32; 3],
+    // in square micrometers
+    c: [f32; 3],
+}
+
+// either a number or one `channel` of a texture, 0, 1 and 2 being red, green and blue
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ParameterDesc {
+    Value(f32),
+    Texture { texture: TextureDesc, #[serde(default)] channel: usize },
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum MaterialDesc {
+    Lambertian { albedo: TextureDesc },
+    Metal { albedo: [f32; 3], #[serde(default)] fuzz: f32 },
+    // a measured `metal`, or one of complex index of refraction `eta` + i`k`, anisotropic when it has a
+    // `roughness_v` along the v direction of its surface
+    Conductor {
+        metal: Option<String>,
+        eta: Option<[f32; 3]>,
+        k: Option<[f32; 3]>,
+        #[serde(default)]
+        roughness: f32,
+        roughness_v: Option<f32>,
+        film: Option<FilmDesc>,
+    },
+    // of index `ref_idx`, or dispersive with the Sellmeier coefficients of a named `glass`, the `a` and
+    // `b` of `cauchy` or its own `sellmeier` ones; frosted when rough, and tinted by the `absorption`
+    // of every unit of length light travels inside
+    Dielectric {
+        ref_idx: Option<f32>,
+        glass: Option<String>,
+        cauchy: Option<[f32; 2]>,
+        sellmeier: Option<SellmeierDesc>,
+        #[serde(default)]
+        roughness: f32,
+        #[serde(default)]
+        absorption: [f32; 3],
+        film: Option<FilmDesc>,
+    },
+    // the Disney BSDF, the parameters left out taking their default values
+    Principled(Box<PrincipledDesc>),
+    DiffuseLight { emit: TextureDesc },
+    // phase function of constant media
+    Isotropic { albedo: TextureDesc },
+}
+
+#[derive(Deserialize)]
+#[serde(try_from = "ObjectFields")]
+struct ObjectDesc {
+    shape: ShapeDesc,
+    // optional for OBJ and glTF models and instances, where it overrides the materials of the model
+    material: Option<String>,
+    // applied in the order they are listed
+    transforms: Vec<TransformDesc>,
+}
+
+// a flattened shape cannot deny unknown fields, serde handing it every key of the object, so the keys
+// left over are gathered and the shape is read from them alone
+#[derive(Deserialize)]
+struct ObjectFields {
+    material: Option<String>,
+    #[serde(default)]
+    transforms: Vec<TransformDesc>,
+    #[serde(flatten)]
+    shape: toml::Table,
+}
+
+impl TryFrom<ObjectFields> for ObjectDesc {
+    type Error = toml::de::Error;
+
+    fn try_from(fields: ObjectFields) -> Result<Self, Self::Error> {
+        Ok(ObjectDesc {
+            shape: ShapeDesc::deserialize(toml::Value::Table(fields.shape))?,
+            material: fields.material,
+            transforms: fields.transforms,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum ShapeDesc {
+    Sphere { center: [f32; 3], radius: f32 },
+    MovingSphere {
+        center0: [f32; 3],
+        center1: [f32; 3],
+        #[serde(default)]
+        time0: f32,
+        #[serde(default = "default_time1")]
+        time1: f32,
+        radius: f32,
+    },
+    XyRect { x0: f32, x1: f32, y0: f32, y1: f32, k: f32 },
+    XzRect { x0: f32, x1: f32, z0: f32, z1: f32, k: f32 },
+    YzRect { y0: f32, y1: f32, z0: f32, z1: f32, k: f32 },
+    Box { p0: [f32; 3], p1: [f32; 3] },
+    Triangle {
+        vertices: [[f32; 3]; 3],
+        normals: Option<[[f32; 3]; 3]>,
+        uvs: Option<[[f32; 2]; 3]>,
+    },
+    // indexed triangles sharing their vertices
+    Mesh {
+        positions: Vec<[f32; 3]>,
+        #[serde(default)]
+        normals: Vec<[f32; 3]>,
+        #[serde(default)]
+        uvs: Vec<[f32; 2]>,
+        indices: Vec<[u32; 3]>,
+    },
+    // Wavefront OBJ model, relative to the scene file
+    Obj { path: PathBuf },
+    // Stanford PLY mesh, relative to the scene file
+    Ply { path: PathBuf },
+    // glTF 2.0 scene, .gltf or .glb, relative to the scene file; its cameras are ignored
+    Gltf { path: PathBuf },
+    // one of the `[geometries]`, placed by its own transforms
+    Instance { geometry: String },
+    // fog filling a convex boundary object, scattering with the material of the medium
+    ConstantMedium { density: f32, boundary: Box<ObjectDesc> },
+    // a medium of varying density in a convex boundary object, which needs no material; without
+    // one it fills the box of a grid
+    HeterogeneousMedium {
+        boundary: Option<Box<ObjectDesc>>,
+        density: DensityDesc,
+        // absorption and scattering coefficients where the density is 1
+        sigma_a: f32,
+        sigma_s: f32,
+        #[serde(default = "default_albedo")]
+        albedo: [f32; 3],
+        // Henyey-Greenstein anisotropy
+        #[serde(default)]
+        g: f32,
+    },
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum DensityDesc {
+    // `density` times Perlin turbulence
+    Noise { scale: f32, density: f32 },
+    // densities at the nodes of a grid over the box from `min` to `max`, x varying fastest
+    Grid { min: [f32; 3], max: [f32; 3], resolution: [usize; 3], values: Vec<f32> },
+    // a `.grid` or Mitsuba `.vol` file, relative to the scene file
+    File { path: PathBuf },
+}
+
+fn default_albedo() -> [f32; 3] {
+    [1., 1., 1.]
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+enum TransformDesc {
+    Translate([f32; 3]),
+    // degrees, counterclockwise looking down the axis
+    RotateX(f32),
+    RotateY(f32),
+    RotateZ(f32),
+    Rotate { axis: [f32; 3], angle: f32 },
+    Scale([f32; 3]),
+    // the first three rows, the last one is always 0 0 0 1
+    Matrix([[f32; 4]; 3]),
+}
+
+fn vec3(v: [f32; 3]) -> Vec3 {
+    Vec3::new(v[0], v[1], v[2])
+}
+
+fn color(c: [f32; 3]) -> Color {
+    Color(vec3(c))
+}
+
+pub fn load<P: AsRef<Path>>(path: P, bvh: BvhBuilder, rng: &mut Sampler) -> Result<Scene, SceneError> {
+    let path = path.as_ref();
+    let text = fs::read_to_string(path).map_err(|e| SceneError::Io(path.to_path_buf(), e))?;
+    let desc: SceneDesc = toml::from_str(&text).map_err(|e| SceneError::Parse(path.to_path_buf(), e))?;
+
+    build(desc, path.parent().unwrap_or_else(|| Path::new("")), bvh, rng)
+}
+
+// renders a glTF file on its own, through its first camera or one framing the whole scene from the front
+pub fn load_gltf<P: AsRef<Path>>(path: P, bvh: BvhBuilder) -> Result<Scene, SceneError> {
+    let model = gltf_import::load(path, None).map_err(SceneError::Gltf)?;
+    let image = ImageDesc::default();
+
+    let (look_from, look_at, vup, vfov, aspect_ratio) = match model.camera {
+        Some(cam) => (cam.look_from, cam.look_at, cam.vup, cam.vfov, cam.aspect_ratio.unwrap_or_else(default_aspect_ratio)),
+        None => {
+            let vfov = 40.;
+            let (center, radius) = match model.objects.bounding_box(0., 1.) {
+                Some(bbox) => ((bbox.min + bbox.max) / 2., (bbox.max - bbox.min).length() / 2.),
+                None => (Vec3::new(0., 0., 0.), 1.),
+            };
+            let distance = radius / (vfov / 2f32).to_radians().sin();
+            (center + Vec3::new(0., 0., distance), center, vec3(default_vup()), vfov, default_aspect_ratio())
+        },
+    };
+    let camera = Camera::new(look_from, look_at, vup, vfov, aspect_ratio, 0., (look_at - look_from).length(), 0., default_time1());
+    let (world, bvh_stats) = bvh.build(&model.objects, 0., default_time1());
+
+    Ok(Scene {
+        world,
+        bvh_stats,
+        lights: HittableList { objects: model.objects.emitting_parts() },
+        camera,
+        background: Color(Vec3::new(0.7, 0.8, 1.)),
+        image_width: image.width,
+        image_height: ((image.width as f32 / aspect_ratio) as usize).max(2),
+        samples_per_pixel: image.samples_per_pixel,
+    })
+}
+
+// whether the shape can pick directions towards itself; a moving sphere cannot, lacking the time of the ray
+// `is_none_or` would need Rust 1.82
+#[allow(clippy::unnecessary_map_or)]
+fn samples_light(shape: &ShapeDesc, geometries: &BTreeMap<String, ObjectDesc>) -> bool {
+    match shape {
+        ShapeDesc::MovingSphere { .. } | ShapeDesc::ConstantMedium { .. } | ShapeDesc::HeterogeneousMedium { .. } => false,
+        ShapeDesc::Instance { geometry } => geometries.get(geometry).map_or(true, |g| samples_light(&g.shape, geometries)),
+        _ => true,
+    }
+}
+
+fn build(desc: SceneDesc, base_dir: &Path, bvh: BvhBuilder, rng: &mut Sampler) -> Result<Scene, SceneError> {
+    let mut materials = BTreeMap::new();
+    for (name, material) in &desc.materials {
+        materials.insert(name.as_str(), build_material(name, material, base_dir, rng)?);
+    }
+
+    let material = |object: &ObjectDesc| match &object.material {
+        Some(name) => materials.get(name.as_str()).map(Some).ok_or_else(|| SceneError::UnknownMaterial(name.clone())),
+        None => Ok(None),
+    };
+
+    // geometries cannot be instances themselves
+    let mut geometries = BTreeMap::new();
+    for (name, geometry) in &desc.geometries {
+        let object = build_object(&format!("geometry `{}`", name), geometry, material(geometry)?, &BTreeMap::new(), base_dir, rng)?;
+        geometries.insert(name.as_str(), Arc::from(object));
+    }
+
+    let mut world = HittableList { objects: Vec::new() };
+    let mut lights = HittableList { objects: Vec::new() };
+    for (index, object) in desc.objects.iter().enumerate() {
+        let name = format!("object {}", index);
+        let built: Arc<dyn Hittable> = Arc::from(build_object(&name, object, material(object)?, &geometries, base_dir, rng)?);
+        // whatever gives off light once built is sampled, down to the meshes of emissive materials in models
+        let emitters = hittable::emitters(&built);
+        if !emitters.is_empty() && !samples_light(&object.shape, &desc.geometries) {
+            return Err(SceneError::InvalidLight(name));
+        }
+        lights.objects.extend(emitters);
+        world.add(built);
+    }
+
+    let cam = &desc.camera;
+    let image_height = (desc.image.width as f32 / cam.aspect_ratio) as usize;
+    if desc.image.width < 2 || image_height < 2 {
+        return Err(SceneError::InvalidImage(format!("{}x{} pixels, at least 2x2 are needed", desc.image.width, image_height)));
+    }
+    if desc.image.samples_per_pixel == 0 {
+        return Err(SceneError::InvalidImage("no samples per pixel".to_string()));
+    }
+    let camera = Camera::new(vec3(cam.look_from), vec3(cam.look_at), vec3(cam.vup), cam.vfov, cam.aspect_ratio,
+                             cam.aperture, cam.focus_dist, cam.time0, cam.time1);
+    let (world, bvh_stats) = bvh.build(&world, cam.time0, cam.time1);
+
+    Ok(Scene {
+        world,
+        bvh_stats,
+        lights,
+        camera,
+        background: color(desc.background),
+        image_width: desc.image.width,
+        image_height,
+        samples_per_pixel: desc.image.samples_per_pixel,
+    })
+}
+
+fn build_texture(desc: &TextureDesc, base_dir: &Path, rng: &mut Sampler) -> Result<Box<dyn Texture>, SceneError> {
+    Ok(match desc {
+        TextureDesc::Color(c) => Box::new(SolidColor::new_from_color(color(*c))),
+        TextureDesc::Pattern(PatternDesc::Checker { odd, even }) => Box::new(CheckerTexture::new(color(*odd), color(*even))),
+        TextureDesc::Pattern(PatternDesc::Noise { scale }) => Box::new(NoiseTexture { noise: Perlin::new(rng), scale: *scale }),
+        TextureDesc::Pattern(PatternDesc::VertexColor { fallback }) => Box::new(VertexColorTexture { fallback: color(*fallback) }),
+        TextureDesc::Pattern(PatternDesc::Image { path }) => {
+            let path = base_dir.join(path);
+            let texture = ImageTexture::load(&path).map_err(|e| SceneError::Image(path, e))?;
+            Box::new(texture)
+        },
+    })
+}
+
+fn build_film(desc: &FilmDesc, base_dir: &Path, rng: &mut Sampler) -> Result<ThinFilm, SceneError> {
+    Ok(ThinFilm {
+        thickness_map: desc.thickness_map.as_ref().map(|map| build_texture(map, base_dir, rng)).transpose()?,
+        ..ThinFilm::new(desc.ior, desc.thickness)
+    })
+}
+
+fn build_material(name: &str, desc: &MaterialDesc, base_dir: &Path, rng: &mut Sampler) -> Result<Arc<dyn Material>, SceneError> {
+    Ok(match desc {
+        MaterialDesc::Lambertian { albedo } => Arc::new(Lambertian::new_from_texture(build_texture(albedo, base_dir, rng)?)),
+        MaterialDesc::Metal { albedo, fuzz } => Arc::new(Metal::new(color(*albedo), *fuzz)),
+        MaterialDesc::Conductor { metal, eta, k, roughness, roughness_v, film } => {
+            let (eta, k) = match (metal, eta, k) {
+                (Some(metal), None, None) => conductor_ior(metal)
+                    .ok_or_else(|| SceneError::InvalidMaterial(format!("{}: unknown metal `{}`", name, metal)))?,
+                (None, Some(eta), Some(k)) => (color(*eta), color(*k)),
+                _ => return Err(SceneError::InvalidMaterial(format!("{}: a conductor needs either a metal or eta and k", name))),
+            };
+            let mut conductor = Metal::conductor(eta, k, Ggx::new(*roughness, roughness_v.unwrap_or(*roughness)));
+            conductor.film = film.as_ref().map(|film| build_film(film, base_dir, rng)).transpose()?;
+            Arc::new(conductor)
+        },
+        MaterialDesc::Dielectric { ref_idx, glass, cauchy, sellmeier, roughness, absorption, film } => {
+            let distribution = Ggx::isotropic(*roughness);
+            let mut dielectric = match (ref_idx, glass, cauchy, sellmeier) {
+                (Some(ref_idx), None, None, None) => Dielectric::rough(*ref_idx, distribution),
+                (None, Some(glass), None, None) => Dielectric::dispersive(glass_dispersion(glass)
+                    .ok_or_else(|| SceneError::InvalidMaterial(format!("{}: unknown glass `{}`", name, glass)))?, distribution),
+                (None, None, Some([a, b]), None) => Dielectric::dispersive(Dispersion::Cauchy { a: *a, b: *b }, distribution),
+                (None, None, None, Some(SellmeierDesc { b, c })) => Dielectric::dispersive(Dispersion::Sellmeier { b: *b, c: *c }, distribution),
+                _ => return Err(SceneError::InvalidMaterial(
+                    format!("{}: a dielectric needs one of ref_idx, glass, cauchy or sellmeier", name))),
+            };
+            dielectric.absorption = color(*absorption);
+            dielectric.film = film.as_ref().map(|film| build_film(film, base_dir, rng)).transpose()?;
+            Arc::new(dielectric)
+        },
+        MaterialDesc::Principled(desc) => {
+            let PrincipledDesc {
+                base_color, metallic, roughness, specular, specular_tint, anisotropic, sheen, sheen_tint, clearcoat,
+                clearcoat_gloss, transmission, subsurface, ior, emission, normal_map, normal_scale,
+            } = desc.as_ref();
+            let mut principled = Principled::new(build_texture(base_color, base_dir, rng)?);
+            let mut parameter = |desc: &Option<ParameterDesc>, parameter: &mut Parameter| {
+                match desc {
+                    None => {},
+                    Some(ParameterDesc::Value(value)) => *parameter = Parameter::constant(*value),
+                    Some(ParameterDesc::Texture { channel, .. }) if *channel > 2 => {
+                        return Err(SceneError::InvalidMaterial(format!("{}: no channel {} in a color", name, channel)));
+                    },
+                    Some(ParameterDesc::Texture { texture, channel }) => {
+                        *parameter = Parameter::from_texture(build_texture(texture, base_dir, rng)?, *channel);
+                    },
+                }
+                Ok(())
+            };
+            parameter(metallic, &mut principled.metallic)?;
+            parameter(roughness, &mut principled.roughness)?;
+            parameter(specular, &mut principled.specular)?;
+            parameter(specular_tint, &mut principled.specular_tint)?;
+            parameter(anisotropic, &mut principled.anisotropic)?;
+            parameter(sheen, &mut principled.sheen)?;
+            parameter(sheen_tint, &mut principled.sheen_tint)?;
+            parameter(clearcoat, &mut principled.clearcoat)?;
+            parameter(clearcoat_gloss, &mut principled.clearcoat_gloss)?;
+            parameter(transmission, &mut principled.transmission)?;
+            parameter(subsurface, &mut principled.subsurface)?;
+
+            principled.ior = ior.unwrap_or(principled.ior);
+            if let Some(emission) = emission {
+                principled.emission = build_texture(emission, base_dir, rng)?;
+            }
+            if let Some(normal_map) = normal_map {
+                principled.normal_map = Some(build_texture(normal_map, base_dir, rng)?);
+            }
+            principled.normal_scale = normal_scale.unwrap_or(principled.normal_scale);
+            Arc::new(principled)
+        },
+        MaterialDesc::DiffuseLight { emit } => Arc::new(DiffuseLight::new_from_texture(build_texture(emit, base_dir, rng)?)),
+        MaterialDesc::Isotropic { albedo } => Arc::new(Isotropic::new_from_texture(build_texture(albedo, base_dir, rng)?)),
+    })
+}
+
+fn build_object(name: &str, desc: &ObjectDesc, material: Option<&Arc<dyn Material>>,
+                geometries: &BTreeMap<&str, Arc<dyn Hittable>>, base_dir: &Path, rng: &mut Sampler)
+                -> Result<Box<dyn Hittable>, SceneError> {
+    if let ShapeDesc::Instance { geometry } = &desc.shape {
+        let object = geometries.get(geometry.as_str()).ok_or_else(|| SceneError::UnknownGeometry(geometry.clone()))?;
+        let instance = Transformed::new(object.clone(), transform(name, &desc.transforms)?);
+        return Ok(Box::new(match material {
+            Some(material) => instance.with_material(material.clone()),
+            None => instance,
+        }));
+    }
+    if let ShapeDesc::Obj { path } = &desc.shape {
+        let model = obj::load(base_dir.join(path), material).map_err(SceneError::Obj)?;
+        return apply_transforms(name, Box::new(model), &desc.transforms);
+    }
+    if let ShapeDesc::Gltf { path } = &desc.shape {
+        let model = gltf_import::load(base_dir.join(path), material).map_err(SceneError::Gltf)?;
+        return apply_transforms(name, Box::new(model.objects), &desc.transforms);
+    }
+
+    if let ShapeDesc::HeterogeneousMedium { boundary, density, sigma_a, sigma_s, albedo, g } = &desc.shape {
+        let density: Box<dyn DensityField> = match density {
+            DensityDesc::Noise { scale, density } => Box::new(NoiseDensity { noise: Perlin::new(rng), scale: *scale, density: *density }),
+            DensityDesc::Grid { min, max, resolution, values } => Box::new(
+                GridDensity::new(AABB::new(vec3(*min), vec3(*max)), *resolution, values.clone())
+                    .map_err(|e| SceneError::InvalidVolume(format!("{}: {}", name, e)))?),
+            DensityDesc::File { path } => Box::new(volume_file::load(base_dir.join(path)).map_err(SceneError::Volume)?),
+        };
+        // the boundary is only intersected, never shaded
+        let unused: Arc<dyn Material> = Arc::new(Isotropic::new(color(*albedo)));
+        let boundary: Box<dyn Hittable> = match (boundary, density.bounding_box()) {
+            (Some(boundary), _) => build_object(&format!("{} boundary", name), boundary, Some(&unused), geometries, base_dir, rng)?,
+            (None, Some(bbox)) => Box::new(r#box::Box::new(bbox.min, bbox.max, unused)),
+            (None, None) => return Err(SceneError::InvalidVolume(format!("{}: no boundary around an unbounded density", name))),
+        };
+        let medium = HeterogeneousMedium::new(boundary, density, *sigma_a, *sigma_s, color(*albedo), *g);
+        return apply_transforms(name, Box::new(medium), &desc.transforms);
+    }
+
+    let material = material.ok_or_else(|| SceneError::MissingMaterial(name.to_string()))?.clone();
+    let object: Box<dyn Hittable> = match desc.shape {
+        ShapeDesc::Sphere { center, radius } => Box::new(Sphere { center: vec3(center), radius, material }),
+        ShapeDesc::MovingSphere { center0, center1, time0, time1, radius } => Box::new(MovingSphere {
+            center0: vec3(center0),
+            center1: vec3(center1),
+            time0,
+            time1,
+            radius,
+            material,
+        }),
+        ShapeDesc::XyRect { x0, x1, y0, y1, k } => Box::new(XYRect { x0, x1, y0, y1, k, material }),
+        ShapeDesc::XzRect { x0, x1, z0, z1, k } => Box::new(XZRect { x0, x1, z0, z1, k, material }),
+        ShapeDesc::YzRect { y0, y1, z0, z1, k } => Box::new(YZRect { y0, y1, z0, z1, k, material }),
+        ShapeDesc::Box { p0, p1 } => Box::new(r#box::Box::new(vec3(p0), vec3(p1), material)),
+        ShapeDesc::Triangle { vertices, normals, uvs } => {
+            let mut triangle = Triangle::new(vec3(vertices[0]), vec3(vertices[1]), vec3(vertices[2]), material);
+            triangle.normals = normals.map(|n| [vec3(n[0]), vec3(n[1]), vec3(n[2])]);
+            triangle.uvs = uvs;
+            Box::new(triangle)
+        },
+        ShapeDesc::Mesh { ref positions, ref normals, ref uvs, ref indices } => {
+            let mesh = Mesh {
+                positions: positions.iter().map(|&p| vec3(p)).collect(),
+                normals: normals.iter().map(|&n| vec3(n)).collect(),
+                uvs: uvs.clone(),
+                colors: Vec::new(),
+                indices: indices.clone(),
+                material,
+            };
+            mesh.validate().map_err(SceneError::InvalidMesh)?;
+            Box::new(TriangleMesh::new(mesh))
+        },
+        // the boundary is only intersected, never shaded
+        ShapeDesc::ConstantMedium { density, ref boundary } => {
+            // the mean free path is the inverse of the density
+            if !(density > 0. && density.is_finite()) {
+                return Err(SceneError::InvalidVolume(format!("{}: density {} is not positive", name, density)));
+            }
+            let boundary = build_object(&format!("{} boundary", name), boundary, Some(&material), geometries, base_dir, rng)?;
+            Box::new(ConstantMedium::new(boundary, density, material))
+        },
+        ShapeDesc::Ply { ref path } => Box::new(ply::load(base_dir.join(path), material).map_err(SceneError::Ply)?),
+        ShapeDesc::Obj { .. } | ShapeDesc::Gltf { .. } | ShapeDesc::Instance { .. } | ShapeDesc::HeterogeneousMedium { .. } => {
+            unreachable!("handled above")
+        },
+    };
+
+    apply_transforms(name, object, &desc.transforms)
+}
+
+fn apply_transforms(name: &str, object: Box<dyn Hittable>, transforms: &[TransformDesc])
+                    -> Result<Box<dyn Hittable>, SceneError> {
+    if transforms.is_empty() {
+        return Ok(object);
+    }
+
+    Ok(Box::new(Transformed::new(Arc::from(object), transform(name, transforms)?)))
+}
+
+// the transforms of an object are composed into a single one
+fn transform(name: &str, transforms: &[TransformDesc]) -> Result<Transform, SceneError> {
+    let invalid = || SceneError::InvalidTransform(name.to_string());
+
+    let mut matrix = Matrix4::identity();
+    for transform in transforms {
+        let next = match transform {
+            TransformDesc::Translate(offset) => Matrix4::translation(vec3(*offset)),
+            TransformDesc::RotateX(angle) => Matrix4::rotation(Vec3::new(1., 0., 0.), *angle),
+            TransformDesc::RotateY(angle) => Matrix4::rotation(Vec3::new(0., 1., 0.), *angle),
+            TransformDesc::RotateZ(angle) => Matrix4::rotation(Vec3::new(0., 0., 1.), *angle),
+            TransformDesc::Rotate { axis, angle } if vec3(*axis).length() > 0. => Matrix4::rotation(vec3(*axis), *angle),
+            TransformDesc::Rotate { .. } => return Err(invalid()),
+            TransformDesc::Scale(factors) => Matrix4::scaling(vec3(*factors)),
+            TransformDesc::Matrix(rows) => Matrix4 { m: [rows[0], rows[1], rows[2], [0., 0., 0., 1.]] },
+        };
+        matrix = next * matrix;
+    }
+
+    Transform::new(matrix).ok_or_else(invalid)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::ray::Ray;
+    use crate::vec3::Point3;
+    use rand::{Rng, SeedableRng};
+
+    fn example(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("scenes").join(name)
+    }
+
+    #[test]
+    fn test_load_examples() {
+        for name in &["two_spheres.toml", "two_perlin_spheres.toml", "earth.toml", "simple_light.toml", "cornell_box.toml", "obj_model.toml", "ply_model.toml", "gltf_model.toml", "instances.toml", "cornell_smoke.toml", "clouds.toml", "smoke_plume.toml", "metals.toml", "frosted_glass.toml", "principled.toml", "coloured_glass.toml", "thin_film.toml"] {
+            let scene = load(example(name), BvhBuilder::Sah, &mut Sampler::seed_from_u64(0));
+            assert!(scene.is_ok(), "{}: {}", name, scene.err().unwrap());
+            assert!(scene.unwrap().bvh_stats.primitive_count > 0);
+        }
+    }
+
+    // the scene file must hit exactly what the built-in scene hits, and sample the same lights
+    fn assert_matches_builtin(name: &str, (builtin, builtin_lights): (HittableList, HittableList)) {
+        let mut rng = Sampler::seed_from_u64(0);
+        let scene = load(example(name), BvhBuilder::Sah, &mut rng).unwrap();
+
+        assert_eq!(600, scene.image_width);
+        assert_eq!(600, scene.image_height);
+        assert_eq!(builtin.objects.len(), scene.bvh_stats.primitive_count);
+        assert_eq!(builtin_lights.objects.len(), scene.lights.objects.len());
+
+        for _ in 0..10000 {
+            let origin = Point3::new(rng.gen_range(1., 554.), rng.gen_range(1., 554.), rng.gen_range(1., 554.));
+            let direction = Vec3::new(rng.gen_range(-1., 1.), rng.gen_range(-1., 1.), rng.gen_range(-1., 1.));
+            let r = Ray::new(origin, direction, 0.);
+
+            let expected = builtin.hit(&r, 0.001, f32::MAX);
+            let actual = scene.world.hit(&r, 0.001, f32::MAX);
+            assert_eq!(expected.is_some(), actual.is_some());
+            if let (Some(expected), Some(actual)) = (expected, actual) {
+                assert_eq!(expected.t, actual.t);
+                assert_eq!(expected.normal, actual.normal);
+            }
+            assert_eq!(builtin_lights.pdf_value(&r, 0.001, f32::MAX), scene.lights.pdf_value(&r, 0.001, f32::MAX));
+        }
+    }
+
+    #[test]
+    fn test_cornell_box_matches_builtin() {
+        assert_matches_builtin("cornell_box.toml", crate::cornell_box());
+    }
+
+    #[test]
+    fn test_cornell_smoke_matches_builtin() {
+        assert_matches_builtin("cornell_smoke.toml", crate::cornell_smoke());
+    }
+
+    #[test]
+    fn test_unknown_material() {
+        let desc: SceneDesc = toml::from_str(r#"
+            [camera]
+            look_from = [0, 0, -1]
+            look_at = [0, 0, 0]
+            vfov = 40
+
+            [[objects]]
+            type = "sphere"
+            center = [0, 0, 0]
+            radius = 1
+            material = "missing"
+        "#).unwrap();
+
+        match build(desc, Path::new(""), BvhBuilder::Sah, &mut Sampler::seed_from_u64(0)) {
+            Err(SceneError::UnknownMaterial(name)) => assert_eq!("missing", name),
+            _ => panic!("expected an unknown material error"),
+        }
+    }
+
+    #[test]
+    fn test_invalid_light() {
+        for (objects, expected) in &[("type = \"moving_sphere\"\ncenter0 = [0, 0, 0]\ncenter1 = [0, 1, 0]\nradius = 1\nmaterial = \"lamp\"", "object 0"),
+                                     ("type = \"instance\"\ngeometry = \"ball\"", "object 0")] {
+            let desc: SceneDesc = toml::from_str(&format!(r#"
+                [camera]
+                look_from = [0, 0, -1]
+                look_at = [0, 0, 0]
+                vfov = 40
+
+                [materials.lamp]
+                type = "diffuse_light"
+                emit = [4, 4, 4]
+
+                [geometries.ball]
+                type = "moving_sphere"
+                center0 = [0, 0, 0]
+                center1 = [0, 1, 0]
+                radius = 1
+                material = "lamp"
+
+                [[objects]]
+                {}
+            "#, objects)).unwrap();
+
+            match build(desc, Path::new(""), BvhBuilder::Sah, &mut Sampler::seed_from_u64(0)) {
+                Err(SceneError::InvalidLight(name)) => assert_eq!(expected, &name),
+                _ => panic!("expected an invalid light error"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_invalid_constant_medium() {
+        for density in &["0", "-0.5"] {
+            let desc: SceneDesc = toml::from_str(&format!(r#"
+                [camera]
+                look_from = [0, 0, -1]
+                look_at = [0, 0, 0]
+                vfov = 40
+
+                [materials.smoke]
+                type = "isotropic"
+                albedo = [1, 1, 1]
+
+                [[objects]]
+                type = "constant_medium"
+                density = {}
+                material = "smoke"
+                boundary = {{ type = "sphere", center = [0, 0, 0], radius = 1 }}
+            "#, density)).unwrap();
+
+            match build(desc, Path::new(""), BvhBuilder::Sah, &mut Sampler::seed_from_u64(0)) {
+                Err(SceneError::InvalidVolume(message)) => assert_eq!(format!("object 0: density {} is not positive", density), message),
+                _ => panic!("expected an invalid volume error"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_unknown_object_field() {
+        let result = toml::from_str::<SceneDesc>(r#"
+            [camera]
+            look_from = [0, 0, -1]
+            look_at = [0, 0, 0]
+            vfov = 40
+
+            [[objects]]
+            type = "sphere"
+            center = [0, 0, 0]
+            radius = 1
+            raduis = 2
+            material = "white"
+        "#);
+
+        let message = result.err().unwrap().to_string();
+        assert!(message.contains("unknown field `raduis`"), "{}", message);
+    }
+
+    #[test]
+    fn test_invalid_conductor() {
+        for (material, expected) in &[("metal = \"brass\"", "copper: unknown metal `brass`"),
+                                      ("eta = [0.2, 0.9, 1.1]", "copper: a conductor needs either a metal or eta and k")] {
+            let desc: SceneDesc = toml::from_str(&format!(r#"
+                [camera]
+                look_from = [0, 0, -1]
+                look_at = [0, 0, 0]
+                vfov = 40
+
+                [materials.copper]
+                type = "conductor"
+                {}
+            "#, material)).unwrap();
+
+            match build(desc, Path::new(""), BvhBuilder::Sah, &mut Sampler::seed_from_u64(0)) {
+                Err(SceneError::InvalidMaterial(message)) => assert_eq!(expected, &message),
+                _ => panic!("expected an invalid material error"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_invalid_dielectric() {
+        for (material, expected) in &[("glass = \"crown\"", "prism: unknown glass `crown`"),
+                                      ("ref_idx = 1.5\ncauchy = [1.5, 0.01]", "prism: a dielectric needs one of ref_idx, glass, cauchy or sellmeier"),
+                                      ("roughness = 0.1", "prism: a dielectric needs one of ref_idx, glass, cauchy or sellmeier")] {
+            let desc: SceneDesc = toml::from_str(&format!(r#"
+                [camera]
+                look_from = [0, 0, -1]
+                look_at = [0, 0, 0]
+                vfov = 40
+
+                [materials.prism]
+                type = "dielectric"
+                {}
+            "#, material)).unwrap();
+
+            match build(desc, Path::new(""), BvhBuilder::Sah, &mut Sampler::seed_from_u64(0)) {
+                Err(SceneError::InvalidMaterial(message)) => assert_eq!(expected, &message),
+                _ => panic!("expected an invalid material error"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_invalid_principled_channel() {
+        let desc: SceneDesc = toml::from_str(r#"
+            [camera]
+            look_from = [0, 0, -1]
+            look_at = [0, 0, 0]
+            vfov = 40
+
+            [materials.paint]
+            type = "principled"
+            base_color = [0.8, 0.1, 0.1]
+            clearcoat = 1
+            roughness = { texture = { type = "noise", scale = 4 }, channel = 3 }
+        "#).unwrap();
+
+        match build(desc, Path::new(""), BvhBuilder::Sah, &mut Sampler::seed_from_u64(0)) {
+            Err(SceneError::InvalidMaterial(message)) => assert_eq!("paint: no channel 3 in a color", message),
+            _ => panic!("expected an invalid material error"),
+        }
+    }
+
+    #[test]
+    fn test_invalid_image() {
+        for (image, aspect_ratio, expected) in &[("width = 1", 1.5, "1x0 pixels, at least 2x2 are needed"),
+                                                 ("width = 300", 200., "300x1 pixels, at least 2x2 are needed"),
+                                                 ("samples_per_pixel = 0", 1.5, "no samples per pixel")] {
+            let desc: SceneDesc = toml::from_str(&format!(r#"
+                [image]
+                {}
+
+                [camera]
+                look_from = [0, 0, -1]
+                look_at = [0, 0, 0]
+                vfov = 40
+                aspect_ratio = {}
+            "#, image, aspect_ratio)).unwrap();
+
+            match build(desc, Path::new(""), BvhBuilder::Sah, &mut Sampler::seed_from_u64(0)) {
+                Err(SceneError::InvalidImage(message)) => assert_eq!(expected, &message),
+                _ => panic!("expected an invalid image error"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_invalid_mesh() {
+        for (indices, expected) in &[("[[0, 1, 3]]", "index 3 out of 3 positions"), ("[]", "no triangles")] {
+            let desc: SceneDesc = toml::from_str(&format!(r#"
+                [camera]
+                look_from = [0, 0, -1]
+                look_at = [0, 0, 0]
+                vfov = 40
+
+                [materials.white]
+                type = "lambertian"
+                albedo = [0.7, 0.7, 0.7]
+
+                [[objects]]
+                type = "mesh"
+                positions = [[0, 0, 0], [1, 0, 0], [0, 1, 0]]
+                indices = {}
+                material = "white"
+            "#, indices)).unwrap();
+
+            match build(desc, Path::new(""), BvhBuilder::Sah, &mut Sampler::seed_from_u64(0)) {
+                Err(SceneError::InvalidMesh(message)) => assert_eq!(expected, &message),
+                _ => panic!("expected an invalid mesh error"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_transforms() {
+        let desc: SceneDesc = toml::from_str(r#"
+            [camera]
+            look_from = [0, 0, -1]
+            look_at = [0, 0, 0]
+            vfov = 40
+
+            [materials.white]
+            type = "lambertian"
+            albedo = [0.7, 0.7, 0.7]
+
+            [[objects]]
+            type = "sphere"
+            center = [0, 0, 0]
+            radius = 1
+            material = "white"
+            transforms = [{ scale = [1, 1, 3] }, { rotate_x = 90 }, { rotate = { axis = [0, 0, 1], angle = 90 } }, { translate = [1, 0, 0] }]
+
+            [[objects]]
+            type = "sphere"
+            center = [0, 0, 0]
+            radius = 1
+            material = "white"
+            transforms = [{ matrix = [[1, 0, 0, 0], [0, 0, 0, 0], [0, 0, 1, 0]] }]
+        "#).unwrap();
+
+        match build(desc, Path::new(""), BvhBuilder::Sah, &mut Sampler::seed_from_u64(0)) {
+            Err(SceneError::InvalidTransform(name)) => assert_eq!("object 1", name),
+            _ => panic!("expected an invalid transform error"),
+        }
+
+        // the sphere stretched along z, turned onto -y, then onto +x and moved along x: x in [-2, 4]
+        let sphere = build_object("object 0", &toml::from_str(r#"
+            type = "sphere"
+            center = [0, 0, 0]
+            radius = 1
+            transforms = [{ scale = [1, 1, 3] }, { rotate_x = 90 }, { rotate_z = 90 }, { translate = [1, 0, 0] }]
+        "#).unwrap(), Some(&(Arc::new(Dielectric::new(1.5)) as Arc<dyn Material>)), &BTreeMap::new(), Path::new(""),
+           &mut Sampler::seed_from_u64(0))
+        .unwrap();
+        let bbox = sphere.bounding_box(0., 1.).unwrap();
+        assert!((bbox.min - Vec3::new(-2., -1., -1.)).length() < 1e-4);
+        assert!((bbox.max - Vec3::new(4., 1., 1.)).length() < 1e-4);
+    }
+
+    #[test]
+    fn test_emissive_materials_are_lights() {
+        let dir = std::env::temp_dir().join(format!("scene-{}-emissive", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("lamp.obj"), "
+            mtllib lamp.mtl
+            v 0 0 0
+            v 1 0 0
+            v 1 1 0
+            v 0 1 0
+            usemtl light
+            f 1 2 3 4
+            v 2 0 0
+            v 3 0 0
+            v 2 1 0
+            usemtl white
+            f 5 6 7
+        ").unwrap();
+        fs::write(dir.join("lamp.mtl"), "
+            newmtl light
+            Ke 4 4 4
+            newmtl white
+            Kd 0.7 0.7 0.7
+        ").unwrap();
+
+        let desc: SceneDesc = toml::from_str(r#"
+            [camera]
+            look_from = [0, 0, 5]
+            look_at = [0, 0, 0]
+            vfov = 40
+
+            [materials.glow]
+            type = "principled"
+            base_color = [0.7, 0.7, 0.7]
+            emission = [2, 2, 2]
+
+            [materials.white]
+            type = "principled"
+            base_color = [0.7, 0.7, 0.7]
+
+            [[objects]]
+            type = "obj"
+            path = "lamp.obj"
+            transforms = [{ translate = [0, 0, -1] }]
+
+            [[objects]]
+            type = "sphere"
+            center = [0, 5, 0]
+            radius = 1
+            material = "glow"
+
+            [[objects]]
+            type = "sphere"
+            center = [0, -5, 0]
+            radius = 1
+            material = "white"
+        "#).unwrap();
+        let scene = build(desc, &dir, BvhBuilder::Sah, &mut Sampler::seed_from_u64(0)).unwrap();
+
+        // the emitting face of the model and the glowing sphere, not the rest of the model
+        assert_eq!(scene.lights.objects.len(), 2);
+        let pdf = |origin: Point3, direction: Vec3| scene.lights.pdf_value(&Ray::new_without_move(origin, direction), 0.001, f32::MAX);
+        assert!(pdf(Point3::new(0.5, 0.5, 5.), Vec3::new(0., 0., -1.)) > 0.);
+        assert_eq!(pdf(Point3::new(2.25, 0.5, 5.), Vec3::new(0., 0., -1.)), 0.);
+        assert!(pdf(Point3::new(0., 0., 0.), Vec3::new(0., 1., 0.)) > 0.);
+        assert_eq!(pdf(Point3::new(0., 0., 0.), Vec3::new(0., -1., 0.)), 0.);
+    }
+}
@@ -1,19 +1,20 @@
-use std::rc::Rc;
+use std::sync::Arc;
 
 use crate::vec3::Vec3;
 use std::f32::consts::PI;
-use rand::{self, Rng};
+use rand::Rng;
 
 use crate::hittable::{HitRecord, Hittable};
 use crate::ray::Ray;
 use crate::vec3::{Point3};
-use crate::material::Material;
+use crate::material::{orthonormal_basis, Material};
 use crate::aabb::{AABB, surrounding_box};
+use crate::sampler::Sampler;
 
 pub struct Sphere {
     pub center: Point3,
     pub radius: f32,
-    pub material: Rc<dyn Material>,
+    pub material: Arc<dyn Material>,
 }
 
 fn get_sphere_uv(p: &Point3) -> (f32, f32) {
@@ -56,35 +57,52 @@ impl Hittable for Sphere {
             }
         }
 
-        return None;
+        None
     }
 
-    fn bounding_box(&self, t0: f32, t1: f32) -> Option<AABB> {
+    fn bounding_box(&self, _t0: f32, _t1: f32) -> Option<AABB> {
         Some(AABB::new(self.center - Point3::new(self.radius, self.radius, self.radius),
                        self.center + Point3::new(self.radius, self.radius, self.radius)))
     }
-}
 
-// TODO implement traits in rand crate
-pub fn random_in_unit_sphere() -> Point3 {
-    let mut rng = rand::thread_rng();
+    fn emits(&self) -> bool {
+        self.material.emits()
+    }
 
-    loop {
-        let p = Point3 {
-            x: rng.gen_range(-1., 1.),
-            y: rng.gen_range(-1., 1.),
-            z: rng.gen_range(-1., 1.),
-        };
+    // uniform over the cone of directions the sphere fills, seen from outside
+    fn pdf_value(&self, r: &Ray, t_min: f32, t_max: f32) -> f32 {
+        let distance_squared = (self.center - r.origin).length_squared();
+        if distance_squared <= self.radius * self.radius || self.hit(r, t_min, t_max).is_none() {
+            return 0.;
+        }
 
-        if p.length_squared() < 1.0 {
-            return p;
+        let cos_theta_max = (1. - self.radius * self.radius / distance_squared).sqrt();
+        1. / (2. * PI * (1. - cos_theta_max))
+    }
+
+    fn random(&self, origin: &Point3, rng: &mut Sampler) -> Vec3 {
+        let to_center = self.center - *origin;
+        let distance_squared = to_center.length_squared();
+        if distance_squared <= self.radius * self.radius {
+            return to_center;
         }
+
+        let cos_theta_max = (1. - self.radius * self.radius / distance_squared).sqrt();
+        let cos_theta = 1. + rng.gen::<f32>() * (cos_theta_max - 1.);
+        let sin_theta = (1. - cos_theta * cos_theta).max(0.).sqrt();
+        let phi = rng.gen_range(0., 2. * PI);
+        let w = to_center.normalize();
+        let (u, v) = orthonormal_basis(w);
+        let direction = sin_theta * phi.cos() * u + sin_theta * phi.sin() * v + cos_theta * w;
+
+        // as far as the near side of the sphere, the direction grazing it at worst
+        let along = direction.dot(to_center);
+        let discriminant = (along * along - distance_squared + self.radius * self.radius).max(0.);
+        (along - discriminant.sqrt()) * direction
     }
 }
 
-pub fn random_unit_vector() -> Vec3 {
-    let mut rng = rand::thread_rng();
-
+pub fn random_unit_vector(rng: &mut Sampler) -> Vec3 {
     let a: f32 = rng.gen_range(0., 2. * PI);
     let z: f32 = rng.gen_range(-1., 1.);
     let r: f32 = (1. - z * z).sqrt();
@@ -92,22 +110,13 @@ pub fn random_unit_vector() -> Vec3 {
     Vec3::new(r * a.cos(), r * a.sin(), z)
 }
 
-pub fn random_in_hemisphere(normal: &Vec3) -> Vec3 {
-    let in_unit_sphere = random_in_unit_sphere();
-    if in_unit_sphere.dot(*normal) > 0.0 {
-        in_unit_sphere
-    } else {
-        -in_unit_sphere
-    }
-}
-
 pub struct MovingSphere {
     pub center0: Vec3,
     pub center1: Vec3,
     pub time0: f32,
     pub time1: f32,
     pub radius: f32,
-    pub material: Rc<dyn Material>,
+    pub material: Arc<dyn Material>,
 }
 
 impl MovingSphere {
@@ -149,7 +158,7 @@ impl Hittable for MovingSphere {
             }
         }
 
-        return None;
+        None
     }
 
     fn bounding_box(&self, t0: f32, t1: f32) -> Option<AABB> {
@@ -160,4 +169,8 @@ impl Hittable for MovingSphere {
 
         Some(surrounding_box(&box0, &box1))
     }
+
+    fn emits(&self) -> bool {
+        self.material.emits()
+    }
 }
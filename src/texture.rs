@@ -1,10 +1,21 @@
 use crate::Vec3;
-use crate::vec3::{self, Point3};
+use crate::vec3::Point3;
 use crate::color::{Color};
 use crate::perlin::{Perlin};
+use crate::hittable::HitRecord;
 
-pub trait Texture {
+pub trait Texture: Send + Sync {
     fn value(&self, u: f32, v: f32, p: &Point3) -> Color;
+
+    // textures that need more than the surface coordinates, like vertex colors, look at the whole hit
+    fn value_at(&self, rec: &HitRecord) -> Color {
+        self.value(rec.u, rec.v, &rec.p)
+    }
+
+    // whether the texture is black everywhere, which only constant ones can tell
+    fn is_black(&self) -> bool {
+        false
+    }
 }
 
 pub struct SolidColor {
@@ -15,16 +26,16 @@ impl SolidColor {
     pub fn new_from_color(c: Color) -> Self {
         SolidColor { color: c }
     }
-
-    pub fn new_from_raw(r: f32, g: f32, b: f32) -> Self {
-        SolidColor { color: Color(vec3::Vec3{ x: r, y: g, z: b }) }
-    }
 }
 
 impl Texture for SolidColor {
     fn value(&self, _u: f32, _v: f32, _p: &Point3) -> Color {
         self.color
     }
+
+    fn is_black(&self) -> bool {
+        self.color.0 == Vec3::new(0., 0., 0.)
+    }
 }
 
 pub struct CheckerTexture {
@@ -55,6 +66,21 @@ impl Texture for CheckerTexture {
 
 }
 
+// the interpolated vertex colors of a mesh, `fallback` where the surface has none
+pub struct VertexColorTexture {
+    pub fallback: Color,
+}
+
+impl Texture for VertexColorTexture {
+    fn value(&self, _u: f32, _v: f32, _p: &Point3) -> Color {
+        self.fallback
+    }
+
+    fn value_at(&self, rec: &HitRecord) -> Color {
+        rec.color.unwrap_or(self.fallback)
+    }
+}
+
 pub struct NoiseTexture {
     pub noise: Perlin,
     pub scale: f32,
@@ -66,7 +92,9 @@ impl Texture for NoiseTexture {
     }
 }
 
-use image::{open, DynamicImage, GenericImageView, Pixel, Rgb};
+use std::path::Path;
+
+use image::{open, DynamicImage, GenericImageView, ImageResult, Pixel, Rgb};
 
 pub struct ImageTexture {
     pub data: DynamicImage,
@@ -79,20 +107,24 @@ fn clamp(x: f32, min: f32, max: f32) -> f32 {
     if x > max {
         return max;
     }
-    return x;
+    x
 }
 
 impl ImageTexture {
     pub fn new(fname: &str) -> Self {
-        ImageTexture {
-            data: open(fname).unwrap(),
-        }
+        Self::load(fname).unwrap()
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> ImageResult<Self> {
+        Ok(ImageTexture {
+            data: open(path)?,
+        })
     }
 }
 
 impl Texture for ImageTexture {
 
-    fn value(&self, u: f32, v: f32, p: &Point3) -> Color {
+    fn value(&self, u: f32, v: f32, _p: &Point3) -> Color {
         let u = clamp(u, 0., 1.);
         let v = 1.0 - clamp(v, 0., 1.);
 
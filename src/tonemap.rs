@@ -0,0 +1,150 @@
+use crate::color::Color;
+use crate::vec3::Vec3;
+
+#[derive(Clone, Copy, Debug, PartialEq, clap::ValueEnum)]
+pub enum ToneMap {
+    // clamp to [0, 1]
+    Clip,
+    // x / (1 + x)
+    Reinhard,
+    // Reinhard that maps the white point to 1
+    ExtendedReinhard,
+    // John Hable's Uncharted 2 filmic curve
+    Hable,
+    // Krzysztof Narkowicz's fit of the ACES filmic curve
+    Aces,
+}
+
+// maps the linear radiance of the film to display referred sRGB in [0, 1]
+#[derive(Clone, Copy, Debug)]
+pub struct ToneMapper {
+    pub operator: ToneMap,
+    // in stops, every stop doubles the radiance
+    pub exposure: f32,
+    // smallest radiance mapped to white by the extended Reinhard operator
+    pub white_point: f32,
+}
+
+impl Default for ToneMapper {
+    fn default() -> Self {
+        Self {
+            operator: ToneMap::Clip,
+            exposure: 0.,
+            white_point: 4.,
+        }
+    }
+}
+
+impl ToneMapper {
+    pub fn map(&self, c: Color) -> Color {
+        let scale = self.exposure.exp2();
+        let map = |x: f32| srgb_oetf(self.map_channel(x.max(0.) * scale).clamp(0., 1.));
+
+        Color(Vec3::new(map(c.0.x), map(c.0.y), map(c.0.z)))
+    }
+
+    fn map_channel(&self, x: f32) -> f32 {
+        match self.operator {
+            ToneMap::Clip => x,
+            ToneMap::Reinhard => x / (1. + x),
+            ToneMap::ExtendedReinhard => {
+                let white = self.white_point.max(f32::EPSILON);
+                x * (1. + x / (white * white)) / (1. + x)
+            },
+            ToneMap::Hable => {
+                // the exposure bias and linear white point of the original talk
+                const EXPOSURE_BIAS: f32 = 2.;
+                const WHITE: f32 = 11.2;
+                hable_partial(x * EXPOSURE_BIAS) / hable_partial(WHITE)
+            },
+            ToneMap::Aces => {
+                // the fit expects the input pre-exposed by 0.6
+                let x = 0.6 * x;
+                (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14)
+            },
+        }
+    }
+
+    // tone map, encode and quantize to 8 bits
+    pub fn quantize(&self, c: Color) -> [u8; 3] {
+        let c = self.map(c).0;
+        let quantize = |x: f32| (255. * x + 0.5) as u8;
+
+        [quantize(c.x), quantize(c.y), quantize(c.z)]
+    }
+}
+
+fn hable_partial(x: f32) -> f32 {
+    const A: f32 = 0.15;
+    const B: f32 = 0.50;
+    const C: f32 = 0.10;
+    const D: f32 = 0.20;
+    const E: f32 = 0.02;
+    const F: f32 = 0.30;
+
+    ((x * (A * x + C * B) + D * E) / (x * (A * x + B) + D * F)) - E / F
+}
+
+// the piecewise sRGB transfer function, from linear [0, 1] to encoded [0, 1]
+pub fn srgb_oetf(x: f32) -> f32 {
+    if x <= 0.003_130_8 {
+        12.92 * x
+    } else {
+        1.055 * x.powf(1. / 2.4) - 0.055
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn gray(x: f32) -> Color {
+        Color(Vec3::new(x, x, x))
+    }
+
+    fn mapper(operator: ToneMap) -> ToneMapper {
+        ToneMapper { operator, ..ToneMapper::default() }
+    }
+
+    #[test]
+    fn test_srgb_oetf() {
+        assert_eq!(srgb_oetf(0.), 0.);
+        assert!((srgb_oetf(1.) - 1.).abs() < 1e-6);
+        assert!((srgb_oetf(0.5) - 0.735_357).abs() < 1e-5);
+        // both pieces meet at the threshold
+        assert!((12.92 * 0.003_130_8 - (1.055 * 0.003_130_8f32.powf(1. / 2.4) - 0.055)).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_operators_are_monotonic_and_bounded() {
+        for operator in [ToneMap::Clip, ToneMap::Reinhard, ToneMap::ExtendedReinhard, ToneMap::Hable, ToneMap::Aces] {
+            let tone = mapper(operator);
+            let mut previous = 0.;
+            for i in 0..1000 {
+                let y = tone.map(gray(i as f32 * 0.02)).0.x;
+                assert!(y >= previous, "{:?}", operator);
+                assert!((0. ..=1.).contains(&y), "{:?}", operator);
+                previous = y;
+            }
+            assert_eq!(tone.quantize(gray(0.)), [0, 0, 0], "{:?}", operator);
+        }
+    }
+
+    #[test]
+    fn test_extended_reinhard_white_point() {
+        let tone = ToneMapper { operator: ToneMap::ExtendedReinhard, exposure: 0., white_point: 15. };
+
+        assert_eq!(tone.quantize(gray(15.)), [255, 255, 255]);
+        assert!(tone.quantize(gray(7.))[0] < 255);
+    }
+
+    #[test]
+    fn test_exposure_in_stops() {
+        let bright = ToneMapper { exposure: 1., ..ToneMapper::default() };
+        let dark = ToneMapper { exposure: -2., ..ToneMapper::default() };
+
+        assert_eq!(bright.map(gray(0.25)).0, ToneMapper::default().map(gray(0.5)).0);
+        assert_eq!(dark.map(gray(0.8)).0, ToneMapper::default().map(gray(0.2)).0);
+    }
+}
@@ -0,0 +1,511 @@
+use std::sync::Arc;
+
+use crate::aabb::AABB;
+use crate::color::Color;
+use crate::flat_bvh::{FlatBvh, SplitMethod};
+use crate::hittable::{HitRecord, Hittable};
+use crate::hittable_list::HittableList;
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::sampler::Sampler;
+use crate::vec3::{Point3, Vec3};
+
+use rand::Rng;
+
+// flat boxes are padded like the axis-aligned rectangles so that the BVH never sees a zero extent
+const BOX_PADDING: f32 = 0.0001;
+
+pub struct Triangle {
+    pub vertices: [Point3; 3],
+    // shading normals, the geometric normal is used when absent
+    pub normals: Option<[Vec3; 3]>,
+    // texture coordinates, the barycentric coordinates are used when absent
+    pub uvs: Option<[[f32; 2]; 3]>,
+    pub material: Arc<dyn Material>,
+}
+
+impl Triangle {
+    pub fn new(p0: Point3, p1: Point3, p2: Point3, material: Arc<dyn Material>) -> Self {
+        Self {
+            vertices: [p0, p1, p2],
+            normals: None,
+            uvs: None,
+            material,
+        }
+    }
+}
+
+impl Hittable for Triangle {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        hit_triangle(&self.vertices, self.normals.as_ref(), self.uvs.as_ref(), None, &self.material, r, t_min, t_max)
+    }
+
+    fn bounding_box(&self, _t0: f32, _t1: f32) -> Option<AABB> {
+        Some(triangle_box(&self.vertices))
+    }
+
+    fn emits(&self) -> bool {
+        self.material.emits()
+    }
+
+    fn pdf_value(&self, r: &Ray, t_min: f32, t_max: f32) -> f32 {
+        triangle_pdf(&self.vertices, r, t_min, t_max)
+    }
+
+    fn random(&self, origin: &Point3, rng: &mut Sampler) -> Vec3 {
+        random_on_triangle(&self.vertices, rng) - *origin
+    }
+}
+
+// vertex buffers shared by every triangle of a mesh, `normals`, `uvs` and `colors` are either empty
+// or hold one entry per position
+pub struct Mesh {
+    pub positions: Vec<Point3>,
+    pub normals: Vec<Vec3>,
+    pub uvs: Vec<[f32; 2]>,
+    pub colors: Vec<Color>,
+    pub indices: Vec<[u32; 3]>,
+    pub material: Arc<dyn Material>,
+}
+
+impl Mesh {
+    // checks the buffer sizes and indices, which would otherwise panic while rendering, and that there
+    // is a triangle to bound
+    pub fn validate(&self) -> Result<(), String> {
+        if self.indices.is_empty() {
+            return Err("no triangles".to_string());
+        }
+        if !self.normals.is_empty() && self.normals.len() != self.positions.len() {
+            return Err(format!("{} normals for {} positions", self.normals.len(), self.positions.len()));
+        }
+        if !self.uvs.is_empty() && self.uvs.len() != self.positions.len() {
+            return Err(format!("{} uvs for {} positions", self.uvs.len(), self.positions.len()));
+        }
+        if !self.colors.is_empty() && self.colors.len() != self.positions.len() {
+            return Err(format!("{} colors for {} positions", self.colors.len(), self.positions.len()));
+        }
+        match self.indices.iter().flatten().find(|&&i| i as usize >= self.positions.len()) {
+            Some(i) => Err(format!("index {} out of {} positions", i, self.positions.len())),
+            None => Ok(()),
+        }
+    }
+
+    fn vertices(&self, triangle: usize) -> [Point3; 3] {
+        let [a, b, c] = self.indices[triangle];
+        [self.positions[a as usize], self.positions[b as usize], self.positions[c as usize]]
+    }
+
+    fn normals(&self, triangle: usize) -> Option<[Vec3; 3]> {
+        if self.normals.is_empty() {
+            return None;
+        }
+        let [a, b, c] = self.indices[triangle];
+        Some([self.normals[a as usize], self.normals[b as usize], self.normals[c as usize]])
+    }
+
+    fn uvs(&self, triangle: usize) -> Option<[[f32; 2]; 3]> {
+        if self.uvs.is_empty() {
+            return None;
+        }
+        let [a, b, c] = self.indices[triangle];
+        Some([self.uvs[a as usize], self.uvs[b as usize], self.uvs[c as usize]])
+    }
+
+    fn colors(&self, triangle: usize) -> Option<[Color; 3]> {
+        if self.colors.is_empty() {
+            return None;
+        }
+        let [a, b, c] = self.indices[triangle];
+        Some([self.colors[a as usize], self.colors[b as usize], self.colors[c as usize]])
+    }
+}
+
+// one triangle of a mesh, only an index into the shared buffers
+struct MeshTriangle {
+    mesh: Arc<Mesh>,
+    index: usize,
+    // the share of the area of the mesh, how often the mesh picks the triangle as a light
+    weight: f32,
+}
+
+impl Hittable for MeshTriangle {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        let mesh = &self.mesh;
+        hit_triangle(&mesh.vertices(self.index), mesh.normals(self.index).as_ref(), mesh.uvs(self.index).as_ref(),
+                     mesh.colors(self.index).as_ref(), &mesh.material, r, t_min, t_max)
+    }
+
+    fn bounding_box(&self, _t0: f32, _t1: f32) -> Option<AABB> {
+        Some(triangle_box(&self.mesh.vertices(self.index)))
+    }
+
+    fn pdf_value(&self, r: &Ray, t_min: f32, t_max: f32) -> f32 {
+        self.weight * triangle_pdf(&self.mesh.vertices(self.index), r, t_min, t_max)
+    }
+
+    fn random(&self, origin: &Point3, rng: &mut Sampler) -> Vec3 {
+        random_on_triangle(&self.mesh.vertices(self.index), rng) - *origin
+    }
+}
+
+// a mesh with its own BVH over its triangles, so it can sit as a single object in a scene
+pub struct TriangleMesh {
+    bvh: FlatBvh,
+    mesh: Arc<Mesh>,
+    // the running sums of the areas of the triangles, to pick them in proportion to their area
+    cdf: Vec<f32>,
+}
+
+impl TriangleMesh {
+    pub fn new(mesh: Mesh) -> Self {
+        let mesh = Arc::new(mesh);
+        let areas: Vec<f32> = (0..mesh.indices.len()).map(|index| triangle_area(&mesh.vertices(index))).collect();
+        let total: f32 = areas.iter().sum();
+
+        let mut triangles = HittableList { objects: Vec::with_capacity(areas.len()) };
+        let mut cdf = Vec::with_capacity(areas.len());
+        let mut sum = 0.;
+        for (index, &area) in areas.iter().enumerate() {
+            let weight = if total > 0. { area / total } else { 0. };
+            triangles.add(Arc::new(MeshTriangle { mesh: mesh.clone(), index, weight }));
+            sum += area;
+            cdf.push(sum);
+        }
+
+        Self {
+            bvh: FlatBvh::new(&triangles, SplitMethod::Sah, 0., 0.),
+            mesh,
+            cdf,
+        }
+    }
+}
+
+impl Hittable for TriangleMesh {
+    fn hit(&self, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        self.bvh.hit(r, t_min, t_max)
+    }
+
+    fn bounding_box(&self, t0: f32, t1: f32) -> Option<AABB> {
+        self.bvh.bounding_box(t0, t1)
+    }
+
+    fn emits(&self) -> bool {
+        self.mesh.material.emits()
+    }
+
+    // the triangles weigh their densities by their share of the area, so the mesh is sampled uniformly
+    // over its whole surface
+    fn pdf_value(&self, r: &Ray, t_min: f32, t_max: f32) -> f32 {
+        let mut sum = 0.;
+        self.bvh.for_each_crossed(r, t_min, t_max, |triangle| {
+            sum += triangle.pdf_value(r, t_min, t_max);
+            true
+        });
+        sum
+    }
+
+    fn random(&self, origin: &Point3, rng: &mut Sampler) -> Vec3 {
+        let total = match self.cdf.last() {
+            Some(&total) => total,
+            None => return Vec3::new(1., 0., 0.),
+        };
+        let area = rng.gen::<f32>() * total;
+        let index = self.cdf.partition_point(|&sum| sum <= area).min(self.cdf.len() - 1);
+        random_on_triangle(&self.mesh.vertices(index), rng) - *origin
+    }
+}
+
+// the watertight test of Woop, Benthin and Wald: the vertices are moved into a space where the ray
+// runs along +z from the origin, and the edges are tested in 2D, so that two triangles sharing an
+// edge compute the same edge function for it and a ray cannot slip between them; returns the
+// distance along the ray and the barycentric coordinates of p1 and p2
+fn intersect(vertices: &[Point3; 3], r: &Ray, t_min: f32, t_max: f32) -> Option<(f32, f32, f32)> {
+    let d = r.direction;
+    // z is the largest axis of the direction, x and y keep the winding of the triangle
+    let kz = if d.x.abs() > d.y.abs() { if d.x.abs() > d.z.abs() { 0 } else { 2 } } else if d.y.abs() > d.z.abs() { 1 } else { 2 };
+    let (mut kx, mut ky) = ((kz + 1) % 3, (kz + 2) % 3);
+    if d[kz] < 0. {
+        std::mem::swap(&mut kx, &mut ky);
+    }
+    let (sx, sy, sz) = (d[kx] / d[kz], d[ky] / d[kz], 1. / d[kz]);
+
+    // only x and y are sheared, z is scaled by sz once the triangle is hit
+    let [a, b, c] = vertices.map(|p| p - r.origin);
+    let (ax, ay) = (a[kx] - sx * a[kz], a[ky] - sy * a[kz]);
+    let (bx, by) = (b[kx] - sx * b[kz], b[ky] - sy * b[kz]);
+    let (cx, cy) = (c[kx] - sx * c[kz], c[ky] - sy * c[kz]);
+
+    let mut e0 = cx * by - cy * bx;
+    let mut e1 = ax * cy - ay * cx;
+    let mut e2 = bx * ay - by * ax;
+    // an edge through the ray is decided in double precision, which has no ties left for floats
+    if e0 == 0. || e1 == 0. || e2 == 0. {
+        let edge = |px: f32, py: f32, qx: f32, qy: f32| (px as f64 * qy as f64 - py as f64 * qx as f64) as f32;
+        e0 = edge(cx, cy, bx, by);
+        e1 = edge(ax, ay, cx, cy);
+        e2 = edge(bx, by, ax, ay);
+    }
+    if (e0 < 0. || e1 < 0. || e2 < 0.) && (e0 > 0. || e1 > 0. || e2 > 0.) {
+        return None;
+    }
+    // the ray is parallel to the plane of the triangle
+    let det = e0 + e1 + e2;
+    if det == 0. {
+        return None;
+    }
+
+    let t = (e0 * a[kz] + e1 * b[kz] + e2 * c[kz]) * sz / det;
+    if !(t_min..=t_max).contains(&t) {
+        return None;
+    }
+
+    Some((t, e1 / det, e2 / det))
+}
+
+#[allow(clippy::too_many_arguments)]
+fn hit_triangle(vertices: &[Point3; 3], normals: Option<&[Vec3; 3]>, uvs: Option<&[[f32; 2]; 3]>, colors: Option<&[Color; 3]>,
+                material: &Arc<dyn Material>, r: &Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+    let (t, b1, b2) = intersect(vertices, r, t_min, t_max)?;
+    let b0 = 1. - b1 - b2;
+
+    // the side is decided by the geometric normal, the interpolated one only shades
+    let geometric_normal = (vertices[1] - vertices[0]).cross(vertices[2] - vertices[0]).normalize();
+    let is_front_face = r.direction.dot(geometric_normal) < 0.;
+    let outward_normal = match normals {
+        Some(n) => (b0 * n[0] + b1 * n[1] + b2 * n[2]).normalize(),
+        None => geometric_normal,
+    };
+
+    let (u, v) = match uvs {
+        Some(uv) => (b0 * uv[0][0] + b1 * uv[1][0] + b2 * uv[2][0],
+                     b0 * uv[0][1] + b1 * uv[1][1] + b2 * uv[2][1]),
+        None => (b1, b2),
+    };
+
+    let mut rec = HitRecord::new(t, r.at(t), u, v, is_front_face, outward_normal, material);
+    rec.color = colors.map(|c| Color(b0 * c[0].0 + b1 * c[1].0 + b2 * c[2].0));
+    rec.tangents = uvs.and_then(|uv| tangents(vertices, uv));
+    Some(rec)
+}
+
+// the uniform density over the triangle, over the directions seen from the origin of a ray hitting it
+fn triangle_pdf(vertices: &[Point3; 3], r: &Ray, t_min: f32, t_max: f32) -> f32 {
+    let t = match intersect(vertices, r, t_min, t_max) {
+        Some((t, _, _)) => t,
+        None => return 0.,
+    };
+
+    let cross = (vertices[1] - vertices[0]).cross(vertices[2] - vertices[0]);
+    let area = cross.length() / 2.;
+    let length = r.direction.length();
+    let cosine = r.direction.dot(cross).abs() / (length * cross.length());
+    t * t * length * length / (cosine * area)
+}
+
+fn triangle_area(vertices: &[Point3; 3]) -> f32 {
+    (vertices[1] - vertices[0]).cross(vertices[2] - vertices[0]).length() / 2.
+}
+
+// folding the unit square onto the triangle keeps the points uniform
+fn random_on_triangle(vertices: &[Point3; 3], rng: &mut Sampler) -> Point3 {
+    let (mut b1, mut b2) = (rng.gen::<f32>(), rng.gen::<f32>());
+    if b1 + b2 > 1. {
+        b1 = 1. - b1;
+        b2 = 1. - b2;
+    }
+    vertices[0] + b1 * (vertices[1] - vertices[0]) + b2 * (vertices[2] - vertices[0])
+}
+
+// dp/du and dp/dv, none when the texture coordinates are degenerate
+fn tangents(vertices: &[Point3; 3], uvs: &[[f32; 2]; 3]) -> Option<(Vec3, Vec3)> {
+    let edge1 = vertices[1] - vertices[0];
+    let edge2 = vertices[2] - vertices[0];
+    let (du1, dv1) = (uvs[1][0] - uvs[0][0], uvs[1][1] - uvs[0][1]);
+    let (du2, dv2) = (uvs[2][0] - uvs[0][0], uvs[2][1] - uvs[0][1]);
+
+    let det = du1 * dv2 - dv1 * du2;
+    if det.abs() < 1e-12 {
+        return None;
+    }
+    let dpdu = (dv2 * edge1 - dv1 * edge2) / det;
+    let dpdv = (du1 * edge2 - du2 * edge1) / det;
+    Some((dpdu.normalize(), dpdv.normalize()))
+}
+
+fn triangle_box(vertices: &[Point3; 3]) -> AABB {
+    let [p0, p1, p2] = *vertices;
+    let mut min = Point3::new(p0.x.min(p1.x).min(p2.x), p0.y.min(p1.y).min(p2.y), p0.z.min(p1.z).min(p2.z));
+    let mut max = Point3::new(p0.x.max(p1.x).max(p2.x), p0.y.max(p1.y).max(p2.y), p0.z.max(p1.z).max(p2.z));
+
+    if max.x - min.x < BOX_PADDING {
+        min.x -= BOX_PADDING;
+        max.x += BOX_PADDING;
+    }
+    if max.y - min.y < BOX_PADDING {
+        min.y -= BOX_PADDING;
+        max.y += BOX_PADDING;
+    }
+    if max.z - min.z < BOX_PADDING {
+        min.z -= BOX_PADDING;
+        max.z += BOX_PADDING;
+    }
+
+    AABB::new(min, max)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::material::Lambertian;
+    use crate::sampler::Sampler;
+    use rand::{Rng, SeedableRng};
+
+    fn material() -> Arc<dyn Material> {
+        Arc::new(Lambertian::new_from_color(Color(Vec3::new(0.5, 0.5, 0.5))))
+    }
+
+    // a unit square in the z = 0 plane split along its diagonal
+    fn square() -> Mesh {
+        Mesh {
+            positions: vec![Point3::new(0., 0., 0.), Point3::new(1., 0., 0.), Point3::new(1., 1., 0.), Point3::new(0., 1., 0.)],
+            normals: Vec::new(),
+            uvs: vec![[0., 0.], [1., 0.], [1., 1.], [0., 1.]],
+            colors: Vec::new(),
+            indices: vec![[0, 1, 2], [0, 2, 3]],
+            material: material(),
+        }
+    }
+
+    #[test]
+    fn test_barycentric_uv() {
+        let triangle = Triangle::new(Point3::new(0., 0., 0.), Point3::new(1., 0., 0.), Point3::new(0., 1., 0.), material());
+        let r = Ray::new_without_move(Point3::new(0.25, 0.5, 1.), Vec3::new(0., 0., -1.));
+
+        let rec = triangle.hit(&r, 0.001, f32::MAX).unwrap();
+        assert_eq!(rec.t, 1.);
+        assert_eq!((rec.u, rec.v), (0.25, 0.5));
+        assert_eq!(rec.normal, Vec3::new(0., 0., 1.));
+        assert!(rec.is_front_face);
+
+        let behind = Ray::new_without_move(Point3::new(0.25, 0.5, -1.), Vec3::new(0., 0., 1.));
+        let rec = triangle.hit(&behind, 0.001, f32::MAX).unwrap();
+        assert!(!rec.is_front_face);
+        assert_eq!(rec.normal, Vec3::new(0., 0., -1.));
+
+        let outside = Ray::new_without_move(Point3::new(0.75, 0.5, 1.), Vec3::new(0., 0., -1.));
+        assert!(triangle.hit(&outside, 0.001, f32::MAX).is_none());
+    }
+
+    #[test]
+    fn test_interpolated_normal_and_uv() {
+        let mut triangle = Triangle::new(Point3::new(0., 0., 0.), Point3::new(1., 0., 0.), Point3::new(0., 1., 0.), material());
+        triangle.normals = Some([Vec3::new(0., 0., 1.), Vec3::new(1., 0., 1.).normalize(), Vec3::new(0., 0., 1.)]);
+        triangle.uvs = Some([[0.5, 0.5], [1., 0.5], [0.5, 1.]]);
+
+        let r = Ray::new_without_move(Point3::new(0.5, 0., 1.), Vec3::new(0., 0., -1.));
+        let rec = triangle.hit(&r, 0.001, f32::MAX).unwrap();
+        assert_eq!((rec.u, rec.v), (0.75, 0.5));
+        assert!(rec.normal.x > 0. && rec.normal.z > 0.);
+        assert!((rec.normal.length() - 1.).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_mesh_interpolates_across_the_shared_diagonal() {
+        let mesh = TriangleMesh::new(square());
+        let mut rng = Sampler::seed_from_u64(3);
+
+        // rays through the shared diagonal must hit one of the two triangles
+        for _ in 0..1000 {
+            let x = rng.gen::<f32>();
+            let r = Ray::new_without_move(Point3::new(x, x, 1.), Vec3::new(0., 0., -1.));
+            let rec = mesh.hit(&r, 0.001, f32::MAX).expect("ray through the diagonal missed");
+            assert!((rec.u - x).abs() < 1e-6 && (rec.v - x).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn test_mesh_is_watertight_across_a_fan() {
+        // six triangles around a shared center, each spoke shared by two of them, in the z = 0 plane
+        let center = Point3::new(0.3, 0.2, 0.);
+        let ring: Vec<Point3> = (0..6)
+            .map(|k| {
+                let angle = (10. + 60. * k as f32).to_radians();
+                center + Vec3::new(angle.cos(), angle.sin(), 0.)
+            })
+            .collect();
+        let mut positions = vec![center];
+        positions.extend(&ring);
+        let indices = (0..6).map(|k| [0, 1 + k, 1 + (k + 1) % 6]).collect();
+        let mesh = TriangleMesh::new(Mesh { positions, normals: Vec::new(), uvs: Vec::new(), colors: Vec::new(), indices, material: material() });
+        let mut rng = Sampler::seed_from_u64(3);
+
+        // the center, then points along the spokes, the rays coming from all over above the fan
+        for i in 0..=6000 {
+            let target = if i == 0 { center } else { center + rng.gen::<f32>() * (ring[i % 6] - center) };
+            let origin = Point3::new(rng.gen_range(-2., 2.), rng.gen_range(-2., 2.), rng.gen_range(0.5, 2.));
+            let r = Ray::new_without_move(origin, target - origin);
+
+            let rec = mesh.hit(&r, 0.001, f32::MAX).unwrap_or_else(|| panic!("ray through {} fell between the triangles", target));
+            assert!((rec.t - 1.).abs() < 1e-4, "ray through {} hit at t = {}", target, rec.t);
+            assert!(mesh.hit(&r, rec.t + 1e-3, f32::MAX).is_none(), "ray through {} hit the fan twice", target);
+        }
+    }
+
+    #[test]
+    fn test_mesh_light_is_uniform_over_its_area() {
+        // a small and a nine times larger triangle, side by side in the z = 0 plane
+        let mesh = TriangleMesh::new(Mesh {
+            positions: vec![Point3::new(0., 0., 0.), Point3::new(1., 0., 0.), Point3::new(0., 1., 0.),
+                            Point3::new(2., 0., 0.), Point3::new(5., 0., 0.), Point3::new(2., 3., 0.)],
+            normals: Vec::new(),
+            uvs: Vec::new(),
+            colors: Vec::new(),
+            indices: vec![[0, 1, 2], [3, 4, 5]],
+            material: material(),
+        });
+        let origin = Point3::new(1., 1., 2.);
+        let mut rng = Sampler::seed_from_u64(7);
+
+        let n = 10000;
+        let mut large = 0;
+        for _ in 0..n {
+            let direction = mesh.random(&origin, &mut rng);
+            let p = origin + direction;
+            if p.x >= 2. {
+                large += 1;
+            }
+
+            // the density of a uniform point over the five units of area of the mesh
+            let r = Ray::new_without_move(origin, direction);
+            let cosine = direction.z.abs() / direction.length();
+            let expected = direction.length_squared() / (cosine * 5.);
+            let pdf = mesh.pdf_value(&r, 0.001, f32::MAX);
+            assert!((pdf - expected).abs() < 1e-3 * expected, "{} != {}", pdf, expected);
+        }
+        assert!((large as f32 / n as f32 - 0.9).abs() < 0.02, "{} of {} points on the large triangle", large, n);
+    }
+
+    #[test]
+    fn test_mesh_matches_triangles() {
+        let mut rng = Sampler::seed_from_u64(5);
+        let positions: Vec<Point3> = (0..300).map(|_| rng.gen::<Vec3>() * 10.).collect();
+        let indices: Vec<[u32; 3]> = (0..100).map(|i| [3 * i, 3 * i + 1, 3 * i + 2]).collect();
+        let triangles: Vec<Triangle> = indices.iter()
+            .map(|&[a, b, c]| Triangle::new(positions[a as usize], positions[b as usize], positions[c as usize], material()))
+            .collect();
+        let mesh = TriangleMesh::new(Mesh { positions, normals: Vec::new(), uvs: Vec::new(), colors: Vec::new(), indices, material: material() });
+
+        for _ in 0..1000 {
+            let origin = rng.gen::<Vec3>() * 10.;
+            let direction = Vec3::new(rng.gen_range(-1., 1.), rng.gen_range(-1., 1.), rng.gen_range(-1., 1.));
+            let r = Ray::new_without_move(origin, direction);
+
+            let expected = triangles.iter()
+                .filter_map(|triangle| triangle.hit(&r, 0.001, f32::MAX))
+                .min_by(|a, b| a.t.partial_cmp(&b.t).unwrap());
+            let actual = mesh.hit(&r, 0.001, f32::MAX);
+            assert_eq!(expected.map(|rec| rec.t), actual.map(|rec| rec.t));
+        }
+    }
+}
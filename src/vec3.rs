@@ -13,7 +13,7 @@ pub type Point3 = Vec3;
 
 impl Vec3 {
     pub fn new(x: f32, y: f32, z: f32) -> Self {
-        Vec3 { x: x, y: y, z: z }
+        Vec3 { x, y, z }
     }
 
     pub fn length_squared(&self) -> f32 {
@@ -39,7 +39,7 @@ impl Vec3 {
     pub fn normalize(&self) -> Self {
         assert_ne!(0., self.length());
 
-        self.clone() / self.length()
+        *self / self.length()
     }
 }
 
@@ -0,0 +1,317 @@
+use crate::aabb::AABB;
+use crate::perlin::Perlin;
+use crate::ray::Ray;
+use crate::vec3::Point3;
+
+// densities of a heterogeneous medium at any point in its space, bounded by `max_density` for tracking
+pub trait DensityField: Send + Sync {
+    fn density(&self, p: &Point3) -> f32;
+    fn max_density(&self) -> f32;
+
+    // the box outside which the density is zero, if there is one
+    fn bounding_box(&self) -> Option<AABB> {
+        None
+    }
+
+    // consecutive segments of the ray from t_min to t_max, as the t where each ends and a density
+    // bounding the ones along it; tighter bounds waste fewer tentative collisions
+    fn majorants(&self, _r: &Ray, _t_min: f32, t_max: f32) -> Vec<(f32, f32)> {
+        vec![(t_max, self.max_density())]
+    }
+}
+
+// cells of a grid divide a box, each keeping the maximum density inside it
+pub struct MajorantGrid {
+    pub bbox: AABB,
+    pub resolution: [usize; 3],
+    pub values: Vec<f32>,
+}
+
+impl MajorantGrid {
+    // the segments where the ray crosses each cell, walked through with a 3D DDA, and zero outside the box
+    pub fn majorants(&self, r: &Ray, t_min: f32, t_max: f32) -> Vec<(f32, f32)> {
+        let (min, max) = (self.bbox.min, self.bbox.max);
+        let (mut t0, mut t1) = (t_min, t_max);
+        for a in 0..3 {
+            let inv_d = 1. / r.direction[a];
+            let (mut near, mut far) = ((min[a] - r.origin[a]) * inv_d, (max[a] - r.origin[a]) * inv_d);
+            if inv_d < 0. {
+                std::mem::swap(&mut near, &mut far);
+            }
+            // NaN when the ray runs along a face, which then bounds nothing
+            t0 = if near > t0 { near } else { t0 };
+            t1 = if far < t1 { far } else { t1 };
+        }
+        if t0 >= t1 {
+            return vec![(t_max, 0.)];
+        }
+
+        let mut segments = Vec::new();
+        if t0 > t_min {
+            segments.push((t0, 0.));
+        }
+
+        // the cell holding the point where the ray enters, and when it crosses the next cell along each axis
+        let p = r.at(t0);
+        let mut cell = [0; 3];
+        let mut step = [0isize; 3];
+        let mut next = [f32::INFINITY; 3];
+        let mut delta = [f32::INFINITY; 3];
+        for a in 0..3 {
+            let size = (max[a] - min[a]) / self.resolution[a] as f32;
+            let x = ((p[a] - min[a]) / size).max(0.);
+            cell[a] = (x as usize).min(self.resolution[a] - 1);
+
+            let d = r.direction[a];
+            if d > 0. {
+                step[a] = 1;
+                next[a] = t0 + (min[a] + (cell[a] + 1) as f32 * size - p[a]) / d;
+                delta[a] = size / d;
+            } else if d < 0. {
+                step[a] = -1;
+                next[a] = t0 + (min[a] + cell[a] as f32 * size - p[a]) / d;
+                delta[a] = -size / d;
+            }
+        }
+
+        loop {
+            let axis = if next[0] < next[1] && next[0] < next[2] { 0 } else if next[1] < next[2] { 1 } else { 2 };
+            let [i, j, k] = cell;
+            let end = next[axis].min(t1);
+            segments.push((end, self.values[(k * self.resolution[1] + j) * self.resolution[0] + i]));
+
+            let c = cell[axis] as isize + step[axis];
+            if end >= t1 || c < 0 || c >= self.resolution[axis] as isize {
+                break;
+            }
+            cell[axis] = c as usize;
+            next[axis] += delta[axis];
+        }
+
+        let last = segments.last().map_or(t_min, |s| s.0);
+        if last < t1 {
+            segments.push((t1, self.values.iter().cloned().fold(0., f32::max)));
+        }
+        if t1 < t_max {
+            segments.push((t_max, 0.));
+        }
+        segments
+    }
+}
+
+// densities at the nodes of a regular grid stretched over `bbox`, trilinearly interpolated and zero outside
+pub struct GridDensity {
+    pub bbox: AABB,
+    // number of nodes along x, y and z
+    pub resolution: [usize; 3],
+    // x varies fastest, then y, then z
+    pub values: Vec<f32>,
+    majorants: MajorantGrid,
+}
+
+// density grid cells grouped in each majorant cell along an axis
+const MAJORANT_CELL: usize = 8;
+
+impl GridDensity {
+    pub fn new(bbox: AABB, resolution: [usize; 3], values: Vec<f32>) -> Result<Self, String> {
+        if resolution.iter().any(|&n| n < 2) {
+            return Err(format!("a grid needs at least 2 nodes along each axis, not {:?}", resolution));
+        }
+        // the nodes are spread over the extent of the box along each axis
+        let extent = |a: usize| bbox.max[a] - bbox.min[a];
+        if (0..3).any(|a| !(extent(a) > 0. && extent(a).is_finite())) {
+            return Err(format!("a grid needs a box of some size along each axis, not {} to {}", bbox.min, bbox.max));
+        }
+        let count = resolution[0] * resolution[1] * resolution[2];
+        if values.len() != count {
+            return Err(format!("{} densities for a grid of {} nodes", values.len(), count));
+        }
+        if let Some(value) = values.iter().find(|v| v.is_nan() || **v < 0.) {
+            return Err(format!("{} density {}", if value.is_nan() { "invalid" } else { "negative" }, value));
+        }
+
+        let majorants = Self::majorant_grid(&bbox, resolution, &values);
+        Ok(GridDensity { bbox, resolution, values, majorants })
+    }
+
+    // interpolated densities stay below the nodes around them, so a majorant cell takes the maximum
+    // over the nodes of every density cell it overlaps
+    fn majorant_grid(bbox: &AABB, resolution: [usize; 3], values: &[f32]) -> MajorantGrid {
+        let cells = [0, 1, 2].map(|a| (resolution[a] - 1).div_ceil(MAJORANT_CELL));
+        let nodes = |a: usize, c: usize| {
+            let scale = (resolution[a] - 1) as f32 / cells[a] as f32;
+            let first = (c as f32 * scale).floor() as usize;
+            let last = (((c + 1) as f32 * scale).ceil() as usize).min(resolution[a] - 1);
+            first..=last
+        };
+
+        let mut majorants = Vec::with_capacity(cells[0] * cells[1] * cells[2]);
+        for ck in 0..cells[2] {
+            for cj in 0..cells[1] {
+                for ci in 0..cells[0] {
+                    let mut max = 0f32;
+                    for k in nodes(2, ck) {
+                        for j in nodes(1, cj) {
+                            for i in nodes(0, ci) {
+                                max = max.max(values[(k * resolution[1] + j) * resolution[0] + i]);
+                            }
+                        }
+                    }
+                    majorants.push(max);
+                }
+            }
+        }
+
+        MajorantGrid { bbox: bbox.clone(), resolution: cells, values: majorants }
+    }
+
+    fn value(&self, i: usize, j: usize, k: usize) -> f32 {
+        self.values[(k * self.resolution[1] + j) * self.resolution[0] + i]
+    }
+}
+
+impl DensityField for GridDensity {
+    fn density(&self, p: &Point3) -> f32 {
+        let (min, max) = (self.bbox.min, self.bbox.max);
+        if (0..3).any(|a| p[a] < min[a] || p[a] > max[a]) {
+            return 0.;
+        }
+
+        // the cell holding p and where p lies in it
+        let mut cell = [0; 3];
+        let mut f = [0.; 3];
+        for a in 0..3 {
+            let x = (p[a] - min[a]) / (max[a] - min[a]) * (self.resolution[a] - 1) as f32;
+            cell[a] = (x as usize).min(self.resolution[a] - 2);
+            f[a] = x - cell[a] as f32;
+        }
+
+        let [i, j, k] = cell;
+        let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+        let x00 = lerp(self.value(i, j, k), self.value(i + 1, j, k), f[0]);
+        let x10 = lerp(self.value(i, j + 1, k), self.value(i + 1, j + 1, k), f[0]);
+        let x01 = lerp(self.value(i, j, k + 1), self.value(i + 1, j, k + 1), f[0]);
+        let x11 = lerp(self.value(i, j + 1, k + 1), self.value(i + 1, j + 1, k + 1), f[0]);
+        lerp(lerp(x00, x10, f[1]), lerp(x01, x11, f[1]), f[2])
+    }
+
+    fn max_density(&self) -> f32 {
+        self.majorants.values.iter().cloned().fold(0., f32::max)
+    }
+
+    fn bounding_box(&self) -> Option<AABB> {
+        Some(self.bbox.clone())
+    }
+
+    fn majorants(&self, r: &Ray, t_min: f32, t_max: f32) -> Vec<(f32, f32)> {
+        self.majorants.majorants(r, t_min, t_max)
+    }
+}
+
+// clouds of Perlin turbulence, `density` times a turbulence below 2
+pub struct NoiseDensity {
+    pub noise: Perlin,
+    pub scale: f32,
+    pub density: f32,
+}
+
+impl DensityField for NoiseDensity {
+    fn density(&self, p: &Point3) -> f32 {
+        self.density * self.noise.turb(&(self.scale * *p), 7)
+    }
+
+    // the octaves of the turbulence weigh 1 + 1/2 + 1/4 + ... and the noise stays within [-1, 1]
+    fn max_density(&self) -> f32 {
+        2. * self.density
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::sampler::Sampler;
+    use crate::vec3::Vec3;
+    use rand::{Rng, SeedableRng};
+
+    fn unit_grid(values: Vec<f32>) -> GridDensity {
+        GridDensity::new(AABB::new(Vec3::new(0., 0., 0.), Vec3::new(1., 1., 1.)), [2, 2, 2], values).unwrap()
+    }
+
+    #[test]
+    fn test_trilinear() {
+        // the density grows along x and z only
+        let grid = unit_grid(vec![0., 1., 0., 1., 2., 3., 2., 3.]);
+        assert_eq!(grid.density(&Point3::new(0.5, 0.5, 0.5)), 1.5);
+        assert_eq!(grid.density(&Point3::new(1., 0.3, 1.)), 3.);
+        assert_eq!(grid.density(&Point3::new(0.25, 0.9, 0.)), 0.25);
+        assert_eq!(grid.density(&Point3::new(1.1, 0.5, 0.5)), 0.);
+        assert_eq!(grid.max_density(), 3.);
+    }
+
+    #[test]
+    fn test_invalid_grids() {
+        let bbox = || AABB::new(Vec3::new(0., 0., 0.), Vec3::new(1., 1., 1.));
+        assert!(GridDensity::new(bbox(), [2, 2, 2], vec![0.; 7]).is_err());
+        assert!(GridDensity::new(bbox(), [1, 2, 2], vec![0.; 4]).is_err());
+        assert_eq!(GridDensity::new(bbox(), [2, 2, 2], vec![-1.; 8]).err().unwrap(), "negative density -1");
+        assert_eq!(GridDensity::new(bbox(), [2, 2, 2], vec![f32::NAN; 8]).err().unwrap(), "invalid density NaN");
+
+        let flat = AABB::new(Vec3::new(0., 0., 0.), Vec3::new(1., 0., 1.));
+        assert_eq!(GridDensity::new(flat, [2, 2, 2], vec![0.; 8]).err().unwrap(),
+                   "a grid needs a box of some size along each axis, not 0 0 0 to 1 0 1");
+    }
+
+    #[test]
+    fn test_majorants_bound_density() {
+        let mut rng = Sampler::seed_from_u64(0);
+        let resolution = [20, 12, 9];
+        let values = (0..20 * 12 * 9).map(|_| rng.gen_range(0., 3.)).collect();
+        let grid = GridDensity::new(AABB::new(Vec3::new(-1., 0., 2.), Vec3::new(3., 1., 4.)), resolution, values).unwrap();
+        assert_eq!(grid.majorants.resolution, [3, 2, 1]);
+
+        for _ in 0..200 {
+            let origin = Point3::new(rng.gen_range(-3., 5.), rng.gen_range(-2., 3.), rng.gen_range(0., 6.));
+            let direction = Vec3::new(rng.gen_range(-1., 1.), rng.gen_range(-1., 1.), rng.gen_range(-1., 1.));
+            let r = Ray::new_without_move(origin, direction);
+
+            // the segments follow each other up to t_max, and no point along them exceeds their bound
+            let segments = grid.majorants(&r, 0., 10.);
+            assert_eq!(segments.last().unwrap().0, 10.);
+            let mut start = 0.;
+            for &(end, majorant) in &segments {
+                assert!(end >= start);
+                for i in 0..10 {
+                    let t = start + (end - start) * (i as f32 + 0.5) / 10.;
+                    assert!(grid.density(&r.at(t)) <= majorant + 1e-4);
+                }
+                start = end;
+            }
+        }
+    }
+
+    #[test]
+    fn test_majorants_skip_empty_cells() {
+        // the density only rises in the last of two majorant cells along x
+        let mut values = vec![0.; 17 * 2 * 2];
+        for k in 0..2 {
+            for j in 0..2 {
+                values[(k * 2 + j) * 17 + 16] = 1.;
+            }
+        }
+        let grid = GridDensity::new(AABB::new(Vec3::new(0., 0., 0.), Vec3::new(16., 1., 1.)), [17, 2, 2], values).unwrap();
+
+        let r = Ray::new_without_move(Point3::new(-4., 0.5, 0.5), Vec3::new(1., 0., 0.));
+        assert_eq!(grid.majorants(&r, 0., 30.), vec![(4., 0.), (12., 0.), (20., 1.), (30., 0.)]);
+    }
+
+    #[test]
+    fn test_noise_below_majorant() {
+        let mut rng = Sampler::seed_from_u64(0);
+        let noise = NoiseDensity { noise: Perlin::new(&mut rng), scale: 3., density: 0.5 };
+        for _ in 0..10000 {
+            let p = Point3::new(rng.gen_range(-5., 5.), rng.gen_range(-5., 5.), rng.gen_range(-5., 5.));
+            assert!(noise.density(&p) <= noise.max_density());
+        }
+    }
+}
@@ -0,0 +1,267 @@
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::aabb::AABB;
+use crate::vec3::Point3;
+use crate::volume::GridDensity;
+
+// density grids on disk, in two formats told apart by their extension:
+//
+// `.grid` is text, `#` starting a comment, giving the number of nodes along each axis and the
+// world space box they span, then either every density with x varying fastest, then y, then z:
+//
+//     resolution 2 2 2
+//     bbox -1 -1 -1 1 1 1
+//     dense
+//     0 0 0 0 1 1 1 1
+//
+// or a background density and the nodes that differ from it, one per line:
+//
+//     resolution 64 64 64
+//     bbox 0 0 0 1 1 1
+//     sparse 0
+//     31 12 40 0.75
+//
+// `.vol` is the binary grid of Mitsuba: `VOL` and the version 3, the encoding (1 for 32 bit floats,
+// 3 for bytes read as 0 to 1), the three resolutions and the number of channels, of which only 1 is
+// supported, as little endian 32 bit integers, the box as six floats, then the densities
+#[derive(Debug)]
+pub enum VolumeError {
+    Io(PathBuf, io::Error),
+    Parse(PathBuf, String),
+}
+
+impl Display for VolumeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            VolumeError::Io(path, e) => write!(f, "{}: {}", path.display(), e),
+            VolumeError::Parse(path, message) => write!(f, "{}: {}", path.display(), message),
+        }
+    }
+}
+
+impl Error for VolumeError {}
+
+pub fn load<P: AsRef<Path>>(path: P) -> Result<GridDensity, VolumeError> {
+    let path = path.as_ref();
+    let bytes = fs::read(path).map_err(|e| VolumeError::Io(path.to_path_buf(), e))?;
+    let grid = match path.extension().and_then(|e| e.to_str()) {
+        Some("vol") => parse_vol(&bytes),
+        Some("grid") => String::from_utf8(bytes).map_err(|e| e.to_string()).and_then(|text| parse_grid(&text)),
+        _ => Err("unknown volume format, expected .grid or .vol".to_string()),
+    };
+    grid.map_err(|message| VolumeError::Parse(path.to_path_buf(), message))
+}
+
+// whitespace separated words of a `.grid` file, without its comments
+struct Tokens<'a> {
+    words: Vec<&'a str>,
+    next: usize,
+}
+
+impl<'a> Tokens<'a> {
+    fn word(&mut self) -> Option<&'a str> {
+        let word = self.words.get(self.next).copied();
+        self.next += 1;
+        word
+    }
+
+    fn number(&mut self, what: &str) -> Result<f32, String> {
+        let word = self.word().ok_or_else(|| format!("missing {}", what))?;
+        word.parse().map_err(|_| format!("invalid {} `{}`", what, word))
+    }
+
+    fn index(&mut self, what: &str, count: usize) -> Result<usize, String> {
+        let word = self.word().ok_or_else(|| format!("missing {}", what))?;
+        match word.parse::<usize>() {
+            Ok(i) if i < count => Ok(i),
+            _ => Err(format!("invalid {} `{}`", what, word)),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.next >= self.words.len()
+    }
+}
+
+// the most nodes read into a grid, 4 GiB of densities, so that a sparse grid of a mistyped resolution
+// fails to load instead of exhausting the memory
+const MAX_NODES: usize = 1 << 30;
+
+fn node_count(resolution: [usize; 3]) -> Result<usize, String> {
+    resolution.iter().try_fold(1usize, |count, &n| count.checked_mul(n))
+        .filter(|&count| count <= MAX_NODES)
+        .ok_or_else(|| format!("too many nodes in a grid of {:?}, at most {} are read", resolution, MAX_NODES))
+}
+
+fn parse_grid(text: &str) -> Result<GridDensity, String> {
+    let mut tokens = Tokens {
+        words: text.lines().flat_map(|line| line.split('#').next().unwrap_or("").split_whitespace()).collect(),
+        next: 0,
+    };
+
+    let mut resolution = None;
+    let mut bbox = None;
+    loop {
+        match tokens.word() {
+            Some("resolution") => {
+                let mut r = [0; 3];
+                for n in r.iter_mut() {
+                    *n = tokens.index("resolution", usize::MAX)?;
+                }
+                resolution = Some(r);
+            },
+            Some("bbox") => {
+                let mut b = [0.; 6];
+                for v in b.iter_mut() {
+                    *v = tokens.number("bbox")?;
+                }
+                bbox = Some(AABB::new(Point3::new(b[0], b[1], b[2]), Point3::new(b[3], b[4], b[5])));
+            },
+            Some(layout @ "dense") | Some(layout @ "sparse") => {
+                let resolution = resolution.ok_or("no resolution before the densities")?;
+                let bbox = bbox.ok_or("no bbox before the densities")?;
+                let count = node_count(resolution)?;
+
+                let values = if layout == "dense" {
+                    (0..count).map(|_| tokens.number("density")).collect::<Result<Vec<_>, _>>()?
+                } else {
+                    let mut values = vec![tokens.number("background density")?; count];
+                    while !tokens.is_empty() {
+                        let i = tokens.index("x index", resolution[0])?;
+                        let j = tokens.index("y index", resolution[1])?;
+                        let k = tokens.index("z index", resolution[2])?;
+                        values[(k * resolution[1] + j) * resolution[0] + i] = tokens.number("density")?;
+                    }
+                    values
+                };
+                if !tokens.is_empty() {
+                    return Err(format!("more densities than the {} nodes of the grid", count));
+                }
+                return GridDensity::new(bbox, resolution, values);
+            },
+            Some(other) => return Err(format!("unknown keyword `{}`", other)),
+            None => return Err("no `dense` or `sparse` densities".to_string()),
+        }
+    }
+}
+
+fn parse_vol(bytes: &[u8]) -> Result<GridDensity, String> {
+    if bytes.len() < 48 || &bytes[..3] != b"VOL" {
+        return Err("not a Mitsuba volume".to_string());
+    }
+    if bytes[3] != 3 {
+        return Err(format!("unsupported version {}", bytes[3]));
+    }
+
+    let word = |offset: usize| [bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]];
+    let int = |offset: usize| i32::from_le_bytes(word(offset));
+    let float = |offset: usize| f32::from_le_bytes(word(offset));
+
+    let encoding = int(4);
+    let mut resolution = [0; 3];
+    for (a, n) in resolution.iter_mut().enumerate() {
+        let value = int(8 + 4 * a);
+        if value < 0 {
+            return Err(format!("negative resolution {}", value));
+        }
+        *n = value as usize;
+    }
+    let channels = int(20);
+    if channels != 1 {
+        return Err(format!("{} channels, only densities with 1 are supported", channels));
+    }
+    let bbox = AABB::new(Point3::new(float(24), float(28), float(32)), Point3::new(float(36), float(40), float(44)));
+
+    let count = node_count(resolution)?;
+    let data = &bytes[48..];
+    let values = match encoding {
+        1 if data.len() == 4 * count => data.chunks_exact(4).map(|c| f32::from_le_bytes([c[0], c[1], c[2], c[3]])).collect(),
+        3 if data.len() == count => data.iter().map(|&b| b as f32 / 255.).collect(),
+        1 | 3 => return Err(format!("{} bytes of densities for a grid of {} nodes", data.len(), count)),
+        _ => return Err(format!("unsupported encoding {}", encoding)),
+    };
+    GridDensity::new(bbox, resolution, values)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::volume::DensityField;
+    use std::path::Path;
+
+    // a 2 x 2 x 3 grid over the box from the origin to (1, 1, 2), dense only along its top
+    const DENSE: &str = "resolution 2 2 3  # nodes
+bbox 0 0 0 1 1 2
+dense
+0 0 0 0
+0 0 0 0
+0 0 1 0.5
+";
+    const SPARSE: &str = "# the same grid
+resolution 2 2 3
+bbox 0 0 0 1 1 2
+sparse 0
+0 1 2 1
+1 1 2 0.5
+";
+
+    fn vol(encoding: i32, data: &[u8]) -> Vec<u8> {
+        let mut bytes = b"VOL\x03".to_vec();
+        for value in &[encoding, 2, 2, 3, 1] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        for value in &[0f32, 0., 0., 1., 1., 2.] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes.extend_from_slice(data);
+        bytes
+    }
+
+    fn assert_top(grid: &GridDensity) {
+        assert_eq!(grid.resolution, [2, 2, 3]);
+        assert_eq!(grid.bounding_box().unwrap().max, Point3::new(1., 1., 2.));
+        assert_eq!(grid.density(&Point3::new(0., 1., 2.)), 1.);
+        assert_eq!(grid.density(&Point3::new(0.5, 1., 2.)), 0.75);
+        assert_eq!(grid.density(&Point3::new(0., 1., 1.5)), 0.5);
+        assert_eq!(grid.density(&Point3::new(0.5, 0.5, 0.5)), 0.);
+    }
+
+    #[test]
+    fn test_formats_read_the_same_grid() {
+        assert_top(&parse_grid(DENSE).unwrap());
+        assert_top(&parse_grid(SPARSE).unwrap());
+
+        let floats: Vec<u8> = [0f32, 0., 0., 0., 0., 0., 0., 0., 0., 0., 1., 0.5].iter().flat_map(|v| v.to_le_bytes().to_vec()).collect();
+        assert_top(&parse_vol(&vol(1, &floats)).unwrap());
+
+        let grid = parse_vol(&vol(3, &[0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 255, 51])).unwrap();
+        assert!((grid.density(&Point3::new(1., 1., 2.)) - 0.2).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_load_example() {
+        let grid = load(Path::new(env!("CARGO_MANIFEST_DIR")).join("scenes/volumes/plume.grid")).unwrap();
+        assert_eq!(grid.resolution, [16, 24, 16]);
+        assert!(grid.max_density() > 0.);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(parse_grid("bbox 0 0 0 1 1 1\ndense 0 0 0 0 0 0 0 0").err().unwrap().contains("no resolution"));
+        assert!(parse_grid("resolution 2 2 2\nbbox 0 0 0 1 1 1\ndense 0 0 0").err().unwrap().contains("missing density"));
+        assert!(parse_grid("resolution 2 2 2\nbbox 0 0 0 1 1 1\ndense 0 0 0 0 0 0 0 0 0").err().unwrap().contains("more densities"));
+        assert!(parse_grid("resolution 2 2 2\nbbox 0 0 0 1 1 1\nsparse 0\n2 0 0 1").err().unwrap().contains("invalid x index"));
+        assert!(parse_grid("resolution 2 2 2\nvoxels").err().unwrap().contains("unknown keyword"));
+        assert!(parse_grid("resolution 100000 100000 100000\nbbox 0 0 0 1 1 1\nsparse 0\n").err().unwrap().contains("too many nodes"));
+        assert!(parse_grid("resolution 2 2 2\nbbox 0 0 0 1 1 0\nsparse 0\n").err().unwrap().contains("a box of some size"));
+
+        assert!(parse_vol(b"PLY").err().unwrap().contains("not a Mitsuba volume"));
+        assert!(parse_vol(&vol(1, &[0; 12])).err().unwrap().contains("12 bytes"));
+        assert!(parse_vol(&vol(2, &[0; 24])).err().unwrap().contains("unsupported encoding"));
+    }
+}